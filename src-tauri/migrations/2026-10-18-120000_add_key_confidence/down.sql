@@ -0,0 +1,2 @@
+-- Undo up
+ALTER TABLE beats DROP COLUMN key_confidence;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Correlation of the detected key with its Krumhansl profile, 0.0 to 1.0
+ALTER TABLE beats ADD COLUMN key_confidence DOUBLE;
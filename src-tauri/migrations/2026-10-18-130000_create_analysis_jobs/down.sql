@@ -0,0 +1,4 @@
+-- Undo up
+ALTER TABLE beats DROP COLUMN analysis_status;
+DROP INDEX IF EXISTS analysis_jobs_status;
+DROP TABLE IF EXISTS analysis_jobs;
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+
+-- Persistent queue for background analysis, survives app restarts
+CREATE TABLE analysis_jobs (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    beat_id INTEGER NOT NULL,
+    status VARCHAR NOT NULL DEFAULT 'pending',
+    error TEXT,
+    date_created DATETIME NOT NULL DEFAULT current_timestamp,
+    date_updated DATETIME NOT NULL DEFAULT current_timestamp,
+    FOREIGN KEY (beat_id)
+        REFERENCES beats(id)
+        ON DELETE CASCADE
+);
+
+CREATE INDEX analysis_jobs_status ON analysis_jobs (status);
+
+-- Latest analysis state of each beat, mirrored from its newest job
+ALTER TABLE beats ADD COLUMN analysis_status VARCHAR NOT NULL DEFAULT 'pending';
+
+-- Existing beats were analyzed synchronously on import
+UPDATE beats SET analysis_status = 'complete' WHERE bpm IS NOT NULL OR musical_key IS NOT NULL;
+UPDATE beats SET analysis_status = 'failed' WHERE bpm IS NULL AND musical_key IS NULL;
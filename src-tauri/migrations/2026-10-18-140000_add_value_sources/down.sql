@@ -0,0 +1,4 @@
+-- Undo up
+ALTER TABLE analysis_jobs DROP COLUMN force;
+ALTER TABLE beats DROP COLUMN key_source;
+ALTER TABLE beats DROP COLUMN bpm_source;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+
+-- Where the stored bpm / musical_key came from: 'analysis' or 'manual'.
+-- Manually edited values are not overwritten by re-analysis unless forced.
+ALTER TABLE beats ADD COLUMN bpm_source VARCHAR;
+ALTER TABLE beats ADD COLUMN key_source VARCHAR;
+
+UPDATE beats SET bpm_source = 'analysis' WHERE bpm IS NOT NULL;
+UPDATE beats SET key_source = 'analysis' WHERE musical_key IS NOT NULL;
+
+-- Forced jobs overwrite manually edited values
+ALTER TABLE analysis_jobs ADD COLUMN force BOOLEAN NOT NULL DEFAULT 0;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- Tempo as the analyzer reported it, before folding into the preferred range.
+-- beats.bpm holds the corrected value.
+ALTER TABLE beats ADD COLUMN bpm_raw DOUBLE;
+
+UPDATE beats SET bpm_raw = bpm WHERE bpm_source = 'analysis';
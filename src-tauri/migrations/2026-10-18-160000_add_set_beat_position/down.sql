@@ -0,0 +1,2 @@
+-- Undo up
+ALTER TABLE set_beat DROP COLUMN position;
@@ -0,0 +1,5 @@
+-- Your SQL goes here
+
+-- Running order of a beat within its collection, 0 is the opener.
+-- Sets created before this have no order yet and keep NULL until one is saved.
+ALTER TABLE set_beat ADD COLUMN position INTEGER;
@@ -0,0 +1,23 @@
+-- Undo up
+CREATE TABLE set_beat_old (
+    beat_collection_id INTEGER NOT NULL,
+    beat_id INTEGER NOT NULL,
+    position INTEGER,
+    PRIMARY KEY (beat_collection_id, beat_id),
+    FOREIGN KEY (beat_collection_id)
+        REFERENCES beat_collection(id)
+        ON DELETE CASCADE,
+    FOREIGN KEY (beat_id)
+        REFERENCES beats(id)
+        ON DELETE CASCADE
+);
+
+-- Repeated beats can't be represented anymore, keep their first occurrence
+INSERT INTO set_beat_old (beat_collection_id, beat_id, position)
+SELECT beat_collection_id, beat_id, MIN(position)
+FROM set_beat
+GROUP BY beat_collection_id, beat_id;
+
+DROP TABLE set_beat;
+
+ALTER TABLE set_beat_old RENAME TO set_beat;
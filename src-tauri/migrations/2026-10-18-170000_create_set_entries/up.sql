@@ -0,0 +1,33 @@
+-- Your SQL goes here
+
+-- Give every set entry its own id and a required position, so a set has a
+-- running order and the same beat can be played more than once.
+CREATE TABLE set_beat_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    beat_collection_id INTEGER NOT NULL,
+    beat_id INTEGER NOT NULL,
+    position INTEGER NOT NULL,
+    FOREIGN KEY (beat_collection_id)
+        REFERENCES beat_collection(id)
+        ON DELETE CASCADE,
+    FOREIGN KEY (beat_id)
+        REFERENCES beats(id)
+        ON DELETE CASCADE
+);
+
+-- Entries without a saved order go after the ordered ones, in insertion order
+INSERT INTO set_beat_new (beat_collection_id, beat_id, position)
+SELECT
+    beat_collection_id,
+    beat_id,
+    ROW_NUMBER() OVER (
+        PARTITION BY beat_collection_id
+        ORDER BY position IS NULL, position, rowid
+    ) - 1
+FROM set_beat;
+
+DROP TABLE set_beat;
+
+ALTER TABLE set_beat_new RENAME TO set_beat;
+
+CREATE INDEX set_beat_collection_position ON set_beat (beat_collection_id, position);
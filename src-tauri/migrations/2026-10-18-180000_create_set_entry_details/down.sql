@@ -0,0 +1,2 @@
+-- Undo up
+DROP TABLE set_entry_details;
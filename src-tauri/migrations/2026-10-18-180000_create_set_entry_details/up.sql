@@ -0,0 +1,19 @@
+-- Your SQL goes here
+
+-- Performance plan for one entry of a set, at most one row per set_beat
+CREATE TABLE set_entry_details (
+    set_beat_id INTEGER PRIMARY KEY NOT NULL,
+    -- How this track goes into the next one, e.g. 'blend', 'cut', 'echo_out'
+    transition_style VARCHAR,
+    -- Seconds into the track where it starts being mixed in and where the mix out starts
+    mix_in_time DOUBLE,
+    mix_out_time DOUBLE,
+    -- Planned key change in semitones and tempo change in percent
+    key_shift INTEGER,
+    pitch_adjust DOUBLE,
+    note TEXT,
+    date_updated DATETIME NOT NULL DEFAULT current_timestamp,
+    FOREIGN KEY (set_beat_id)
+        REFERENCES set_beat(id)
+        ON DELETE CASCADE
+);
@@ -0,0 +1,5 @@
+-- Undo up
+ALTER TABLE beats DROP COLUMN true_peak;
+ALTER TABLE beats DROP COLUMN loudness_range;
+ALTER TABLE beats DROP COLUMN short_term_loudness_max;
+ALTER TABLE beats DROP COLUMN integrated_loudness;
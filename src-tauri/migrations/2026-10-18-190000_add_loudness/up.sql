@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- EBU R128 measurements from the analyzer
+ALTER TABLE beats ADD COLUMN integrated_loudness DOUBLE; -- LUFS
+ALTER TABLE beats ADD COLUMN short_term_loudness_max DOUBLE; -- LUFS
+ALTER TABLE beats ADD COLUMN loudness_range DOUBLE; -- LU
+ALTER TABLE beats ADD COLUMN true_peak DOUBLE; -- dBTP
@@ -0,0 +1,2 @@
+-- Undo up
+DROP TABLE beat_grids;
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+
+-- Beat grid of a beat, at most one per beat
+CREATE TABLE beat_grids (
+    beat_id INTEGER PRIMARY KEY NOT NULL,
+    -- Seconds into the track of the first beat of the first bar
+    first_downbeat DOUBLE NOT NULL,
+    -- Average tempo of the grid
+    bpm DOUBLE NOT NULL,
+    -- Time signature numerator, 4 for 4/4
+    beats_per_bar INTEGER NOT NULL DEFAULT 4,
+    -- JSON array of {position, bpm} markers for tracks with tempo changes,
+    -- NULL when the whole track has one tempo
+    tempo_map TEXT,
+    -- Seconds of audio the grid covers
+    duration DOUBLE NOT NULL,
+    -- 'analysis' or 'manual', manual grids survive reanalysis unless forced
+    source VARCHAR NOT NULL DEFAULT 'analysis',
+    date_updated DATETIME NOT NULL DEFAULT current_timestamp,
+    FOREIGN KEY (beat_id)
+        REFERENCES beats(id)
+        ON DELETE CASCADE
+);
@@ -0,0 +1,2 @@
+-- Undo up
+DROP TABLE cue_points;
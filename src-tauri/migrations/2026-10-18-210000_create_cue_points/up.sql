@@ -0,0 +1,24 @@
+-- Your SQL goes here
+
+-- Cue markers and saved loops of a beat
+CREATE TABLE cue_points (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    beat_id INTEGER NOT NULL,
+    -- 'memory', 'hot' or 'loop'
+    kind VARCHAR NOT NULL,
+    position_ms INTEGER NOT NULL,
+    -- Required for loops, a hot cue with a length is a hot loop
+    loop_length_ms INTEGER,
+    -- Pad 0 to 7, hot cues only
+    hot_cue_index INTEGER,
+    label VARCHAR,
+    -- '#RRGGBB'
+    color VARCHAR,
+    date_created DATETIME NOT NULL DEFAULT current_timestamp,
+    FOREIGN KEY (beat_id)
+        REFERENCES beats(id)
+        ON DELETE CASCADE
+);
+CREATE INDEX cue_points_beat ON cue_points (beat_id, position_ms);
+CREATE UNIQUE INDEX cue_points_hot_cue ON cue_points (beat_id, hot_cue_index)
+    WHERE hot_cue_index IS NOT NULL;
@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+-- Review comments pinned to a moment in a beat
+CREATE TABLE beat_comments (
+    id INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+    beat_id INTEGER NOT NULL,
+    -- Where in the track the comment is about, in milliseconds
+    timestamp_ms INTEGER NOT NULL,
+    author VARCHAR NOT NULL,
+    body TEXT NOT NULL,
+    resolved BOOLEAN NOT NULL DEFAULT 0,
+    date_created DATETIME NOT NULL DEFAULT current_timestamp,
+    FOREIGN KEY (beat_id)
+        REFERENCES beats(id)
+        ON DELETE CASCADE
+);
+CREATE INDEX beat_comments_beat ON beat_comments (beat_id, timestamp_ms);
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Version number from the file name, e.g. "3" for "Dark Trap v3.wav"
+ALTER TABLE beats ADD COLUMN version TEXT;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Whether file_path still points to a file: 'ok' or 'missing'
+ALTER TABLE beats ADD COLUMN file_status TEXT NOT NULL DEFAULT 'ok';
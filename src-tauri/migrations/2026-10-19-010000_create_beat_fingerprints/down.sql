@@ -0,0 +1,4 @@
+-- Undo up
+DROP TABLE beat_fingerprints;
+DROP INDEX beats_audio_hash;
+ALTER TABLE beats DROP COLUMN audio_hash;
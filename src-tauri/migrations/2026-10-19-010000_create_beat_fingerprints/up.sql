@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+-- blake3 of the decoded audio, equal for copies that only differ in tags
+ALTER TABLE beats ADD COLUMN audio_hash TEXT;
+CREATE INDEX beats_audio_hash ON beats (audio_hash);
+
+-- Acoustic fingerprint of a beat for finding near-duplicates, kept out of
+-- beats so loading the library doesn't load them
+CREATE TABLE beat_fingerprints (
+    beat_id INTEGER PRIMARY KEY NOT NULL,
+    -- Little endian u32 sub-fingerprints, one per 100 ms
+    fingerprint BLOB NOT NULL,
+    date_updated DATETIME NOT NULL DEFAULT current_timestamp,
+    FOREIGN KEY (beat_id)
+        REFERENCES beats(id)
+        ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Bytes, for recognizing a missing file when looking for where it moved
+ALTER TABLE beats ADD COLUMN file_size BIGINT;
@@ -0,0 +1,224 @@
+/*
+ * analysis_queue.rs
+ *
+ * Background analysis for beats. Jobs live in the analysis_jobs table so the
+ * queue survives restarts, and a small pool of worker threads drains it. The
+ * database lock is only held while claiming a job and saving its result, never
+ * while audio is being analyzed, so the rest of the app stays responsive.
+ *
+ * Events emitted to the frontend:
+ * - analysis-progress: A worker started analyzing a beat.
+ * - analysis-complete: Analysis results were saved to the beat.
+ * - analysis-failed: The analyzer returned an error for the beat.
+ */
+
+use serde::Serialize;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+use crate::analyzer::{self, AnalysisResult};
+use crate::models::{AnalysisJob, Beat, BeatAnalysisChangeset};
+use crate::musical_key::MusicalKey;
+use crate::{db, store, DatabaseConnection};
+
+// Workers also wake up on this interval in case a notification was missed
+const IDLE_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// Decoding a whole track takes a fair amount of memory, so cap the pool size
+const MAX_WORKERS: usize = 4;
+
+#[derive(Serialize, Clone)]
+struct AnalysisProgress {
+    job_id: i32,
+    beat_id: i32,
+    // Jobs still waiting or running, including this one
+    remaining: i64,
+}
+
+#[derive(Serialize, Clone)]
+struct AnalysisComplete {
+    job_id: i32,
+    beat_id: i32,
+    result: AnalysisResult,
+}
+
+#[derive(Serialize, Clone)]
+struct AnalysisFailed {
+    job_id: i32,
+    beat_id: i32,
+    error: String,
+}
+
+pub struct AnalysisQueue {
+    conn: Arc<Mutex<DatabaseConnection>>,
+    wake: (Mutex<()>, Condvar),
+}
+
+impl AnalysisQueue {
+    pub fn new(conn: Arc<Mutex<DatabaseConnection>>) -> Arc<Self> {
+        Arc::new(AnalysisQueue {
+            conn,
+            wake: (Mutex::new(()), Condvar::new()),
+        })
+    }
+
+    // Re-queues jobs interrupted by the last shutdown and starts the worker threads.
+    pub fn start(self: &Arc<Self>, app: AppHandle) -> Result<(), String> {
+        {
+            let mut conn_guard = self.conn.lock().map_err(|e| e.to_string())?;
+            let requeued = db::requeue_interrupted_analysis(&mut conn_guard.conn)
+                .map_err(|e| e.to_string())?;
+            if requeued > 0 {
+                println!("Re-queued {} interrupted analysis jobs", requeued);
+            }
+        }
+
+        let workers = thread::available_parallelism()
+            .map(|n| n.get() / 2)
+            .unwrap_or(1)
+            .clamp(1, MAX_WORKERS);
+        println!("Starting {} analysis workers", workers);
+
+        for index in 0..workers {
+            let queue = Arc::clone(self);
+            let app = app.clone();
+            thread::Builder::new()
+                .name(format!("analysis-worker-{}", index))
+                .spawn(move || queue.run_worker(app))
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    }
+
+    // Wakes idle workers after new jobs were queued.
+    pub fn notify(&self) {
+        self.wake.1.notify_all();
+    }
+
+    fn wait_for_work(&self) {
+        if let Ok(guard) = self.wake.0.lock() {
+            let _ = self.wake.1.wait_timeout(guard, IDLE_POLL_INTERVAL);
+        }
+    }
+
+    fn run_worker(&self, app: AppHandle) {
+        loop {
+            match self.claim_next() {
+                Ok(Some((job, beat, remaining))) => self.run_job(&app, job, beat, remaining),
+                Ok(None) => self.wait_for_work(),
+                Err(e) => {
+                    println!("Failed to claim analysis job: {}", e);
+                    self.wait_for_work();
+                }
+            }
+        }
+    }
+
+    fn claim_next(&self) -> Result<Option<(AnalysisJob, Beat, i64)>, String> {
+        let mut conn_guard = self.conn.lock().map_err(|e| e.to_string())?;
+        let conn = &mut conn_guard.conn;
+
+        let Some((job, beat)) = db::claim_next_analysis_job(conn).map_err(|e| e.to_string())?
+        else {
+            return Ok(None);
+        };
+        let remaining = db::count_pending_analysis(conn).map_err(|e| e.to_string())?;
+        Ok(Some((job, beat, remaining)))
+    }
+
+    fn run_job(&self, app: &AppHandle, job: AnalysisJob, beat: Beat, remaining: i64) {
+        println!("Starting analysis for file: {}", beat.file_path);
+        let _ = app.emit_all(
+            "analysis-progress",
+            AnalysisProgress {
+                job_id: job.id,
+                beat_id: job.beat_id,
+                remaining,
+            },
+        );
+
+        let settings = store::read_settings();
+        let bundled_script = app.path_resolver().resolve_resource(analyzer::PYTHON_SCRIPT_RESOURCE);
+        let analyzer = analyzer::from_settings(&settings, bundled_script);
+        println!("Using {} analyzer", analyzer.name());
+
+        // A panic in an analyzer should fail the job, not kill the worker
+        let outcome = panic::catch_unwind(AssertUnwindSafe(|| analyzer.analyze(Path::new(&beat.file_path))))
+            .unwrap_or_else(|_| Err("Analyzer panicked".into()))
+            .map_err(|e| e.to_string());
+
+        let mut conn_guard = match self.conn.lock() {
+            Ok(guard) => guard,
+            Err(e) => {
+                println!("Failed to save analysis for beat {}: {}", job.beat_id, e);
+                return;
+            }
+        };
+        let conn = &mut conn_guard.conn;
+
+        match outcome {
+            Ok(result) => {
+                println!("Analysis Result: {:?}", result); // Debug output
+                // Fix half/double tempo detections using the preferred range for the genre
+                let bpm_range = settings.bpm_range_for(beat.genre.as_deref());
+                let folded_bpm = result.bpm.map(|bpm| bpm_range.fold(bpm));
+                // The grid follows the folded tempo, so a half time detection gets every beat
+                let mut beat_grid = result.beat_grid.clone();
+                if let (Some(grid), Some(raw), Some(folded)) = (beat_grid.as_mut(), result.bpm, folded_bpm) {
+                    grid.scale_tempo(folded / raw);
+                }
+                let changes = BeatAnalysisChangeset {
+                    bpm: folded_bpm.map(|bpm| (bpm * 100.0).round() / 100.0),
+                    bpm_raw: result.bpm,
+                    // External analyzers may report keys in any notation
+                    musical_key: result
+                        .musical_key
+                        .as_deref()
+                        .and_then(MusicalKey::parse)
+                        .map(|key| key.to_string()),
+                    key_confidence: result.key_confidence,
+                    integrated_loudness: result.integrated_loudness,
+                    short_term_loudness_max: result.short_term_loudness_max,
+                    loudness_range: result.loudness_range,
+                    true_peak: result.true_peak,
+                    ..Default::default()
+                };
+                match db::complete_analysis_job(conn, job.id, changes, beat_grid, result.identity.as_ref()) {
+                    Ok(true) => {
+                        let _ = app.emit_all(
+                            "analysis-complete",
+                            AnalysisComplete {
+                                job_id: job.id,
+                                beat_id: job.beat_id,
+                                result,
+                            },
+                        );
+                    }
+                    Ok(false) => println!("Analysis for beat {} was canceled", job.beat_id),
+                    Err(e) => println!("Error updating beat: {:?}", e),
+                }
+            }
+            Err(error) => {
+                println!("Failed to analyze audio. Error: {}", error); // Log the error
+                match db::fail_analysis_job(conn, job.id, &error) {
+                    Ok(true) => {
+                        let _ = app.emit_all(
+                            "analysis-failed",
+                            AnalysisFailed {
+                                job_id: job.id,
+                                beat_id: job.beat_id,
+                                error,
+                            },
+                        );
+                    }
+                    Ok(false) => println!("Analysis for beat {} was canceled", job.beat_id),
+                    Err(e) => println!("Error updating analysis job: {:?}", e),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,181 @@
+/*
+ * analyzer.rs
+ *
+ * Pluggable analysis backends. Every backend implements the Analyzer trait and
+ * returns an AnalysisResult, so the analysis queue workers and
+ * complete_analysis_job don't need to know which one the user picked in settings.
+ *
+ * Backends:
+ * - NativeAnalyzer: The Rust implementation in audio_analysis.
+ * - PythonAnalyzer: Runs audio_analyzer.py (librosa) as an external process.
+ * - NullAnalyzer: Returns an empty result, useful for tests and slow machines.
+ */
+
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::audio_analysis;
+use crate::beat_grid::BeatGrid;
+use crate::store::{AnalyzerBackend, Settings};
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone)]
+#[serde(default)]
+pub struct AnalysisResult {
+    pub bpm: Option<f64>,
+    pub musical_key: Option<String>,
+    pub key_confidence: Option<f64>,
+    // EBU R128 measurements, LUFS / LU / dBTP
+    pub integrated_loudness: Option<f64>,
+    pub short_term_loudness_max: Option<f64>,
+    pub loudness_range: Option<f64>,
+    pub true_peak: Option<f64>,
+    // Grid at the detected tempo, before any half/double time folding
+    pub beat_grid: Option<BeatGrid>,
+    // Measured natively for every backend that decodes the file, never sent
+    // to or read from the frontend and scripts
+    #[serde(skip)]
+    pub identity: Option<audio_analysis::AudioIdentity>,
+}
+
+impl AnalysisResult {
+    fn set_loudness(&mut self, loudness: Option<audio_analysis::Loudness>) {
+        let round = |value: f64| (value * 100.0).round() / 100.0;
+        self.integrated_loudness = loudness.map(|l| round(l.integrated));
+        self.short_term_loudness_max = loudness.and_then(|l| l.short_term_max).map(round);
+        self.loudness_range = loudness.and_then(|l| l.range).map(round);
+        self.true_peak = loudness.map(|l| round(l.true_peak));
+    }
+
+    fn set_beats(&mut self, beats: Option<audio_analysis::BeatTracking>) {
+        self.beat_grid = beats.and_then(|tracking| {
+            BeatGrid::from_beats(
+                &tracking.beats,
+                tracking.first_downbeat,
+                tracking.beats_per_bar as u32,
+                tracking.duration,
+            )
+        });
+    }
+}
+
+pub trait Analyzer: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn analyze(&self, file_path: &Path) -> Result<AnalysisResult, Box<dyn Error>>;
+}
+
+// Builds the analyzer the user selected in settings. bundled_script is the
+// audio_analyzer.py shipped as an app resource, used unless settings point
+// at another script.
+pub fn from_settings(settings: &Settings, bundled_script: Option<PathBuf>) -> Box<dyn Analyzer> {
+    match settings.analyzer {
+        AnalyzerBackend::Native => Box::new(NativeAnalyzer),
+        AnalyzerBackend::Python => Box::new(PythonAnalyzer {
+            python_executable: settings.python_executable.clone(),
+            script_path: settings
+                .python_script_path
+                .as_ref()
+                .map(PathBuf::from)
+                .or(bundled_script),
+        }),
+        AnalyzerBackend::Null => Box::new(NullAnalyzer),
+    }
+}
+
+pub struct NativeAnalyzer;
+
+impl Analyzer for NativeAnalyzer {
+    fn name(&self) -> &'static str {
+        "native"
+    }
+
+    fn analyze(&self, file_path: &Path) -> Result<AnalysisResult, Box<dyn Error>> {
+        let analysis = audio_analysis::analyze_audio(&file_path.to_string_lossy())?;
+        let mut result = AnalysisResult {
+            bpm: analysis.bpm,
+            musical_key: analysis.key.map(|k| k.name()),
+            key_confidence: analysis.key.map(|k| k.confidence),
+            ..Default::default()
+        };
+        result.set_loudness(analysis.loudness);
+        result.set_beats(analysis.beats);
+        result.identity = Some(analysis.identity);
+        Ok(result)
+    }
+}
+
+// Where tauri.conf.json bundles the script, relative to the resource directory
+pub const PYTHON_SCRIPT_RESOURCE: &str = "src/audio_analyzer.py";
+
+pub struct PythonAnalyzer {
+    pub python_executable: String,
+    pub script_path: Option<PathBuf>,
+}
+
+impl Analyzer for PythonAnalyzer {
+    fn name(&self) -> &'static str {
+        "python"
+    }
+
+    fn analyze(&self, file_path: &Path) -> Result<AnalysisResult, Box<dyn Error>> {
+        let script_path = self
+            .script_path
+            .as_ref()
+            .ok_or("audio_analyzer.py wasn't found, set python_script_path in settings")?;
+        let output = Command::new(&self.python_executable)
+            .arg(script_path)
+            .arg(file_path)
+            .output()
+            .map_err(|e| format!("Failed to run {}: {}", self.python_executable, e))?;
+
+        if !output.status.success() {
+            return Err(format!(
+                "audio_analyzer.py exited with {}: {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+
+        // The script prints debug output first and the JSON result last
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let result_line = stdout
+            .lines()
+            .rev()
+            .find(|line| !line.trim().is_empty())
+            .ok_or("audio_analyzer.py produced no output")?;
+
+        let mut result: AnalysisResult = serde_json::from_str(result_line)?;
+
+        // The script doesn't measure loudness, track beats or fingerprint, so
+        // those come from the native analysis, following the tempo the script
+        // detected. Librosa decodes formats symphonia can't, so a file the native
+        // side can't read still keeps the script's bpm and key.
+        match audio_analysis::analyze_loudness_and_beats(&file_path.to_string_lossy(), result.bpm) {
+            Ok(native) => {
+                if result.integrated_loudness.is_none() {
+                    result.set_loudness(native.loudness);
+                }
+                if result.beat_grid.is_none() {
+                    result.set_beats(native.beats);
+                }
+                result.identity = Some(native.identity);
+            }
+            Err(e) => println!("Native analysis of {} failed: {}", file_path.display(), e),
+        }
+        Ok(result)
+    }
+}
+
+pub struct NullAnalyzer;
+
+impl Analyzer for NullAnalyzer {
+    fn name(&self) -> &'static str {
+        "null"
+    }
+
+    fn analyze(&self, _file_path: &Path) -> Result<AnalysisResult, Box<dyn Error>> {
+        Ok(AnalysisResult::default())
+    }
+}
@@ -0,0 +1,146 @@
+/*
+ * artwork.rs
+ *
+ * Cover art store. Artwork found when a beat is added, embedded in the file or
+ * as a cover.jpg or folder.jpg next to it, and artwork set by hand are copied
+ * into the app data directory under the blake3 hash of the image, so an album
+ * of tracks sharing one cover stores it once.
+ *
+ * beats.cover_art holds the file name of the stored image, "<hash>.<ext>". A
+ * JPEG thumbnail for lists sits next to it as "<hash>-thumb.jpg".
+ */
+
+use std::error::Error;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+use image::ImageFormat;
+
+use crate::atomic_file;
+use crate::tags;
+
+// Longest side of a thumbnail in pixels
+const THUMBNAIL_SIZE: u32 = 256;
+
+// Image files next to a track that count as its cover, best first. Matched
+// without regard to case.
+const SIBLING_COVERS: [&str; 6] = [
+    "cover.jpg",
+    "cover.jpeg",
+    "cover.png",
+    "folder.jpg",
+    "folder.jpeg",
+    "folder.png",
+];
+
+pub struct ArtworkStore {
+    // None when the platform has no app data directory, no artwork is stored then
+    dir: Option<PathBuf>,
+}
+
+impl ArtworkStore {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        ArtworkStore { dir }
+    }
+
+    // Stores an image and returns its reference for beats.cover_art. Storing
+    // an image that is already there only returns the reference.
+    pub fn store(&self, data: &[u8]) -> Result<String, Box<dyn Error>> {
+        let dir = self.dir.as_ref().ok_or("No directory to store artwork in")?;
+        let format = image::guess_format(data)?;
+        let extension = format.extensions_str().first().copied().unwrap_or("img");
+        let hash = blake3::hash(data).to_hex().to_string();
+        let reference = format!("{}.{}", hash, extension);
+
+        let image_path = dir.join(&reference);
+        let thumbnail_path = dir.join(thumbnail_name(&hash));
+        if image_path.exists() && thumbnail_path.exists() {
+            return Ok(reference);
+        }
+
+        // Decode before writing anything, so broken images are never stored
+        let thumbnail = image::load_from_memory(data)?
+            .thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE)
+            .to_rgb8();
+        let mut encoded = Cursor::new(Vec::new());
+        thumbnail.write_to(&mut encoded, ImageFormat::Jpeg)?;
+
+        fs::create_dir_all(dir)?;
+        atomic_file::write_file(&image_path, data)?;
+        atomic_file::write_file(&thumbnail_path, encoded.get_ref())?;
+        Ok(reference)
+    }
+
+    pub fn store_file(&self, image_path: &Path) -> Result<String, Box<dyn Error>> {
+        self.store(&fs::read(image_path)?)
+    }
+
+    // Finds and stores the artwork of an audio file: the embedded cover
+    // first, then a cover image in the same folder. None when there is none
+    // or it can't be stored.
+    pub fn import_for_file(&self, file_path: &str) -> Option<String> {
+        let stored = match tags::read_artwork(file_path) {
+            Ok(Some(data)) => self.store(&data),
+            Ok(None) => match sibling_cover(Path::new(file_path)) {
+                Some(cover) => self.store_file(&cover),
+                None => return None,
+            },
+            Err(e) => Err(e),
+        };
+        stored
+            .map_err(|e| println!("Could not store artwork for {}: {}", file_path, e))
+            .ok()
+    }
+
+    // The stored image for a cover_art reference, or its thumbnail.
+    pub fn read(&self, reference: &str, thumbnail: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+        let dir = self.dir.as_ref().ok_or("No artwork directory")?;
+        // References are plain file names, never paths out of the directory
+        let (hash, _) = reference
+            .split_once('.')
+            .filter(|(hash, extension)| {
+                !hash.is_empty()
+                    && hash.chars().all(|c| c.is_ascii_hexdigit())
+                    && extension.chars().all(|c| c.is_ascii_alphanumeric())
+            })
+            .ok_or_else(|| format!("Invalid artwork reference: {}", reference))?;
+
+        let path = if thumbnail {
+            dir.join(thumbnail_name(hash))
+        } else {
+            dir.join(reference)
+        };
+        Ok(fs::read(path)?)
+    }
+}
+
+// The first of SIBLING_COVERS in the audio file's folder
+fn sibling_cover(file_path: &Path) -> Option<PathBuf> {
+    let entries: Vec<PathBuf> = fs::read_dir(file_path.parent()?)
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .collect();
+    SIBLING_COVERS.iter().find_map(|name| {
+        entries
+            .iter()
+            .find(|path| {
+                path.file_name()
+                    .is_some_and(|file_name| file_name.to_string_lossy().eq_ignore_ascii_case(name))
+            })
+            .cloned()
+    })
+}
+
+fn thumbnail_name(hash: &str) -> String {
+    format!("{}-thumb.jpg", hash)
+}
+
+// Data URL for an image, so the frontend can show it directly
+pub fn data_url(data: &[u8]) -> String {
+    use base64::Engine;
+    let mime = image::guess_format(data)
+        .map(|format| format.to_mime_type())
+        .unwrap_or("application/octet-stream");
+    format!("data:{};base64,{}", mime, base64::engine::general_purpose::STANDARD.encode(data))
+}
@@ -0,0 +1,36 @@
+/*
+ * atomic_file.rs
+ *
+ * Writing files so a crash or failed write never leaves half of one behind.
+ * The new contents go to a .partial file next to the final one and are
+ * renamed over it once complete, so readers see the old file or the new one.
+ */
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::Path;
+
+// Has `write` fill a new file next to `path` and moves it over `path`. The
+// partial file is removed again when `write` fails.
+pub fn replace_file(path: &Path, write: impl FnOnce(&mut File) -> Result<(), Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    let partial_path = path.with_extension("partial");
+    let result = File::create(&partial_path)
+        .map_err(|e| e.into())
+        .and_then(|mut partial| {
+            write(&mut partial)?;
+            partial.sync_all()?;
+            Ok(())
+        });
+    if let Err(e) = result {
+        fs::remove_file(&partial_path).ok();
+        return Err(e);
+    }
+    fs::rename(&partial_path, path)?;
+    Ok(())
+}
+
+// Replaces the contents of `path` with `data`.
+pub fn write_file(path: &Path, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    replace_file(path, |file| Ok(file.write_all(data)?))
+}
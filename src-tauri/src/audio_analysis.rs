@@ -1,114 +1,96 @@
-use pyo3::prelude::*;
-use pyo3::types::{PyAny, PyList, PyModule, PyString, PyTuple};
-use pyo3::wrap_pyfunction;
-use std::env;
-use std::path::PathBuf;
-
-fn get_venv_python_path() -> PathBuf {
-    // Get the current directory
-    let current_dir = env::current_dir().expect("Failed to get current directory");
-
-    // Determine the correct Python executable path for the platform
-    if cfg!(target_os = "windows") {
-        current_dir.join(".venv").join("Scripts").join("python.exe")
-    } else {
-        current_dir.join(".venv").join("bin").join("python3.12")
-    }
+/*
+ * audio_analysis.rs
+ *
+ * Native audio analysis for beats. Files are decoded with symphonia and all
+ * of the signal processing happens in Rust, so no Python install is needed.
+ *
+ * Submodules:
+ * - decode: Decodes a file into planar f32 PCM.
+ * - spectrum: Shared STFT helpers.
+ * - tempo: Onset envelope and tempogram based BPM estimation.
+ * - beat_tracking: Beat positions, downbeats and meter.
+ * - key: Chromagram and Krumhansl-Schmuckler key estimation.
+ * - loudness: EBU R128 loudness, loudness range and true peak.
+ * - waveform: Min/max/RMS overviews for drawing waveforms.
+ * - fingerprint: Audio hash and acoustic fingerprint for finding duplicates.
+ * - filter: Biquad filters shared by the above.
+ */
+
+mod beat_tracking;
+mod decode;
+mod filter;
+mod fingerprint;
+mod key;
+mod loudness;
+mod spectrum;
+mod tempo;
+mod waveform;
+
+use std::error::Error;
+use std::path::Path;
+
+pub use beat_tracking::BeatTracking;
+pub use decode::decode_file;
+pub use fingerprint::{decode_fingerprint, find_similar, AudioIdentity};
+pub use key::KeyEstimate;
+pub use loudness::Loudness;
+pub use waveform::Waveform;
+
+// Key estimates below this correlation should be double checked by ear.
+pub const LOW_KEY_CONFIDENCE: f64 = 0.6;
+
+pub struct AudioAnalysis {
+    pub bpm: Option<f64>,
+    pub key: Option<KeyEstimate>,
+    pub loudness: Option<Loudness>,
+    pub beats: Option<BeatTracking>,
+    pub identity: AudioIdentity,
 }
 
-fn get_venv_site_packages() -> PathBuf {
-    // Get the current directory
-    let current_dir = env::current_dir().expect("Failed to get current directory");
-
-    // Build path to the virtual environment's site-packages directory
-    if cfg!(target_os = "windows") {
-        current_dir.join(".venv").join("Lib").join("site-packages")
-    } else {
-        // For macOS/Linux, the site-packages is under lib directly
-        current_dir.join(".venv").join("lib").join("python3.12").join("site-packages")
-    }
+// Decodes the audio file at file_path once and runs tempo, beat, key and loudness analysis on it.
+pub fn analyze_audio(file_path: &str) -> Result<AudioAnalysis, Box<dyn Error>> {
+    let audio = decode_file(Path::new(file_path))?;
+    let mono = audio.mono();
+    let envelope = tempo::onset_envelope(&mono, audio.sample_rate);
+    let bpm = tempo::estimate_bpm_from_envelope(&envelope);
+
+    Ok(AudioAnalysis {
+        bpm,
+        key: key::estimate_key(&mono, audio.sample_rate),
+        loudness: loudness::measure_loudness(&audio),
+        beats: bpm.and_then(|bpm| beat_tracking::track_beats(&envelope, &mono, audio.sample_rate, bpm)),
+        identity: fingerprint::identify(&audio, &mono),
+    })
 }
 
-fn get_analyzer_path() -> PathBuf {
-    // Get the current directory
-    let current_dir = env::current_dir().expect("Failed to get current directory");
-
-    // Build path to the src directory (where the audio_analyzer.py is located)
-    current_dir.join("src")
+// Loudness, beats and identity on their own, for backends that only detect
+// tempo and key. The beat tracker follows `bpm` when given, otherwise it
+// estimates the tempo itself. bpm and key are left empty.
+pub fn analyze_loudness_and_beats(file_path: &str, bpm: Option<f64>) -> Result<AudioAnalysis, Box<dyn Error>> {
+    let audio = decode_file(Path::new(file_path))?;
+    let mono = audio.mono();
+    let envelope = tempo::onset_envelope(&mono, audio.sample_rate);
+    let beats = bpm
+        .or_else(|| tempo::estimate_bpm_from_envelope(&envelope))
+        .and_then(|bpm| beat_tracking::track_beats(&envelope, &mono, audio.sample_rate, bpm));
+
+    Ok(AudioAnalysis {
+        bpm: None,
+        key: None,
+        loudness: loudness::measure_loudness(&audio),
+        beats,
+        identity: fingerprint::identify(&audio, &mono),
+    })
 }
 
-#[pyfunction]
-pub fn analyze_audio(file_path: &str) -> PyResult<(String, f64)> {
-    // Construct the paths outside of the GIL context
-    let venv_python_path = get_venv_python_path();
-    let venv_site_packages = get_venv_site_packages();
-    let analyzer_path = get_analyzer_path();
-
-    Python::with_gil(|py| {
-        // Import the sys module
-        let sys: Bound<'_, PyModule> = py.import_bound("sys")?;
-
-        // Set the Python executable to the one inside the virtual environment
-        let venv_python_path_str = venv_python_path
-            .to_str()
-            .expect("Failed to convert path to str");
-        println!("Setting Python executable to: {:?}", venv_python_path_str);
-        sys.setattr("executable", venv_python_path_str)?;
-
-        // Ensure sys.prefix points to the virtual environment
-        sys.setattr("prefix", venv_python_path_str)?;
-        sys.setattr("base_prefix", venv_python_path_str)?;
-
-        // Ensure sys.path includes the virtual environment's site-packages
-        let path: Bound<'_, PyList> = sys.getattr("path")?.extract()?;
-        let venv_site_packages_str = venv_site_packages
-            .to_str()
-            .expect("Failed to convert venv path to str");
-        let analyzer_path_str = analyzer_path
-            .to_str()
-            .expect("Failed to convert analyzer path to str");
-
-        // Print paths for debugging
-        println!("Analyzer path: {:?}", analyzer_path_str);
-        println!(
-            "Virtual environment site-packages path: {:?}",
-            venv_site_packages_str
-        );
-
-        // Prepend the virtual environment's site-packages to sys.path
-        path.call_method(
-            "insert",
-            (0, PyString::new_bound(py, venv_site_packages_str)),
-            None,
-        )?;
-
-        // Also add the analyzer path where audio_analyzer.py is located
-        path.call_method(
-            "append",
-            (PyString::new_bound(py, analyzer_path_str),),
-            None,
-        )?;
-
-        // Print updated sys.path for debugging
-        let updated_path: Vec<String> = path
-            .iter()
-            .map(|p| p.extract::<String>())
-            .collect::<PyResult<Vec<String>>>()?;
-        println!("Updated Python path: {:?}", updated_path);
-
-        // Import the audio_analyzer module and call the analyze method
-        let my_module: Bound<'_, PyModule> = py.import_bound("audio_analyzer")?;
-        let py_file_path: Bound<'_, PyString> = PyString::new_bound(py, file_path);
-        let args = PyTuple::new_bound(py, vec![py_file_path]);
-        let result: Bound<'_, PyAny> = my_module.call_method("analyze", args, None)?;
-        let extracted_result: (String, f64) = result.extract()?;
-        
-        Ok(extracted_result)
-    })
+// Hash and fingerprint alone, for beats analyzed before they were stored.
+pub fn identify_audio(file_path: &Path) -> Result<AudioIdentity, Box<dyn Error>> {
+    let audio = decode_file(file_path)?;
+    Ok(fingerprint::identify(&audio, &audio.mono()))
 }
 
-#[pymodule(name = "audio_analyzer")]
-fn my_rust_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(analyze_audio, m)?)?;
-    Ok(())
+// Waveform overviews for drawing, optionally split into low/mid/high bands.
+pub fn analyze_waveform(file_path: &Path, bands: bool) -> Result<Waveform, Box<dyn Error>> {
+    let audio = decode_file(file_path)?;
+    Ok(waveform::compute_waveform(&audio, bands))
 }
@@ -0,0 +1,257 @@
+use super::filter::Biquad;
+use super::tempo::OnsetEnvelope;
+
+// How strongly the tracker sticks to the estimated tempo, librosa's default
+const TIGHTNESS: f64 = 100.0;
+
+// Kick drums and bass mark the start of a bar better than anything else
+const BASS_CUTOFF_HZ: f64 = 150.0;
+const ACCENT_WINDOW_SECONDS: f64 = 0.05;
+
+// Meters that are considered, and how much clearer (in standard deviations of
+// the accents) the downbeats of a meter other than 4/4 have to be before it wins
+const METERS: [usize; 2] = [4, 3];
+const METER_MARGIN: f64 = 0.25;
+
+// Beats found in one track, with the bar structure laid over them.
+#[derive(Clone, Debug)]
+pub struct BeatTracking {
+    // Seconds
+    pub beats: Vec<f64>,
+    // Index into beats of the first downbeat, always less than beats_per_bar
+    pub first_downbeat: usize,
+    pub beats_per_bar: usize,
+    // Length of the audio in seconds
+    pub duration: f64,
+}
+
+// Follows the beat through the track with dynamic programming (Ellis 2007):
+// every onset frame is scored by its onset strength plus the best preceding
+// beat about one period earlier, then the best chain is read back from the end.
+pub fn track_beats(envelope: &OnsetEnvelope, samples: &[f32], sample_rate: u32, bpm: f64) -> Option<BeatTracking> {
+    let period = 60.0 * envelope.frame_rate / bpm;
+    if !period.is_finite() || period < 2.0 || envelope.values.len() < (period * 4.0) as usize {
+        return None;
+    }
+
+    let local_score = local_score(&envelope.values, period);
+    let frames = local_score.len();
+    let mut cumulative = vec![0.0f64; frames];
+    let mut backlink: Vec<Option<usize>> = vec![None; frames];
+
+    let min_gap = (period / 2.0).round() as usize;
+    let max_gap = (period * 2.0).round() as usize;
+    for frame in 0..frames {
+        let best = (frame.saturating_sub(max_gap)..frame.saturating_sub(min_gap).min(frame))
+            .map(|previous| {
+                let ratio = (frame - previous) as f64 / period;
+                (previous, cumulative[previous] - TIGHTNESS * ratio.ln().powi(2))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+
+        cumulative[frame] = local_score[frame];
+        if let Some((previous, score)) = best {
+            if score > 0.0 {
+                cumulative[frame] += score;
+                backlink[frame] = Some(previous);
+            }
+        }
+    }
+
+    // The last beat is the last strong peak of the cumulative score
+    let peaks: Vec<usize> = (1..frames.saturating_sub(1))
+        .filter(|&i| cumulative[i] > cumulative[i - 1] && cumulative[i] >= cumulative[i + 1])
+        .collect();
+    let mut peak_scores: Vec<f64> = peaks.iter().map(|&i| cumulative[i]).collect();
+    peak_scores.sort_by(f64::total_cmp);
+    let median = *peak_scores.get(peak_scores.len() / 2)?;
+    let last = *peaks.iter().rev().find(|&&i| cumulative[i] >= 0.5 * median)?;
+
+    let mut frames_on_beat = vec![last];
+    while let Some(previous) = backlink[*frames_on_beat.last()?] {
+        frames_on_beat.push(previous);
+    }
+    frames_on_beat.reverse();
+    trim_weak_edges(&mut frames_on_beat, &local_score);
+    if frames_on_beat.len() < 4 {
+        return None;
+    }
+
+    let beats: Vec<f64> = frames_on_beat
+        .iter()
+        .map(|&frame| envelope.frame_time(frame as f64))
+        .collect();
+    let accents = beat_accents(&beats, samples, sample_rate)
+        .unwrap_or_else(|| frames_on_beat.iter().map(|&f| envelope.values[f] as f64).collect());
+    let (beats_per_bar, first_downbeat) = detect_meter(&accents);
+
+    Some(BeatTracking {
+        beats,
+        first_downbeat,
+        beats_per_bar,
+        duration: samples.len() as f64 / sample_rate as f64,
+    })
+}
+
+// Onset envelope normalized by its standard deviation and smoothed with a
+// Gaussian a fraction of a beat wide, so nearby onsets still attract the beat.
+fn local_score(values: &[f32], period: f64) -> Vec<f64> {
+    let count = values.len().max(1) as f64;
+    let mean = values.iter().map(|v| *v as f64).sum::<f64>() / count;
+    let deviation = (values.iter().map(|v| (*v as f64 - mean).powi(2)).sum::<f64>() / count).sqrt();
+    let scale = if deviation > 0.0 { 1.0 / deviation } else { 1.0 };
+
+    let radius = period.round() as isize;
+    let kernel: Vec<f64> = (-radius..=radius)
+        .map(|offset| (-0.5 * (offset as f64 * 32.0 / period).powi(2)).exp())
+        .collect();
+
+    (0..values.len() as isize)
+        .map(|center| {
+            kernel
+                .iter()
+                .zip(-radius..=radius)
+                .filter_map(|(weight, offset)| {
+                    let index = usize::try_from(center + offset).ok()?;
+                    values.get(index).map(|v| weight * *v as f64 * scale)
+                })
+                .sum()
+        })
+        .collect()
+}
+
+// Drops beats at the start and end that land on almost nothing, which happens
+// when the chain runs on through an intro or fade out.
+fn trim_weak_edges(frames: &mut Vec<usize>, local_score: &[f64]) {
+    let scores: Vec<f64> = frames.iter().map(|&f| local_score[f]).collect();
+    let rms = (scores.iter().map(|s| s * s).sum::<f64>() / scores.len().max(1) as f64).sqrt();
+    let threshold = 0.5 * rms;
+
+    let end = scores.iter().rposition(|s| *s >= threshold).map_or(0, |i| i + 1);
+    frames.truncate(end);
+    let start = scores.iter().position(|s| *s >= threshold).unwrap_or(0).min(frames.len());
+    frames.drain(..start);
+}
+
+// Bass energy right after every beat, or None when the track has hardly any bass.
+fn beat_accents(beats: &[f64], samples: &[f32], sample_rate: u32) -> Option<Vec<f64>> {
+    let mut filter = Biquad::low_pass(sample_rate, BASS_CUTOFF_HZ, std::f64::consts::FRAC_1_SQRT_2);
+    let bass: Vec<f64> = samples.iter().map(|s| filter.process(*s as f64)).collect();
+    let window = (ACCENT_WINDOW_SECONDS * sample_rate as f64) as usize;
+
+    let accents: Vec<f64> = beats
+        .iter()
+        .map(|beat| {
+            let start = ((beat * sample_rate as f64) as usize).min(bass.len());
+            let end = (start + window).min(bass.len());
+            let energy: f64 = bass[start..end].iter().map(|s| s * s).sum();
+            (energy / (end - start).max(1) as f64).sqrt()
+        })
+        .collect();
+
+    let total_rms = (bass.iter().map(|s| s * s).sum::<f64>() / bass.len().max(1) as f64).sqrt();
+    (total_rms > 1e-4).then_some(accents)
+}
+
+// Picks the meter and bar phase whose downbeats stand out most from the other
+// beats. Returns (beats_per_bar, index of the first downbeat).
+fn detect_meter(accents: &[f64]) -> (usize, usize) {
+    let mean = accents.iter().sum::<f64>() / accents.len().max(1) as f64;
+    let deviation = (accents.iter().map(|a| (a - mean).powi(2)).sum::<f64>() / accents.len().max(1) as f64).sqrt();
+    if deviation <= 0.0 {
+        return (METERS[0], 0);
+    }
+
+    let mut best = (METERS[0], 0, f64::NEG_INFINITY);
+    for (rank, &meter) in METERS.iter().enumerate() {
+        if accents.len() < meter * 2 {
+            continue;
+        }
+        for phase in 0..meter {
+            let (mut on, mut off) = ((0.0, 0), (0.0, 0));
+            for (i, accent) in accents.iter().enumerate() {
+                let group = if i % meter == phase { &mut on } else { &mut off };
+                group.0 += accent;
+                group.1 += 1;
+            }
+            let contrast = (on.0 / on.1 as f64 - off.0 / off.1.max(1) as f64) / deviation;
+            let biased = if rank == 0 { contrast + METER_MARGIN } else { contrast };
+            if biased > best.2 {
+                best = (meter, phase, biased);
+            }
+        }
+    }
+    (best.0, best.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_analysis::tempo::onset_envelope;
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: u32 = 44_100;
+
+    // Accents of `bars` bars, 1.0 on the downbeats and 0.3 on the other beats,
+    // starting `phase` beats into the first bar
+    fn accents(beats_per_bar: usize, bars: usize, phase: usize) -> Vec<f64> {
+        (0..beats_per_bar * bars)
+            .map(|i| if (i + phase).is_multiple_of(beats_per_bar) { 1.0 } else { 0.3 })
+            .collect()
+    }
+
+    // A click on every beat and a 60 Hz kick on every downbeat
+    fn kick_and_click(bpm: f64, beats_per_bar: usize, seconds: f64) -> Vec<f32> {
+        let mut samples = vec![0.0f32; (SAMPLE_RATE as f64 * seconds) as usize];
+        let period = 60.0 / bpm;
+        for beat in 0..(seconds / period) as usize {
+            let start = (beat as f64 * period * SAMPLE_RATE as f64).round() as usize;
+            let downbeat = beat.is_multiple_of(beats_per_bar);
+            let length = if downbeat { SAMPLE_RATE as usize / 10 } else { SAMPLE_RATE as usize / 50 };
+            for (i, sample) in samples[start..].iter_mut().take(length).enumerate() {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                *sample += 0.5 * (2.0 * PI * 1000.0 * t).sin() * (-t * 200.0).exp();
+                if downbeat {
+                    *sample += 0.8 * (2.0 * PI * 60.0 * t).sin() * (-t * 20.0).exp();
+                }
+            }
+        }
+        samples
+    }
+
+    #[test]
+    fn detects_four_four_and_its_phase() {
+        assert_eq!(detect_meter(&accents(4, 16, 0)), (4, 0));
+        // Starting on the second beat of a bar, the first downbeat is the fourth beat
+        assert_eq!(detect_meter(&accents(4, 16, 1)), (4, 3));
+    }
+
+    #[test]
+    fn detects_three_four() {
+        assert_eq!(detect_meter(&accents(3, 16, 0)), (3, 0));
+        assert_eq!(detect_meter(&accents(3, 16, 2)), (3, 1));
+    }
+
+    #[test]
+    fn even_accents_fall_back_to_four_four() {
+        assert_eq!(detect_meter(&[0.5; 32]), (4, 0));
+        assert_eq!(detect_meter(&[]), (4, 0));
+    }
+
+    #[test]
+    fn tracks_beats_and_bars_of_a_waltz() {
+        let samples = kick_and_click(120.0, 3, 30.0);
+        let envelope = onset_envelope(&samples, SAMPLE_RATE);
+        let tracking = track_beats(&envelope, &samples, SAMPLE_RATE, 120.0).unwrap();
+
+        assert_eq!(tracking.beats_per_bar, 3);
+        assert!(tracking.beats.len() > 50, "only {} beats", tracking.beats.len());
+        for pair in tracking.beats.windows(2) {
+            assert!((pair[1] - pair[0] - 0.5).abs() < 0.03, "beats {:?} aren't half a second apart", pair);
+        }
+        // Kicks play on every third beat from the start
+        let downbeat = tracking.beats[tracking.first_downbeat];
+        let beat_number = (downbeat / 0.5).round() as usize;
+        assert!(beat_number.is_multiple_of(3), "downbeat at {} s", downbeat);
+    }
+}
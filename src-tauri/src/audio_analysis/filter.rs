@@ -0,0 +1,66 @@
+use std::f64::consts::PI;
+
+// Direct form I biquad, a0 normalized to 1.
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    // Low pass from the RBJ audio EQ cookbook.
+    pub fn low_pass(sample_rate: u32, frequency: f64, q: f64) -> Self {
+        let (cos, alpha) = rbj_terms(sample_rate, frequency, q);
+        let a0 = 1.0 + alpha;
+        Biquad::new(
+            [(1.0 - cos) / 2.0 / a0, (1.0 - cos) / a0, (1.0 - cos) / 2.0 / a0],
+            [-2.0 * cos / a0, (1.0 - alpha) / a0],
+        )
+    }
+
+    // High pass from the RBJ audio EQ cookbook.
+    pub fn high_pass(sample_rate: u32, frequency: f64, q: f64) -> Self {
+        let (cos, alpha) = rbj_terms(sample_rate, frequency, q);
+        let a0 = 1.0 + alpha;
+        Biquad::new(
+            [(1.0 + cos) / 2.0 / a0, -(1.0 + cos) / a0, (1.0 + cos) / 2.0 / a0],
+            [-2.0 * cos / a0, (1.0 - alpha) / a0],
+        )
+    }
+
+    // Band pass with 0 dB gain at the center frequency, from the RBJ audio EQ cookbook.
+    pub fn band_pass(sample_rate: u32, frequency: f64, q: f64) -> Self {
+        let (cos, alpha) = rbj_terms(sample_rate, frequency, q);
+        let a0 = 1.0 + alpha;
+        Biquad::new(
+            [alpha / a0, 0.0, -alpha / a0],
+            [-2.0 * cos / a0, (1.0 - alpha) / a0],
+        )
+    }
+
+    pub fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+fn rbj_terms(sample_rate: u32, frequency: f64, q: f64) -> (f64, f64) {
+    // Keep the corner below Nyquist for low sample rates
+    let frequency = frequency.min(sample_rate as f64 * 0.45);
+    let omega = 2.0 * PI * frequency / sample_rate as f64;
+    (omega.cos(), omega.sin() / (2.0 * q))
+}
@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use super::decode::DecodedAudio;
+use super::spectrum::for_each_magnitude_frame;
+
+// One sub-fingerprint every HOP_SECONDS, each from a FRAME_SECONDS window. Fixed
+// in seconds so files at different sample rates still line up.
+const FRAME_SECONDS: f32 = 0.2;
+const HOP_SECONDS: f32 = 0.1;
+// 33 bands give the 32 bits of a sub-fingerprint. The range carries most of the
+// melody and chords and is the least affected by mixing and encoding.
+const BAND_COUNT: usize = 33;
+const MIN_FREQUENCY: f32 = 300.0;
+const MAX_FREQUENCY: f32 = 2000.0;
+// Sub-fingerprints shared by more beats than this say nothing about any of them
+const MAX_POSTINGS: usize = 64;
+// A pair needs this many exact matches at the same offset to be compared
+const MIN_VOTES: usize = 2;
+
+// What identifies a beat's audio: a hash of the decoded samples for exact
+// copies and a fingerprint for near-duplicates.
+#[derive(Clone, PartialEq)]
+pub struct AudioIdentity {
+    // blake3 of the samples, so copies with different tags still match
+    pub hash: String,
+    // Little endian u32 sub-fingerprints
+    pub fingerprint: Vec<u8>,
+}
+
+// The fingerprint is thousands of bytes, too many for the analysis log
+impl fmt::Debug for AudioIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AudioIdentity")
+            .field("hash", &self.hash)
+            .field("fingerprint", &format_args!("{} bytes", self.fingerprint.len()))
+            .finish()
+    }
+}
+
+pub fn identify(audio: &DecodedAudio, mono: &[f32]) -> AudioIdentity {
+    AudioIdentity {
+        hash: audio_hash(audio),
+        fingerprint: compute_fingerprint(mono, audio.sample_rate)
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect(),
+    }
+}
+
+// Hashes the samples as 16 bit PCM, so tiny float differences between decoder
+// versions don't change it.
+fn audio_hash(audio: &DecodedAudio) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&audio.sample_rate.to_le_bytes());
+    hasher.update(&(audio.channels.len() as u32).to_le_bytes());
+    let frames = audio.channels.first().map_or(0, |channel| channel.len());
+    let mut buffer = Vec::with_capacity(audio.channels.len() * 2 * 4096);
+    for start in (0..frames).step_by(4096) {
+        buffer.clear();
+        for i in start..(start + 4096).min(frames) {
+            for channel in &audio.channels {
+                let sample = (channel[i].clamp(-1.0, 1.0) * 32767.0).round() as i16;
+                buffer.extend_from_slice(&sample.to_le_bytes());
+            }
+        }
+        hasher.update(&buffer);
+    }
+    hasher.finalize().to_hex().to_string()
+}
+
+// Philips style fingerprint: every bit says whether the energy difference of
+// two neighboring bands grew or shrank since the previous frame. Mixing and
+// encoding change the energies but mostly not which way they move.
+fn compute_fingerprint(samples: &[f32], sample_rate: u32) -> Vec<u32> {
+    let frame_size = ((sample_rate as f32 * FRAME_SECONDS) as usize).max(2);
+    let hop_size = ((sample_rate as f32 * HOP_SECONDS) as usize).max(1);
+    let bin_hz = sample_rate as f32 / frame_size as f32;
+
+    // Logarithmically spaced band edges, in FFT bins
+    let edges: Vec<usize> = (0..=BAND_COUNT)
+        .map(|i| {
+            let frequency = MIN_FREQUENCY * (MAX_FREQUENCY / MIN_FREQUENCY).powf(i as f32 / BAND_COUNT as f32);
+            (frequency / bin_hz).round() as usize
+        })
+        .collect();
+
+    let mut fingerprint = Vec::new();
+    let mut previous: Option<[f32; BAND_COUNT]> = None;
+    for_each_magnitude_frame(samples, frame_size, hop_size, |_, magnitudes| {
+        let mut energies = [0.0f32; BAND_COUNT];
+        for (band, energy) in energies.iter_mut().enumerate() {
+            let end = edges[band + 1].max(edges[band] + 1).min(magnitudes.len());
+            let start = edges[band].min(end);
+            *energy = magnitudes[start..end].iter().map(|m| m * m).sum();
+        }
+        if let Some(previous) = previous {
+            let mut bits = 0u32;
+            for band in 0..BAND_COUNT - 1 {
+                let now = energies[band] - energies[band + 1];
+                let before = previous[band] - previous[band + 1];
+                if now - before > 0.0 {
+                    bits |= 1 << band;
+                }
+            }
+            fingerprint.push(bits);
+        }
+        previous = Some(energies);
+    });
+    fingerprint
+}
+
+pub fn decode_fingerprint(data: &[u8]) -> Vec<u32> {
+    data.chunks_exact(4)
+        .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect()
+}
+
+// Share of matching bits when b is shifted by offset frames against a, over
+// the part where they overlap. Overlaps shorter than half the longer
+// fingerprint don't count, so an intro doesn't match a whole track.
+pub fn similarity_at(a: &[u32], b: &[u32], offset: isize) -> f64 {
+    let a_start = offset.max(0) as usize;
+    let b_start = (-offset).max(0) as usize;
+    if a_start >= a.len() || b_start >= b.len() {
+        return 0.0;
+    }
+    let overlap = (a.len() - a_start).min(b.len() - b_start);
+    if overlap * 2 < a.len().max(b.len()) {
+        return 0.0;
+    }
+    let differing: u32 = a[a_start..a_start + overlap]
+        .iter()
+        .zip(&b[b_start..b_start + overlap])
+        .map(|(x, y)| (x ^ y).count_ones())
+        .sum();
+    1.0 - differing as f64 / (overlap * 32) as f64
+}
+
+// Pairs of fingerprints at least min_similarity alike, as indexes into
+// fingerprints with their similarity. Comparing every pair at every offset
+// would be far too slow for a whole library, so candidates and their offset
+// come from sub-fingerprints the two share exactly.
+pub fn find_similar(fingerprints: &[Vec<u32>], min_similarity: f64) -> Vec<(usize, usize, f64)> {
+    let mut postings: HashMap<u32, Vec<(usize, usize)>> = HashMap::new();
+    for (index, fingerprint) in fingerprints.iter().enumerate() {
+        for (frame, value) in fingerprint.iter().enumerate() {
+            // Silence and steady tones give all zeros or all ones
+            if value.count_ones() > 2 && value.count_ones() < 30 {
+                postings.entry(*value).or_default().push((index, frame));
+            }
+        }
+    }
+
+    let mut votes: HashMap<(usize, usize, isize), usize> = HashMap::new();
+    for entries in postings.values().filter(|entries| entries.len() <= MAX_POSTINGS) {
+        for (i, &(a, a_frame)) in entries.iter().enumerate() {
+            for &(b, b_frame) in &entries[i + 1..] {
+                if a != b {
+                    let (first, second, offset) = if a < b {
+                        (a, b, a_frame as isize - b_frame as isize)
+                    } else {
+                        (b, a, b_frame as isize - a_frame as isize)
+                    };
+                    *votes.entry((first, second, offset)).or_default() += 1;
+                }
+            }
+        }
+    }
+
+    // Best offset of every pair with enough votes
+    let mut best: HashMap<(usize, usize), f64> = HashMap::new();
+    for ((a, b, offset), count) in votes {
+        if count < MIN_VOTES {
+            continue;
+        }
+        let similarity = similarity_at(&fingerprints[a], &fingerprints[b], offset);
+        let entry = best.entry((a, b)).or_default();
+        *entry = entry.max(similarity);
+    }
+    let mut pairs: Vec<(usize, usize, f64)> = best
+        .into_iter()
+        .filter(|(_, similarity)| *similarity >= min_similarity)
+        .map(|((a, b), similarity)| (a, b, similarity))
+        .collect();
+    pairs.sort_by_key(|pair| (pair.0, pair.1));
+    pairs
+}
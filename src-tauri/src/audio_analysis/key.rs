@@ -0,0 +1,124 @@
+use super::spectrum::for_each_magnitude_frame;
+use crate::musical_key::{Mode, MusicalKey};
+
+// Only this range carries useful pitch information; below it bins are too
+// coarse to separate semitones and above it harmonics blur the chroma.
+const MIN_FREQUENCY: f32 = 65.0;
+const MAX_FREQUENCY: f32 = 2100.0;
+
+// Krumhansl-Kessler key profiles, indexed from the tonic
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+
+#[derive(Clone, Copy, Debug)]
+pub struct KeyEstimate {
+    pub key: MusicalKey,
+    // Correlation of the chroma with the winning profile, 0.0 to 1.0
+    pub confidence: f64,
+}
+
+impl KeyEstimate {
+    // Normalized key name as stored in the database, e.g. "F# Minor".
+    pub fn name(&self) -> String {
+        self.key.to_string()
+    }
+}
+
+// Estimates the key with the Krumhansl-Schmuckler algorithm: build an average
+// chroma vector for the track and correlate it with all 24 rotated profiles.
+pub fn estimate_key(samples: &[f32], sample_rate: u32) -> Option<KeyEstimate> {
+    let chroma = chromagram_mean(samples, sample_rate)?;
+
+    let mut best: Option<KeyEstimate> = None;
+    for tonic in 0..12 {
+        for (mode, profile) in [(Mode::Major, &MAJOR_PROFILE), (Mode::Minor, &MINOR_PROFILE)] {
+            let rotated: Vec<f64> = (0..12).map(|pc| profile[(pc + 12 - tonic) % 12]).collect();
+            let correlation = pearson(&chroma, &rotated);
+            if best.is_none_or(|b| correlation > b.confidence) {
+                best = Some(KeyEstimate {
+                    key: MusicalKey::new(tonic as u8, mode),
+                    confidence: correlation,
+                });
+            }
+        }
+    }
+
+    best.map(|key| KeyEstimate {
+        confidence: key.confidence.clamp(0.0, 1.0),
+        ..key
+    })
+}
+
+// Average chroma over all non-silent frames. Each frame is normalized first
+// so the loud sections of a track don't outvote the quiet ones.
+fn chromagram_mean(samples: &[f32], sample_rate: u32) -> Option<[f64; 12]> {
+    // Roughly a quarter second window, which resolves semitones down to C2
+    let target = (sample_rate as usize / 4).max(2);
+    let frame_size = if target.is_power_of_two() {
+        target
+    } else {
+        target.next_power_of_two() / 2
+    };
+    let hop_size = frame_size / 2;
+
+    let bin_width = sample_rate as f32 / frame_size as f32;
+    let bin_pitch_classes: Vec<Option<usize>> = (0..frame_size / 2 + 1)
+        .map(|bin| {
+            let frequency = bin as f32 * bin_width;
+            if !(MIN_FREQUENCY..=MAX_FREQUENCY).contains(&frequency) {
+                return None;
+            }
+            let midi = 69.0 + 12.0 * (frequency / 440.0).log2();
+            Some((midi.round() as i64).rem_euclid(12) as usize)
+        })
+        .collect();
+
+    let mut total = [0.0f64; 12];
+    let mut frames_used = 0usize;
+
+    for_each_magnitude_frame(samples, frame_size, hop_size, |_, magnitudes| {
+        let mut frame_chroma = [0.0f64; 12];
+        for (magnitude, pitch_class) in magnitudes.iter().zip(&bin_pitch_classes) {
+            if let Some(pc) = pitch_class {
+                frame_chroma[*pc] += (*magnitude as f64) * (*magnitude as f64);
+            }
+        }
+
+        let peak = frame_chroma.iter().cloned().fold(0.0, f64::max);
+        if peak > 1e-9 {
+            for (sum, value) in total.iter_mut().zip(frame_chroma) {
+                *sum += value / peak;
+            }
+            frames_used += 1;
+        }
+    });
+
+    if frames_used == 0 {
+        return None;
+    }
+    Some(total.map(|v| v / frames_used as f64))
+}
+
+fn pearson(a: &[f64], b: &[f64]) -> f64 {
+    let n = a.len() as f64;
+    let mean_a = a.iter().sum::<f64>() / n;
+    let mean_b = b.iter().sum::<f64>() / n;
+
+    let mut covariance = 0.0;
+    let mut variance_a = 0.0;
+    let mut variance_b = 0.0;
+    for (x, y) in a.iter().zip(b) {
+        covariance += (x - mean_a) * (y - mean_b);
+        variance_a += (x - mean_a) * (x - mean_a);
+        variance_b += (y - mean_b) * (y - mean_b);
+    }
+
+    if variance_a == 0.0 || variance_b == 0.0 {
+        return 0.0;
+    }
+    covariance / (variance_a.sqrt() * variance_b.sqrt())
+}
@@ -0,0 +1,306 @@
+use std::f64::consts::PI;
+
+use super::decode::DecodedAudio;
+use super::filter::Biquad;
+
+// EBU R128 / ITU-R BS.1770-4 loudness measurement. Signal energy is summed
+// per 100 ms segment after K-weighting, and the 400 ms momentary and 3 s
+// short-term windows are built from those segments.
+const SEGMENT_SECONDS: f64 = 0.1;
+const MOMENTARY_SEGMENTS: usize = 4;
+const SHORT_TERM_SEGMENTS: usize = 30;
+
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+const INTEGRATED_RELATIVE_GATE_LU: f64 = -10.0;
+const RANGE_RELATIVE_GATE_LU: f64 = -20.0;
+
+// Taps per polyphase branch of the true peak interpolator
+const TRUE_PEAK_TAPS: usize = 12;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Loudness {
+    // Gated integrated loudness of the whole track, LUFS
+    pub integrated: f64,
+    // Loudest 3 s window, LUFS. None for tracks shorter than that.
+    pub short_term_max: Option<f64>,
+    // Spread between quiet and loud passages, LU. None for tracks under 3 s.
+    pub range: Option<f64>,
+    // Highest inter-sample peak, dBTP
+    pub true_peak: f64,
+}
+
+// Measures a decoded track. Returns None for silence or audio too short to
+// fill a single 400 ms block.
+pub fn measure_loudness(audio: &DecodedAudio) -> Option<Loudness> {
+    let segment_len = (audio.sample_rate as f64 * SEGMENT_SECONDS).round() as usize;
+    if segment_len == 0 {
+        return None;
+    }
+
+    let weights = channel_weights(audio.channels.len());
+    let frames = audio.channels.first().map_or(0, |c| c.len());
+    let segment_count = frames / segment_len;
+    if segment_count < MOMENTARY_SEGMENTS {
+        return None;
+    }
+
+    // Weighted sum of squared K-weighted samples in every 100 ms segment
+    let mut segments = vec![0.0f64; segment_count];
+    for (channel, weight) in audio.channels.iter().zip(&weights) {
+        if *weight == 0.0 {
+            continue;
+        }
+        let mut filter = KWeighting::new(audio.sample_rate);
+        for (i, sample) in channel.iter().take(segment_count * segment_len).enumerate() {
+            let filtered = filter.process(*sample as f64);
+            segments[i / segment_len] += weight * filtered * filtered;
+        }
+    }
+
+    let momentary = window_loudness(&segments, MOMENTARY_SEGMENTS, segment_len);
+    let integrated = gated_mean(&momentary, INTEGRATED_RELATIVE_GATE_LU)?;
+
+    let short_term = window_loudness(&segments, SHORT_TERM_SEGMENTS, segment_len);
+    let short_term_max = short_term.iter().cloned().reduce(f64::max);
+    let range = loudness_range(&short_term);
+
+    let true_peak = audio
+        .channels
+        .iter()
+        .map(|channel| true_peak(channel, audio.sample_rate))
+        .fold(0.0, f64::max);
+
+    Some(Loudness {
+        integrated,
+        short_term_max,
+        range,
+        true_peak: 20.0 * true_peak.max(1e-10).log10(),
+    })
+}
+
+// BS.1770 channel weights. Only 5.1 gets special treatment: the LFE channel
+// is left out and the surrounds count 1.41 times.
+fn channel_weights(channel_count: usize) -> Vec<f64> {
+    if channel_count == 6 {
+        vec![1.0, 1.0, 1.0, 0.0, 1.41, 1.41]
+    } else {
+        vec![1.0; channel_count]
+    }
+}
+
+fn to_lufs(mean_square: f64) -> f64 {
+    -0.691 + 10.0 * mean_square.log10()
+}
+
+// Loudness of every window of `length` segments, stepping one segment at a time.
+fn window_loudness(segments: &[f64], length: usize, segment_len: usize) -> Vec<f64> {
+    if segments.len() < length {
+        return Vec::new();
+    }
+    let samples = (length * segment_len) as f64;
+    segments
+        .windows(length)
+        .map(|window| to_lufs(window.iter().sum::<f64>() / samples))
+        .collect()
+}
+
+// Loudness of the mean energy of a set of blocks. None for an empty set.
+fn energy_mean(blocks: impl Iterator<Item = f64>) -> Option<f64> {
+    let (sum, count) = blocks.fold((0.0, 0usize), |(sum, count), lufs| {
+        (sum + 10f64.powf((lufs + 0.691) / 10.0), count + 1)
+    });
+    (count > 0).then(|| to_lufs(sum / count as f64))
+}
+
+// Mean loudness of the blocks that pass the absolute gate and then the
+// relative gate, `relative_gate` LU below the mean of the first pass.
+fn gated_mean(blocks: &[f64], relative_gate: f64) -> Option<f64> {
+    let above_absolute = || blocks.iter().cloned().filter(|l| *l > ABSOLUTE_GATE_LUFS);
+    let threshold = energy_mean(above_absolute())? + relative_gate;
+    energy_mean(above_absolute().filter(|l| *l > threshold))
+}
+
+// EBU Tech 3342: the spread between the 10th and 95th percentile of the
+// gated short-term loudness.
+fn loudness_range(short_term: &[f64]) -> Option<f64> {
+    let above_absolute = || short_term.iter().cloned().filter(|l| *l > ABSOLUTE_GATE_LUFS);
+    let threshold = energy_mean(above_absolute())? + RANGE_RELATIVE_GATE_LU;
+
+    let mut gated: Vec<f64> = above_absolute().filter(|l| *l > threshold).collect();
+    if gated.is_empty() {
+        return None;
+    }
+    gated.sort_by(|a, b| a.total_cmp(b));
+
+    let percentile = |p: f64| gated[((gated.len() - 1) as f64 * p).round() as usize];
+    Some(percentile(0.95) - percentile(0.10))
+}
+
+// Peak of the signal oversampled to at least 176.4 kHz, which catches the
+// overs a DAC would produce between samples.
+fn true_peak(samples: &[f32], sample_rate: u32) -> f64 {
+    let factor = match sample_rate {
+        0..=95_999 => 4,
+        96_000..=191_999 => 2,
+        _ => 1,
+    };
+    let sample_peak = samples.iter().fold(0.0f64, |peak, s| peak.max(s.abs() as f64));
+    if factor == 1 {
+        return sample_peak;
+    }
+
+    // Windowed sinc low pass, split into one branch per output phase
+    let length = TRUE_PEAK_TAPS * factor;
+    let center = (length - 1) as f64 / 2.0;
+    let mut phases = vec![vec![0.0f64; TRUE_PEAK_TAPS]; factor];
+    for n in 0..length {
+        let x = (n as f64 - center) / factor as f64;
+        let sinc = if x == 0.0 { 1.0 } else { (PI * x).sin() / (PI * x) };
+        let window = 0.5 - 0.5 * (2.0 * PI * (n as f64 + 0.5) / length as f64).cos();
+        phases[n % factor][n / factor] = sinc * window;
+    }
+    for phase in phases.iter_mut() {
+        let sum: f64 = phase.iter().sum();
+        phase.iter_mut().for_each(|tap| *tap /= sum);
+    }
+
+    let mut history = [0.0f64; TRUE_PEAK_TAPS];
+    let mut peak = sample_peak;
+    for (i, sample) in samples.iter().enumerate() {
+        history[i % TRUE_PEAK_TAPS] = *sample as f64;
+        for phase in &phases {
+            let value: f64 = phase
+                .iter()
+                .enumerate()
+                .map(|(k, tap)| tap * history[(i + TRUE_PEAK_TAPS - k) % TRUE_PEAK_TAPS])
+                .sum();
+            peak = peak.max(value.abs());
+        }
+    }
+    peak
+}
+
+// The two stage K-weighting filter from BS.1770: a high shelf modelling the
+// head, then a high pass. Coefficients are derived for any sample rate.
+struct KWeighting {
+    stages: [Biquad; 2],
+}
+
+impl KWeighting {
+    fn new(sample_rate: u32) -> Self {
+        let rate = sample_rate as f64;
+
+        let f0 = 1681.974450955533;
+        let gain_db = 3.999843853973347;
+        let q = 0.7071752369554196;
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+        let shelf = Biquad::new(
+            [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        let high_pass = Biquad::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        );
+
+        KWeighting {
+            stages: [shelf, high_pass],
+        }
+    }
+
+    fn process(&mut self, sample: f64) -> f64 {
+        self.stages.iter_mut().fold(sample, |x, stage| stage.process(x))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio_analysis::decode_file;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    const SAMPLE_RATE: u32 = 48_000;
+
+    // Stereo 1 kHz sine, `seconds` long at each of the given peak levels in dBFS
+    fn sine_file(name: &str, levels: &[(f64, f64)]) -> PathBuf {
+        let mut samples = Vec::new();
+        for (level_db, seconds) in levels {
+            let amplitude = 10f64.powf(level_db / 20.0);
+            for n in 0..(SAMPLE_RATE as f64 * seconds) as usize {
+                let t = n as f64 / SAMPLE_RATE as f64;
+                samples.push(amplitude * (2.0 * PI * 1000.0 * t).sin());
+            }
+        }
+
+        // 16 bit PCM wav, both channels the same
+        let data_len = (samples.len() * 4) as u32;
+        let mut bytes = Vec::with_capacity(44 + data_len as usize);
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + data_len).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        bytes.extend_from_slice(&(SAMPLE_RATE * 4).to_le_bytes());
+        bytes.extend_from_slice(&4u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&data_len.to_le_bytes());
+        for sample in samples {
+            let value = (sample * 32767.0).round() as i16;
+            bytes.extend_from_slice(&value.to_le_bytes());
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let path = std::env::temp_dir().join(format!("beatbank-loudness-{}.wav", name));
+        std::fs::File::create(&path).unwrap().write_all(&bytes).unwrap();
+        path
+    }
+
+    fn measure(path: &PathBuf) -> Loudness {
+        let audio = decode_file(path).unwrap();
+        std::fs::remove_file(path).ok();
+        measure_loudness(&audio).unwrap()
+    }
+
+    // EBU Tech 3341 case 1: a -23 dBFS stereo sine reads -23 LUFS
+    #[test]
+    fn sine_at_minus_23_dbfs() {
+        let loudness = measure(&sine_file("minus-23", &[(-23.0, 20.0)]));
+        assert!((loudness.integrated + 23.0).abs() < 0.1, "{:?}", loudness);
+        assert!((loudness.short_term_max.unwrap() + 23.0).abs() < 0.1, "{:?}", loudness);
+        assert!(loudness.range.unwrap() < 0.1, "{:?}", loudness);
+        assert!((loudness.true_peak + 23.0).abs() < 0.2, "{:?}", loudness);
+    }
+
+    // EBU Tech 3342 case 1: 20 s at -20 dBFS then 20 s at -30 dBFS has a range of 10 LU
+    #[test]
+    fn loudness_range_of_two_levels() {
+        let loudness = measure(&sine_file("range", &[(-20.0, 20.0), (-30.0, 20.0)]));
+        assert!((loudness.range.unwrap() - 10.0).abs() < 1.0, "{:?}", loudness);
+        assert!((loudness.short_term_max.unwrap() + 20.0).abs() < 0.1, "{:?}", loudness);
+        // Both halves pass the relative gate, so they average in the energy domain
+        let expected = 10.0 * ((0.01 + 0.001) / 2.0f64).log10();
+        assert!((loudness.integrated - expected).abs() < 0.1, "{:?}", loudness);
+    }
+
+    // Silence is below the absolute gate and has no integrated loudness
+    #[test]
+    fn silence_is_not_measured() {
+        let audio = DecodedAudio {
+            sample_rate: SAMPLE_RATE,
+            channels: vec![vec![0.0; SAMPLE_RATE as usize * 5]; 2],
+        };
+        assert!(measure_loudness(&audio).is_none());
+    }
+}
@@ -0,0 +1,45 @@
+use rustfft::num_complex::Complex;
+use rustfft::FftPlanner;
+use std::f32::consts::PI;
+
+// Hann window of the given length, used for every STFT frame.
+pub fn hann_window(size: usize) -> Vec<f32> {
+    (0..size)
+        .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / size as f32).cos())
+        .collect()
+}
+
+// Walks the signal in overlapping windowed frames and hands the magnitude
+// spectrum (frame_size / 2 + 1 bins) of each frame to `on_frame`.
+// Frames are streamed rather than collected so long tracks stay cheap on memory.
+pub fn for_each_magnitude_frame<F>(samples: &[f32], frame_size: usize, hop_size: usize, mut on_frame: F)
+where
+    F: FnMut(usize, &[f32]),
+{
+    if samples.len() < frame_size {
+        return;
+    }
+
+    let fft = FftPlanner::<f32>::new().plan_fft_forward(frame_size);
+    let window = hann_window(frame_size);
+    let mut buffer = vec![Complex::new(0.0, 0.0); frame_size];
+    let mut magnitudes = vec![0.0f32; frame_size / 2 + 1];
+
+    let frame_count = (samples.len() - frame_size) / hop_size + 1;
+    for frame_index in 0..frame_count {
+        let start = frame_index * hop_size;
+        for (slot, (sample, w)) in buffer
+            .iter_mut()
+            .zip(samples[start..start + frame_size].iter().zip(&window))
+        {
+            *slot = Complex::new(sample * w, 0.0);
+        }
+
+        fft.process(&mut buffer);
+
+        for (magnitude, bin) in magnitudes.iter_mut().zip(&buffer) {
+            *magnitude = bin.norm();
+        }
+        on_frame(frame_index, &magnitudes);
+    }
+}
@@ -0,0 +1,247 @@
+use super::spectrum::for_each_magnitude_frame;
+
+const FRAME_SIZE: usize = 2048;
+const HOP_SIZE: usize = 512;
+
+// Range of tempos the estimator will consider at all
+const MIN_BPM: f64 = 30.0;
+const MAX_BPM: f64 = 300.0;
+
+// Log-normal prior centered on 120 BPM with a one octave spread, the same
+// starting point librosa's beat tracker uses, to break half/double ties.
+const PRIOR_BPM: f64 = 120.0;
+const PRIOR_OCTAVES: f64 = 1.0;
+
+// Length of each autocorrelation window in the tempogram
+const TEMPOGRAM_WINDOW_SECONDS: f64 = 8.0;
+
+// Onset strength over time, sampled at `frame_rate` frames per second.
+pub struct OnsetEnvelope {
+    pub values: Vec<f32>,
+    pub frame_rate: f64,
+}
+
+impl OnsetEnvelope {
+    // Time in seconds of a (possibly fractional) envelope frame. Flux is the
+    // change from the previous frame, so it peaks about a hop before the
+    // transient reaches the middle of the STFT window.
+    pub fn frame_time(&self, frame: f64) -> f64 {
+        (frame + 1.0 + FRAME_SIZE as f64 / (2.0 * HOP_SIZE as f64)) / self.frame_rate
+    }
+}
+
+// Computes a spectral flux onset envelope: the summed increase in
+// log-magnitude between consecutive STFT frames, with the local average
+// removed so sustained notes don't register as onsets.
+pub fn onset_envelope(samples: &[f32], sample_rate: u32) -> OnsetEnvelope {
+    let mut previous: Vec<f32> = Vec::new();
+    let mut flux: Vec<f32> = Vec::new();
+
+    for_each_magnitude_frame(samples, FRAME_SIZE, HOP_SIZE, |_, magnitudes| {
+        let log_magnitudes: Vec<f32> = magnitudes.iter().map(|m| (1.0 + 100.0 * m).ln()).collect();
+        let value = if previous.is_empty() {
+            0.0
+        } else {
+            log_magnitudes
+                .iter()
+                .zip(&previous)
+                .map(|(current, last)| (current - last).max(0.0))
+                .sum()
+        };
+        flux.push(value);
+        previous = log_magnitudes;
+    });
+
+    let frame_rate = sample_rate as f64 / HOP_SIZE as f64;
+
+    // Subtract a one second moving average and keep only the positive part
+    let radius = (frame_rate / 2.0).round() as usize;
+    let mut prefix = vec![0.0f64; flux.len() + 1];
+    for (i, value) in flux.iter().enumerate() {
+        prefix[i + 1] = prefix[i] + *value as f64;
+    }
+    let values = (0..flux.len())
+        .map(|i| {
+            let start = i.saturating_sub(radius);
+            let end = (i + radius + 1).min(flux.len());
+            let mean = (prefix[end] - prefix[start]) / (end - start) as f64;
+            (flux[i] as f64 - mean).max(0.0) as f32
+        })
+        .collect();
+
+    OnsetEnvelope { values, frame_rate }
+}
+
+// Estimates the dominant tempo in BPM, or None when there is nothing
+// rhythmic to lock on to (silence, or a clip shorter than a couple of beats).
+pub fn estimate_bpm_from_envelope(envelope: &OnsetEnvelope) -> Option<f64> {
+    let frame_rate = envelope.frame_rate;
+    let min_lag = (60.0 * frame_rate / MAX_BPM).floor().max(1.0) as usize;
+    let max_lag = (60.0 * frame_rate / MIN_BPM).ceil() as usize;
+
+    let acf = tempogram(&envelope.values, frame_rate, max_lag)?;
+    let max_lag = max_lag.min(acf.len() - 2);
+    if min_lag + 1 >= max_lag {
+        return None;
+    }
+
+    // Pick the strongest lag after weighting by the tempo prior
+    let weighted = |lag: usize| acf[lag] * tempo_prior(60.0 * frame_rate / lag as f64);
+    let best_lag = (min_lag..=max_lag).max_by(|a, b| weighted(*a).total_cmp(&weighted(*b)))?;
+    if acf[best_lag] <= 0.0 {
+        return None;
+    }
+
+    let lag = refine_lag(&acf, best_lag);
+    let bpm = 60.0 * frame_rate / lag;
+
+    Some((bpm * 100.0).round() / 100.0)
+}
+
+// Averages the normalized autocorrelation of the onset envelope over
+// overlapping windows, which keeps one loud section from dominating.
+fn tempogram(envelope: &[f32], frame_rate: f64, max_lag: usize) -> Option<Vec<f64>> {
+    let window = ((TEMPOGRAM_WINDOW_SECONDS * frame_rate) as usize).max(max_lag + 2);
+    let window = window.min(envelope.len());
+    if window < 4 {
+        return None;
+    }
+    let hop = (window / 4).max(1);
+    let lags = (max_lag + 2).min(window);
+
+    let mut sum = vec![0.0f64; lags];
+    let mut windows_used = 0;
+
+    let mut start = 0;
+    while start + window <= envelope.len() {
+        let frame = &envelope[start..start + window];
+        let energy: f64 = frame.iter().map(|v| (*v as f64) * (*v as f64)).sum();
+        if energy > 0.0 {
+            for (lag, total) in sum.iter_mut().enumerate() {
+                let correlation: f64 = frame[..window - lag]
+                    .iter()
+                    .zip(&frame[lag..])
+                    .map(|(a, b)| *a as f64 * *b as f64)
+                    .sum();
+                *total += correlation / energy;
+            }
+            windows_used += 1;
+        }
+        start += hop;
+    }
+
+    if windows_used == 0 {
+        return None;
+    }
+    Some(sum.into_iter().map(|v| v / windows_used as f64).collect())
+}
+
+fn tempo_prior(bpm: f64) -> f64 {
+    let octaves = (bpm / PRIOR_BPM).log2() / PRIOR_OCTAVES;
+    (-0.5 * octaves * octaves).exp()
+}
+
+// Sub-frame peak position by fitting a parabola through the neighbours.
+fn parabolic_peak(values: &[f64], index: usize) -> f64 {
+    if index == 0 || index + 1 >= values.len() {
+        return index as f64;
+    }
+    let (left, center, right) = (values[index - 1], values[index], values[index + 1]);
+    let denominator = left - 2.0 * center + right;
+    if denominator.abs() < f64::EPSILON {
+        return index as f64;
+    }
+    index as f64 + 0.5 * (left - right) / denominator
+}
+
+// One frame of lag is several BPM at typical tempos, so the peak is refined
+// by also locating it at 2x, 3x and 4x the lag where the relative error is smaller.
+fn refine_lag(acf: &[f64], lag: usize) -> f64 {
+    let mut estimates = vec![parabolic_peak(acf, lag)];
+
+    for multiple in 2..=4usize {
+        let center = lag * multiple;
+        let low = center.saturating_sub(multiple);
+        let high = center + multiple;
+        if high + 1 >= acf.len() {
+            break;
+        }
+        let peak = (low..=high).max_by(|a, b| acf[*a].total_cmp(&acf[*b])).unwrap_or(center);
+        estimates.push(parabolic_peak(acf, peak) / multiple as f64);
+    }
+
+    // Trust the longer multiples more since their error is divided down
+    let (weighted_sum, weight_total) = estimates
+        .iter()
+        .enumerate()
+        .fold((0.0, 0.0), |(sum, total), (i, estimate)| {
+            let weight = (i + 1) as f64;
+            (sum + estimate * weight, total + weight)
+        });
+    weighted_sum / weight_total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::PI;
+
+    const SAMPLE_RATE: u32 = 44_100;
+
+    // `seconds` of 20 ms, 1 kHz clicks with a fast decay at the given tempo
+    fn click_track(bpm: f64, seconds: f64) -> Vec<f32> {
+        let mut samples = vec![0.0f32; (SAMPLE_RATE as f64 * seconds) as usize];
+        let click_length = SAMPLE_RATE as usize / 50;
+        let mut beat = 0;
+        loop {
+            let start = (beat as f64 * 60.0 / bpm * SAMPLE_RATE as f64).round() as usize;
+            if start >= samples.len() {
+                break;
+            }
+            for (i, sample) in samples[start..].iter_mut().take(click_length).enumerate() {
+                let t = i as f32 / SAMPLE_RATE as f32;
+                *sample = (2.0 * PI * 1000.0 * t).sin() * (-t * 200.0).exp();
+            }
+            beat += 1;
+        }
+        samples
+    }
+
+    fn estimate(bpm: f64) -> f64 {
+        let envelope = onset_envelope(&click_track(bpm, 30.0), SAMPLE_RATE);
+        estimate_bpm_from_envelope(&envelope).unwrap()
+    }
+
+    // Within a beat per minute, so half or double time fails as well
+    fn assert_tempo(bpm: f64) {
+        let detected = estimate(bpm);
+        assert!((detected - bpm).abs() <= 1.0, "expected {} bpm, detected {}", bpm, detected);
+    }
+
+    #[test]
+    fn click_track_at_90_bpm() {
+        assert_tempo(90.0);
+    }
+
+    #[test]
+    fn click_track_at_120_bpm() {
+        assert_tempo(120.0);
+    }
+
+    #[test]
+    fn click_track_at_140_bpm() {
+        assert_tempo(140.0);
+    }
+
+    #[test]
+    fn click_track_at_174_bpm() {
+        assert_tempo(174.0);
+    }
+
+    // Nothing rhythmic to lock on to
+    #[test]
+    fn silence_has_no_tempo() {
+        let envelope = onset_envelope(&vec![0.0; SAMPLE_RATE as usize * 10], SAMPLE_RATE);
+        assert!(estimate_bpm_from_envelope(&envelope).is_none());
+    }
+}
@@ -0,0 +1,138 @@
+use super::decode::DecodedAudio;
+use super::filter::Biquad;
+
+// Samples per bin of the most detailed level. Every following level merges
+// LEVEL_FACTOR bins of the previous one.
+const BASE_SAMPLES_PER_BIN: u32 = 256;
+const LEVEL_FACTOR: usize = 4;
+const LEVEL_COUNT: usize = 4;
+
+// Crossovers for the colored waveform bands
+const LOW_BAND_HZ: f64 = 200.0;
+const HIGH_BAND_HZ: f64 = 4000.0;
+const BAND_Q: f64 = std::f64::consts::FRAC_1_SQRT_2;
+
+#[derive(Clone, Copy, Debug)]
+pub struct WaveformBin {
+    pub min: f32,
+    pub max: f32,
+    pub rms: f32,
+    // RMS of the low, mid and high bands, when bands were requested
+    pub bands: Option<[f32; 3]>,
+}
+
+pub struct WaveformLevel {
+    pub samples_per_bin: u32,
+    pub bins: Vec<WaveformBin>,
+}
+
+pub struct Waveform {
+    pub sample_rate: u32,
+    // Length of the track in samples per channel
+    pub frames: u64,
+    pub has_bands: bool,
+    // Most detailed level first
+    pub levels: Vec<WaveformLevel>,
+}
+
+// Running totals for one bin, so coarser levels can be merged exactly
+#[derive(Clone, Copy)]
+struct BinTotals {
+    min: f32,
+    max: f32,
+    sum_squares: f64,
+    band_sum_squares: [f64; 3],
+    count: usize,
+}
+
+impl BinTotals {
+    fn empty() -> Self {
+        BinTotals {
+            min: f32::MAX,
+            max: f32::MIN,
+            sum_squares: 0.0,
+            band_sum_squares: [0.0; 3],
+            count: 0,
+        }
+    }
+
+    fn merge(&mut self, other: &BinTotals) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum_squares += other.sum_squares;
+        for (total, value) in self.band_sum_squares.iter_mut().zip(other.band_sum_squares) {
+            *total += value;
+        }
+        self.count += other.count;
+    }
+
+    fn finish(&self, has_bands: bool) -> WaveformBin {
+        let count = self.count.max(1) as f64;
+        WaveformBin {
+            min: self.min,
+            max: self.max,
+            rms: (self.sum_squares / count).sqrt() as f32,
+            bands: has_bands.then(|| self.band_sum_squares.map(|sum| (sum / count).sqrt() as f32)),
+        }
+    }
+}
+
+// Builds min/max/RMS overviews of the mono mix at LEVEL_COUNT resolutions.
+pub fn compute_waveform(audio: &DecodedAudio, bands: bool) -> Waveform {
+    let mono = audio.mono();
+    let mut filters = bands.then(|| {
+        [
+            Biquad::low_pass(audio.sample_rate, LOW_BAND_HZ, BAND_Q),
+            // Centered between the crossovers, wide enough to cover the whole mid range
+            Biquad::band_pass(audio.sample_rate, (LOW_BAND_HZ * HIGH_BAND_HZ).sqrt(), 0.25),
+            Biquad::high_pass(audio.sample_rate, HIGH_BAND_HZ, BAND_Q),
+        ]
+    });
+
+    let mut base = Vec::with_capacity(mono.len() / BASE_SAMPLES_PER_BIN as usize + 1);
+    for chunk in mono.chunks(BASE_SAMPLES_PER_BIN as usize) {
+        let mut totals = BinTotals::empty();
+        for sample in chunk {
+            totals.min = totals.min.min(*sample);
+            totals.max = totals.max.max(*sample);
+            totals.sum_squares += (*sample as f64) * (*sample as f64);
+            if let Some(filters) = filters.as_mut() {
+                for (total, filter) in totals.band_sum_squares.iter_mut().zip(filters.iter_mut()) {
+                    let filtered = filter.process(*sample as f64);
+                    *total += filtered * filtered;
+                }
+            }
+        }
+        totals.count = chunk.len();
+        base.push(totals);
+    }
+
+    let mut levels = Vec::with_capacity(LEVEL_COUNT);
+    let mut current = base;
+    let mut samples_per_bin = BASE_SAMPLES_PER_BIN;
+    for _ in 0..LEVEL_COUNT {
+        levels.push(WaveformLevel {
+            samples_per_bin,
+            bins: current.iter().map(|totals| totals.finish(bands)).collect(),
+        });
+
+        current = current
+            .chunks(LEVEL_FACTOR)
+            .map(|group| {
+                let mut merged = group[0];
+                for totals in &group[1..] {
+                    merged.merge(totals);
+                }
+                merged
+            })
+            .collect();
+        samples_per_bin *= LEVEL_FACTOR as u32;
+    }
+
+    Waveform {
+        sample_rate: audio.sample_rate,
+        frames: mono.len() as u64,
+        has_bands: bands,
+        levels,
+    }
+}
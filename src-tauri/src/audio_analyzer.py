@@ -1,9 +1,19 @@
 import os
+import json
 import librosa
 import sys
 import numpy as np
 
 
+def pearson(a, b):
+    a = a - np.mean(a)
+    b = b - np.mean(b)
+    denominator = np.sqrt(np.sum(a * a) * np.sum(b * b))
+    if denominator == 0:
+        return 0.0
+    return float(np.sum(a * b) / denominator)
+
+
 def analyze(file_path):
     try:
         print(f"Starting analysis on {file_path}")
@@ -20,9 +30,6 @@ def analyze(file_path):
         # aggregate chroma features over time by taking the mean
         chroma_mean = np.mean(chroma, axis=1)
 
-        # normalize the chroma vector
-        chroma_norm = chroma_mean / np.linalg.norm(chroma_mean)
-
         # define the Krumhansl-Schmuckler key profiles
         # major
         major_profile = np.array([6.35, 2.23, 3.48, 2.33, 4.38, 4.09,
@@ -31,10 +38,6 @@ def analyze(file_path):
         minor_profile = np.array([6.33, 2.68, 3.52, 5.38, 2.60, 3.53,
                                   2.54, 4.75, 3.98, 2.69, 3.34, 3.17])
 
-        # Normalize the key profiles
-        major_profile = major_profile / np.linalg.norm(major_profile)
-        minor_profile = minor_profile / np.linalg.norm(minor_profile)
-
         # Initialize arrays to hold correlation scores
         correlation_major = []
         correlation_minor = []
@@ -45,9 +48,10 @@ def analyze(file_path):
             major_profile_rotated = np.roll(major_profile, i)
             minor_profile_rotated = np.roll(minor_profile, i)
 
-            # Compute the correlation (dot product) between the chroma vector and the key profiles
-            corr_major = np.dot(chroma_norm, major_profile_rotated)
-            corr_minor = np.dot(chroma_norm, minor_profile_rotated)
+            # Pearson correlation, the same confidence the native analyzer
+            # reports, so the low confidence threshold means the same for both
+            corr_major = pearson(chroma_mean, major_profile_rotated)
+            corr_minor = pearson(chroma_mean, minor_profile_rotated)
 
             correlation_major.append(corr_major)
             correlation_minor.append(corr_minor)
@@ -71,7 +75,23 @@ def analyze(file_path):
         key = f"{key_names[key_index]} {mode}"
         print(f"Detected Key: {key} with confidence {confidence}")
 
-        return key, tempo
+        return key, float(np.atleast_1d(tempo)[0]), float(np.clip(confidence, 0.0, 1.0))
     except Exception as e:
-        print(f"Failed to analyze audio: {e}")
-        print("This error is from the audio_analyzer.py script")
\ No newline at end of file
+        # stderr is what the Rust backend reports as the job's error
+        print(f"Failed to analyze audio: {e}", file=sys.stderr)
+        print("This error is from the audio_analyzer.py script")
+
+
+# Entry point used by the Rust PythonAnalyzer backend. Debug output goes to
+# stdout above, so the JSON result is always printed as the final line.
+if __name__ == "__main__":
+    if len(sys.argv) != 2:
+        print("usage: audio_analyzer.py <file_path>", file=sys.stderr)
+        sys.exit(2)
+
+    result = analyze(sys.argv[1])
+    if result is None:
+        sys.exit(1)
+
+    key, tempo, confidence = result
+    print(json.dumps({"bpm": tempo, "musical_key": key, "key_confidence": confidence}))
@@ -0,0 +1,455 @@
+/*
+ * beat_grid.rs
+ *
+ * Beat grids: where every beat and bar of a track falls. A grid is a tempo map,
+ * a list of markers that each hold their tempo until the next one. The first
+ * marker sits on the first downbeat of the track and bars are counted on from
+ * there, so a track with a steady tempo has a single marker. Beats before the
+ * first marker are extended back to the start of the track at its tempo.
+ *
+ * Grids are built from the beats found by audio_analysis during analysis and
+ * can be nudged or re-anchored by hand afterwards.
+ */
+
+use serde::{Deserialize, Serialize};
+
+// A bar tempo that differs more than this from the running tempo starts a new marker
+const TEMPO_CHANGE_PERCENT: f64 = 2.0;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct TempoMarker {
+    // Seconds into the track
+    pub position: f64,
+    pub bpm: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BeatGrid {
+    // Never empty, ordered by position
+    pub markers: Vec<TempoMarker>,
+    pub beats_per_bar: u32,
+    // Length of the track in seconds, the grid ends there
+    pub duration: f64,
+}
+
+// One beat of a grid
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct GridBeat {
+    pub position: f64,
+    // 1 on a downbeat up to beats_per_bar
+    pub beat_in_bar: u32,
+}
+
+impl BeatGrid {
+    // A grid with one tempo, starting on the downbeat at first_downbeat.
+    pub fn constant(first_downbeat: f64, bpm: f64, beats_per_bar: u32, duration: f64) -> Self {
+        BeatGrid {
+            markers: vec![TempoMarker {
+                position: first_downbeat,
+                bpm,
+            }],
+            beats_per_bar,
+            duration,
+        }
+    }
+
+    // A grid from markers read elsewhere, e.g. from DJ software. The first
+    // marker falls on beat `first_beat_in_bar` of its bar, 1 for a downbeat.
+    pub fn from_markers(
+        mut markers: Vec<TempoMarker>,
+        first_beat_in_bar: u32,
+        beats_per_bar: u32,
+        duration: f64,
+    ) -> Option<Self> {
+        markers.retain(|m| m.position.is_finite() && m.bpm.is_finite() && m.bpm > 0.0);
+        markers.sort_by(|a, b| a.position.total_cmp(&b.position));
+        let first = markers.first_mut()?;
+        let beats_per_bar = beats_per_bar.max(1);
+        first.position -= ((first_beat_in_bar.max(1) - 1) % beats_per_bar) as f64 * 60.0 / first.bpm;
+
+        let mut grid = BeatGrid {
+            markers,
+            beats_per_bar,
+            duration,
+        };
+        grid.move_start_to_first_downbeat();
+        Some(grid)
+    }
+
+    // Fits a tempo map to detected beat positions. `first_downbeat` is the index
+    // in `beats` of the first beat that starts a bar. Consecutive bars with
+    // about the same tempo share one marker, so jitter in the detected beats
+    // doesn't turn into a marker per bar.
+    pub fn from_beats(beats: &[f64], first_downbeat: usize, beats_per_bar: u32, duration: f64) -> Option<Self> {
+        let bar_length = beats_per_bar.max(1) as usize;
+        if beats.len() < 2 || first_downbeat >= beats.len() {
+            return None;
+        }
+
+        let downbeats: Vec<usize> = (first_downbeat..beats.len()).step_by(bar_length).collect();
+        let bar_tempos: Vec<f64> = downbeats
+            .windows(2)
+            .map(|bar| 60.0 * bar_length as f64 / (beats[bar[1]] - beats[bar[0]]))
+            .collect();
+
+        // Median of each bar and its neighbours, one misplaced beat shouldn't split a segment
+        let smoothed: Vec<f64> = (0..bar_tempos.len())
+            .map(|i| {
+                let mut window: Vec<f64> =
+                    bar_tempos[i.saturating_sub(1)..(i + 2).min(bar_tempos.len())].to_vec();
+                window.sort_by(f64::total_cmp);
+                window[window.len() / 2]
+            })
+            .collect();
+
+        // Bars where a new tempo segment starts
+        let mut segment_starts = vec![0];
+        let mut segment_sum = 0.0;
+        let mut segment_bars = 0;
+        for (bar, tempo) in smoothed.iter().enumerate() {
+            if segment_bars > 0 {
+                let mean = segment_sum / segment_bars as f64;
+                if ((tempo - mean) / mean).abs() * 100.0 > TEMPO_CHANGE_PERCENT {
+                    segment_starts.push(bar);
+                    segment_sum = 0.0;
+                    segment_bars = 0;
+                }
+            }
+            segment_sum += tempo;
+            segment_bars += 1;
+        }
+
+        let mut markers = Vec::with_capacity(segment_starts.len());
+        for (i, start_bar) in segment_starts.iter().enumerate() {
+            let start = downbeats[*start_bar];
+            // Up to and including the downbeat the next segment starts on
+            let end = match segment_starts.get(i + 1) {
+                Some(next_bar) => downbeats[*next_bar],
+                None => beats.len() - 1,
+            };
+            // Anything before the first downbeat belongs to the first segment too
+            let fit_start = if i == 0 { 0 } else { start };
+            let (intercept, period) = fit_line(&beats[fit_start..=end])?;
+            markers.push(TempoMarker {
+                position: intercept + period * (start - fit_start) as f64,
+                bpm: 60.0 / period,
+            });
+        }
+
+        let mut grid = BeatGrid {
+            markers,
+            beats_per_bar,
+            duration,
+        };
+        grid.move_start_to_first_downbeat();
+        Some(grid)
+    }
+
+    pub fn first_downbeat(&self) -> f64 {
+        self.markers.first().map_or(0.0, |m| m.position)
+    }
+
+    // Average tempo over the grid, the tempo of the single marker for a steady track
+    pub fn bpm(&self) -> f64 {
+        let Some(first) = self.markers.first() else {
+            return 0.0;
+        };
+        let mut beats = 0.0;
+        for (i, marker) in self.markers.iter().enumerate() {
+            let end = self.markers.get(i + 1).map_or(self.duration, |next| next.position);
+            beats += (end - marker.position).max(0.0) * marker.bpm / 60.0;
+        }
+        let length = self.duration - first.position;
+        if beats > 0.0 && length > 0.0 {
+            60.0 * beats / length
+        } else {
+            first.bpm
+        }
+    }
+
+    pub fn is_constant(&self) -> bool {
+        self.markers.len() <= 1
+    }
+
+    // Where each marker falls in its bar, 1 for a downbeat.
+    pub fn marker_beats_in_bar(&self) -> Vec<u32> {
+        self.marker_indices()
+            .iter()
+            .map(|index| self.beat_in_bar(*index))
+            .collect()
+    }
+
+    // Every beat from the start to the end of the track.
+    pub fn beats(&self) -> Vec<GridBeat> {
+        let Some(first) = self.markers.first() else {
+            return Vec::new();
+        };
+        let mut beats = Vec::new();
+
+        let first_period = 60.0 / first.bpm;
+        if first_period.is_finite() && first_period > 0.0 {
+            let before = (first.position / first_period).floor() as i64;
+            for index in -before..0 {
+                beats.push(GridBeat {
+                    position: first.position + index as f64 * first_period,
+                    beat_in_bar: self.beat_in_bar(index),
+                });
+            }
+        }
+
+        for (i, first_index) in self.marker_indices().into_iter().enumerate() {
+            let marker = self.markers[i];
+            let period = 60.0 / marker.bpm;
+            for step in 0..self.segment_beats(i) {
+                let position = marker.position + step as f64 * period;
+                if position >= 0.0 {
+                    beats.push(GridBeat {
+                        position,
+                        beat_in_bar: self.beat_in_bar(first_index + step),
+                    });
+                }
+            }
+        }
+        beats
+    }
+
+    // Moves the whole grid, positive offsets later into the track.
+    pub fn nudge(&mut self, seconds: f64) {
+        for marker in &mut self.markers {
+            marker.position += seconds;
+        }
+        self.move_start_to_first_downbeat();
+    }
+
+    // Shifts the grid so a beat lands exactly on `position` and makes that beat
+    // a downbeat, optionally with a different number of beats per bar.
+    pub fn anchor(&mut self, position: f64, beats_per_bar: Option<u32>) {
+        self.beats_per_bar = beats_per_bar.unwrap_or(self.beats_per_bar).max(1);
+        let beats = self.beats();
+        let Some(nearest) = beats
+            .iter()
+            .min_by(|a, b| (a.position - position).abs().total_cmp(&(b.position - position).abs()))
+        else {
+            self.markers[0].position = position;
+            return;
+        };
+
+        for marker in &mut self.markers {
+            marker.position += position - nearest.position;
+        }
+        // Beats before the first marker follow its tempo, so moving it back
+        // changes which beats start a bar without moving any of them
+        let beats_per_bar = self.beats_per_bar;
+        let first = &mut self.markers[0];
+        let steps_back = (beats_per_bar + 1 - nearest.beat_in_bar) % beats_per_bar;
+        first.position -= steps_back as f64 * 60.0 / first.bpm;
+        self.move_start_to_first_downbeat();
+    }
+
+    // Multiplies every tempo by `factor`, used when a half or double time
+    // detection is folded into the preferred bpm range.
+    pub fn scale_tempo(&mut self, factor: f64) {
+        if factor.is_finite() && factor > 0.0 {
+            for marker in &mut self.markers {
+                marker.bpm *= factor;
+            }
+        }
+    }
+
+    fn beat_in_bar(&self, index: i64) -> u32 {
+        index.rem_euclid(self.beats_per_bar.max(1) as i64) as u32 + 1
+    }
+
+    // Number of beats laid out from marker `i` before the next marker takes over
+    fn segment_beats(&self, i: usize) -> i64 {
+        let marker = self.markers[i];
+        let period = 60.0 / marker.bpm;
+        if !period.is_finite() || period <= 0.0 {
+            return 0;
+        }
+        // Stop half a beat early so a marker that doesn't quite land on a beat doesn't double it
+        let end = self
+            .markers
+            .get(i + 1)
+            .map_or(self.duration, |next| next.position - period / 2.0);
+        if end < marker.position {
+            return 0;
+        }
+        ((end - marker.position) / period).floor() as i64 + 1
+    }
+
+    // Beat number of every marker, counted from 0 at the first marker
+    fn marker_indices(&self) -> Vec<i64> {
+        let mut index = 0;
+        (0..self.markers.len())
+            .map(|i| {
+                let start = index;
+                index += self.segment_beats(i);
+                start
+            })
+            .collect()
+    }
+
+    // Keeps the first marker on the earliest downbeat inside the track by
+    // moving it whole bars at a time, which leaves every beat where it is.
+    fn move_start_to_first_downbeat(&mut self) {
+        let beats_per_bar = self.beats_per_bar.max(1) as f64;
+        let next_marker = self.markers.get(1).map(|m| m.position);
+        let Some(first) = self.markers.first_mut() else {
+            return;
+        };
+        let bar = 60.0 / first.bpm * beats_per_bar;
+        if !bar.is_finite() || bar <= 0.0 {
+            return;
+        }
+        if first.position < 0.0 {
+            let bars = (-first.position / bar).ceil();
+            let moved = first.position + bars * bar;
+            // Past the next marker the bar count would no longer line up
+            if !matches!(next_marker, Some(next) if moved >= next) {
+                first.position = moved;
+            }
+        } else {
+            first.position -= (first.position / bar).floor() * bar;
+        }
+    }
+}
+
+// Least squares line through evenly spaced beats: position at the first beat
+// and the period in seconds.
+fn fit_line(beats: &[f64]) -> Option<(f64, f64)> {
+    let count = beats.len() as f64;
+    if beats.len() < 2 {
+        return None;
+    }
+    let mean_index = (count - 1.0) / 2.0;
+    let mean_position = beats.iter().sum::<f64>() / count;
+    let (covariance, variance) = beats.iter().enumerate().fold((0.0, 0.0), |(cov, var), (i, p)| {
+        let di = i as f64 - mean_index;
+        (cov + di * (p - mean_position), var + di * di)
+    });
+    let period = covariance / variance;
+    if !period.is_finite() || period <= 0.0 {
+        return None;
+    }
+    Some((mean_position - period * mean_index, period))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `count` beats `60 / bpm` apart starting at `start`
+    fn steady_beats(start: f64, bpm: f64, count: usize) -> Vec<f64> {
+        (0..count).map(|i| start + i as f64 * 60.0 / bpm).collect()
+    }
+
+    fn assert_close(actual: f64, expected: f64, what: &str) {
+        assert!((actual - expected).abs() < 1e-3, "{}: expected {}, got {}", what, expected, actual);
+    }
+
+    #[test]
+    fn steady_beats_make_one_marker() {
+        // The third beat is the first downbeat
+        let beats = steady_beats(0.25, 120.0, 120);
+        let grid = BeatGrid::from_beats(&beats, 2, 4, 60.0).unwrap();
+
+        assert!(grid.is_constant());
+        assert_close(grid.markers[0].bpm, 120.0, "bpm");
+        assert_close(grid.first_downbeat(), 1.25, "first downbeat");
+        assert_close(grid.bpm(), 120.0, "average bpm");
+    }
+
+    #[test]
+    fn jitter_doesnt_add_markers() {
+        let beats: Vec<f64> = steady_beats(0.5, 128.0, 200)
+            .into_iter()
+            .enumerate()
+            .map(|(i, beat)| beat + [0.004, -0.003, 0.0, 0.005, -0.004][i % 5])
+            .collect();
+        let grid = BeatGrid::from_beats(&beats, 0, 4, 100.0).unwrap();
+
+        assert!(grid.is_constant());
+        assert!((grid.markers[0].bpm - 128.0).abs() < 0.1, "bpm {}", grid.markers[0].bpm);
+    }
+
+    #[test]
+    fn tempo_change_starts_a_marker() {
+        // 16 bars at 120 bpm, then 16 bars at 126 bpm from the downbeat at 32 s
+        let mut beats = steady_beats(0.0, 120.0, 64);
+        beats.extend(steady_beats(32.0, 126.0, 64));
+        let grid = BeatGrid::from_beats(&beats, 0, 4, 64.0).unwrap();
+
+        assert_eq!(grid.markers.len(), 2);
+        assert_close(grid.markers[0].bpm, 120.0, "first tempo");
+        assert_close(grid.markers[1].bpm, 126.0, "second tempo");
+        assert_close(grid.markers[1].position, 32.0, "tempo change");
+        assert_eq!(grid.marker_beats_in_bar(), vec![1, 1]);
+    }
+
+    #[test]
+    fn too_few_beats_make_no_grid() {
+        assert!(BeatGrid::from_beats(&[1.0], 0, 4, 10.0).is_none());
+        assert!(BeatGrid::from_beats(&[1.0, 1.5], 2, 4, 10.0).is_none());
+    }
+
+    #[test]
+    fn nudge_keeps_the_first_marker_on_the_earliest_downbeat() {
+        // Bars are 2 seconds long at 120 bpm in 4/4
+        let mut grid = BeatGrid::constant(0.5, 120.0, 4, 30.0);
+        grid.nudge(0.1);
+        assert_close(grid.first_downbeat(), 0.6, "nudged later");
+
+        // Moving before the start wraps to the next downbeat inside the track
+        grid.nudge(-0.8);
+        assert_close(grid.first_downbeat(), 1.8, "nudged earlier");
+        assert_close(grid.beats()[0].position, 0.3, "first beat");
+    }
+
+    #[test]
+    fn first_marker_moves_whole_bars() {
+        // A marker on beat 3 of its bar at 10 s, the bar started a second earlier
+        let grid = BeatGrid::from_markers(vec![TempoMarker { position: 10.0, bpm: 120.0 }], 3, 4, 30.0).unwrap();
+        assert_close(grid.first_downbeat(), 1.0, "first downbeat");
+        let beat = grid.beats().into_iter().find(|beat| (beat.position - 10.0).abs() < 1e-6).unwrap();
+        assert_eq!(beat.beat_in_bar, 3);
+    }
+
+    #[test]
+    fn anchor_makes_the_nearest_beat_a_downbeat() {
+        let mut grid = BeatGrid::constant(0.0, 120.0, 4, 30.0);
+        // Closest to the beat at 1.5 s, the fourth of the first bar
+        grid.anchor(1.52, None);
+
+        let beats = grid.beats();
+        let anchored = beats.iter().find(|beat| (beat.position - 1.52).abs() < 1e-6).unwrap();
+        assert_eq!(anchored.beat_in_bar, 1);
+        assert_close(grid.first_downbeat(), 1.52, "first downbeat");
+        assert_close(grid.markers[0].bpm, 120.0, "bpm");
+    }
+
+    #[test]
+    fn anchor_can_change_the_meter() {
+        let mut grid = BeatGrid::constant(0.0, 120.0, 4, 30.0);
+        grid.anchor(2.5, Some(3));
+
+        assert_eq!(grid.beats_per_bar, 3);
+        let beats = grid.beats();
+        let bar: Vec<u32> = beats
+            .iter()
+            .filter(|beat| beat.position >= 2.5 - 1e-6)
+            .take(4)
+            .map(|beat| beat.beat_in_bar)
+            .collect();
+        assert_eq!(bar, vec![1, 2, 3, 1]);
+    }
+
+    #[test]
+    fn scale_tempo_doubles_the_beats() {
+        let mut grid = BeatGrid::constant(0.0, 70.0, 4, 60.0);
+        let before = grid.beats().len();
+        grid.scale_tempo(2.0);
+        assert_close(grid.bpm(), 140.0, "bpm");
+        assert_eq!(grid.beats().len(), before * 2 - 1);
+    }
+}
@@ -1,8 +1,10 @@
-use diesel::result::Error as DieselError;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
 use std::error::Error;
 use chrono::Utc;
 use diesel::prelude::*;
+use diesel::sql_types::Text;
 use dotenvy::dotenv;
+use std::collections::{HashMap, HashSet};
 use std::env;
 
 use std::path::Path;
@@ -12,8 +14,22 @@ use symphonia::core::io::MediaSourceStream;
 use symphonia::core::meta::MetadataOptions;
 use symphonia::core::probe::Hint;
 
-use crate::models::{Beat, BeatCollection, NewBeat, NewBeatCollection, BeatChangeset};
+use crate::models::{
+    file_status, job_status, value_source, AnalysisJob, Beat, BeatAnalysisChangeset, BeatChangeset,
+    BeatCollection, BeatComment, BeatFile, BeatFileChangeset, BeatGridRecord, CommentCounts, NewBeatComment, BeatInCollection, CuePoint, CuePointChangeset, NewBeat,
+    BeatFingerprint, FilenameChangeset, FilenameParseSummary,
+    NewBeatCollection, NewBeatInCollection, SetEntryDetails, SetEntryDetailsChangeset,
+    TrackLoudness, cue_kind, HOT_CUE_COUNT,
+};
+use crate::audio_analysis::AudioIdentity;
+use crate::beat_grid::BeatGrid;
+use crate::filename_parser::{FilenameParser, ParsedFilename};
+use crate::musical_key::MusicalKey;
+use crate::paths::comparable_path;
+use crate::tags::{self, EmbeddedTags};
 
+// SQLite's replace(), for comparing stored paths the way comparable_path does
+define_sql_function!(fn replace(text: Text, from: Text, to: Text) -> Text);
 
 pub fn establish_connection() -> SqliteConnection {
     dotenv().ok();
@@ -25,38 +41,121 @@ pub fn establish_connection() -> SqliteConnection {
         .unwrap_or_else(|_| panic!("Error connecting to {}", database_url))
 }
 
+// What add_beat stores from the file itself. Reading it means probing the
+// file, so it's done before the database is locked.
+pub struct FileMetadata {
+    pub duration: Option<i32>,
+    pub file_size: Option<i64>,
+    pub tags: EmbeddedTags,
+    pub filename: ParsedFilename,
+}
+
+impl FileMetadata {
+    // Fails when the file can't be opened as audio.
+    pub fn read(file_path: &str, filename: ParsedFilename) -> Result<Self, Box<dyn Error>> {
+        let tags = tags::read_tags(file_path)?;
+        Ok(FileMetadata {
+            duration: get_duration_from_file_path(file_path).ok(),
+            file_size: std::fs::metadata(file_path).ok().map(|metadata| metadata.len() as i64),
+            tags,
+            filename,
+        })
+    }
+
+    // Only what the file name says, for files whose tags can't be read
+    pub fn from_filename(filename: ParsedFilename) -> Self {
+        FileMetadata {
+            duration: None,
+            file_size: None,
+            tags: EmbeddedTags::default(),
+            filename,
+        }
+    }
+}
+
+// Adds a beat with the metadata embedded in its file. What was parsed from
+// the file name fills in what the tags leave out. cover_art is a reference
+// from ArtworkStore. Fails with a UniqueViolation when the file is already in
+// the library.
 pub fn add_beat(
     conn: &mut SqliteConnection,
-    title: &str,
     file_path: &str,
+    metadata: &FileMetadata,
+    cover_art: Option<&str>,
 ) -> Result<Beat, DieselError> {
     use crate::schema::beats;
 
-    let calculated_duration: Option<i32> = get_duration_from_file_path(&file_path).ok();
+    let FileMetadata {
+        duration,
+        file_size,
+        tags: embedded,
+        filename,
+    } = metadata;
+    let file_stem = Path::new(file_path).file_stem().map(|stem| stem.to_string_lossy());
+    let title = embedded
+        .title
+        .as_deref()
+        .or(filename.title.as_deref())
+        .or(file_stem.as_deref())
+        .unwrap_or("Unknown");
+    // Tags first, the file name only for what they don't have
+    let (bpm, bpm_source) = match (embedded.bpm, filename.bpm) {
+        (Some(bpm), _) => (Some(bpm), Some(value_source::TAG)),
+        (None, Some(bpm)) => (Some(bpm), Some(value_source::FILENAME)),
+        (None, None) => (None, None),
+    };
+    let (musical_key, key_source) = match (&embedded.musical_key, &filename.musical_key) {
+        (Some(key), _) => (Some(key.as_str()), Some(value_source::TAG)),
+        (None, Some(key)) => (Some(key.as_str()), Some(value_source::FILENAME)),
+        (None, None) => (None, None),
+    };
 
     let new_beat = NewBeat {
         title,
         file_path,
-        artist: None,
-        album: None,
-        genre: None,
-        year: None,
-        track_number: None,
-        // get the duration from the file path
-        duration: calculated_duration,
-        composer: None,
-        lyricist: None,
-        cover_art: None,
-        comments: None,
-        bpm: None,
-        musical_key: None,
+        artist: embedded.artist.as_deref().or(filename.artist.as_deref()),
+        album: embedded.album.as_deref(),
+        genre: embedded.genre.as_deref(),
+        year: embedded.year,
+        track_number: embedded.track_number,
+        duration: *duration,
+        composer: embedded.composer.as_deref(),
+        lyricist: embedded.lyricist.as_deref(),
+        cover_art,
+        comments: embedded.comments.as_deref(),
+        bpm,
+        musical_key,
+        bpm_source,
+        key_source,
+        version: filename.version.as_deref(),
         date_created: Utc::now().naive_utc(),
+        analysis_status: job_status::PENDING,
+        file_size: *file_size,
     };
 
-    diesel::insert_into(beats::table)
-        .values(&new_beat)
-        .returning(Beat::as_returning())
-        .get_result(conn)
+    // Checked in the same transaction as the insert, so two adds of one file can't both get in
+    conn.transaction(|conn| {
+        if let Some(id) = find_beat_by_path(conn, file_path)? {
+            return Err(DieselError::DatabaseError(
+                DatabaseErrorKind::UniqueViolation,
+                Box::new(format!("{} is already in the library as beat {}", file_path, id)),
+            ));
+        }
+        diesel::insert_into(beats::table)
+            .values(&new_beat)
+            .returning(Beat::as_returning())
+            .get_result(conn)
+    })
+}
+
+// The beat stored under file_path, however its slashes were written
+fn find_beat_by_path(conn: &mut SqliteConnection, file_path: &str) -> Result<Option<i32>, DieselError> {
+    use crate::schema::beats;
+    beats::table
+        .filter(replace(beats::file_path, "\\", "/").eq(comparable_path(file_path)))
+        .select(beats::id)
+        .first(conn)
+        .optional()
 }
 
 fn get_duration_from_file_path(file_path: &str) -> Result<i32, Box<dyn Error>> {
@@ -98,18 +197,66 @@ fn get_duration_from_file_path(file_path: &str) -> Result<i32, Box<dyn Error>> {
 
 pub fn delete_beat(conn: &mut SqliteConnection, id: i32) -> Result<(), DieselError> {
     use crate::schema::beats;
-    diesel::delete(beats::table.find(id))
-        .execute(conn)
-        .map(|_| ())
+    use crate::schema::set_beat;
+
+    conn.transaction(|conn| {
+        let collection_ids: Vec<i32> = set_beat::table
+            .filter(set_beat::beat_id.eq(id))
+            .select(set_beat::beat_collection_id)
+            .distinct()
+            .load(conn)?;
+
+        diesel::delete(beats::table.find(id)).execute(conn)?;
+
+        // The cascade leaves gaps in the running order of every set the beat was in
+        for collection_id in collection_ids {
+            compact_set_positions(conn, collection_id)?;
+        }
+        Ok(())
+    })
 }
 
-pub fn update_beat(conn: &mut SqliteConnection, beat: BeatChangeset) -> Result<(), DieselError> {
+// Saves an edit from the frontend. clear_key removes the key along with where
+// it came from and how confident analysis was, since a None musical_key in
+// the changeset leaves the stored one alone.
+pub fn update_beat(conn: &mut SqliteConnection, beat: BeatChangeset, clear_key: bool) -> Result<(), DieselError> {
     use crate::schema::beats::dsl::*;
 
-    diesel::update(beats.find(beat.id))
-        .set(&beat)
-        .execute(conn)
-        .map(|_| ())
+    conn.transaction(|conn| {
+        if clear_key {
+            diesel::update(beats.find(beat.id))
+                .set((
+                    musical_key.eq(None::<String>),
+                    key_source.eq(None::<String>),
+                    key_confidence.eq(None::<f64>),
+                ))
+                .execute(conn)?;
+        }
+
+        let (current_bpm, current_key): (Option<f64>, Option<String>) = beats
+            .find(beat.id)
+            .select((bpm, musical_key))
+            .first(conn)?;
+
+        // The frontend sends the whole edit form back, so only values that actually
+        // changed count as manual edits and get protected from re-analysis
+        if beat.bpm.is_some() && beat.bpm != current_bpm {
+            diesel::update(beats.find(beat.id))
+                .set(bpm_source.eq(value_source::MANUAL))
+                .execute(conn)?;
+        }
+        if beat.musical_key.is_some() && beat.musical_key != current_key {
+            // A key set by hand is certain, analysis confidence no longer applies
+            diesel::update(beats.find(beat.id))
+                .set((key_source.eq(value_source::MANUAL), key_confidence.eq(None::<f64>)))
+                .execute(conn)?;
+        }
+
+        diesel::update(beats.find(beat.id))
+            .set(&beat)
+            .execute(conn)
+            .map(|_| ())
+    })
 }
 
 
@@ -151,16 +298,416 @@ pub fn add_beat_to_collection(
     collection_id: i32,
     beat_id: i32,
 ) -> Result<(), DieselError> {
+    insert_beat_in_collection(conn, collection_id, beat_id, None).map(|_| ())
+}
+
+// Inserts a beat into a set at `index`, or at the end when None. Entries at and
+// after that index move one position later.
+pub fn insert_beat_in_collection(
+    conn: &mut SqliteConnection,
+    collection_id: i32,
+    beat_id: i32,
+    index: Option<i32>,
+) -> Result<BeatInCollection, DieselError> {
     use crate::schema::set_beat;
-    diesel::insert_into(set_beat::table)
-        .values((
-            set_beat::dsl::beat_id.eq(beat_id),
-            set_beat::dsl::beat_collection_id.eq(collection_id),
-        ))
+
+    conn.transaction(|conn| {
+        let length = count_set_entries(conn, collection_id)?;
+        let position = index.map_or(length, |index| index.clamp(0, length));
+
+        diesel::update(
+            set_beat::table
+                .filter(set_beat::beat_collection_id.eq(collection_id))
+                .filter(set_beat::position.ge(position)),
+        )
+        .set(set_beat::position.eq(set_beat::position + 1))
+        .execute(conn)?;
+
+        diesel::insert_into(set_beat::table)
+            .values(NewBeatInCollection {
+                beat_id: &beat_id,
+                beat_collection_id: &collection_id,
+                position: &position,
+            })
+            .returning(BeatInCollection::as_returning())
+            .get_result(conn)
+    })
+}
+
+// Moves a set entry to `index`, shifting the entries in between.
+pub fn move_set_entry(
+    conn: &mut SqliteConnection,
+    entry_id: i32,
+    index: i32,
+) -> Result<BeatInCollection, DieselError> {
+    use crate::schema::set_beat;
+
+    conn.transaction(|conn| {
+        let entry = set_beat::table
+            .find(entry_id)
+            .select(BeatInCollection::as_select())
+            .first(conn)?;
+        let last = count_set_entries(conn, entry.beat_collection_id)? - 1;
+        let target = index.clamp(0, last);
+
+        if target > entry.position {
+            diesel::update(
+                set_beat::table
+                    .filter(set_beat::beat_collection_id.eq(entry.beat_collection_id))
+                    .filter(set_beat::position.gt(entry.position))
+                    .filter(set_beat::position.le(target)),
+            )
+            .set(set_beat::position.eq(set_beat::position - 1))
+            .execute(conn)?;
+        } else if target < entry.position {
+            diesel::update(
+                set_beat::table
+                    .filter(set_beat::beat_collection_id.eq(entry.beat_collection_id))
+                    .filter(set_beat::position.ge(target))
+                    .filter(set_beat::position.lt(entry.position)),
+            )
+            .set(set_beat::position.eq(set_beat::position + 1))
+            .execute(conn)?;
+        }
+
+        diesel::update(set_beat::table.find(entry_id))
+            .set(set_beat::position.eq(target))
+            .returning(BeatInCollection::as_returning())
+            .get_result(conn)
+    })
+}
+
+// Removes one occurrence of a beat from a set and closes the gap it leaves.
+pub fn remove_set_entry(conn: &mut SqliteConnection, entry_id: i32) -> Result<(), DieselError> {
+    use crate::schema::set_beat;
+
+    conn.transaction(|conn| {
+        let entry = set_beat::table
+            .find(entry_id)
+            .select(BeatInCollection::as_select())
+            .first(conn)?;
+        diesel::delete(set_beat::table.find(entry_id)).execute(conn)?;
+
+        diesel::update(
+            set_beat::table
+                .filter(set_beat::beat_collection_id.eq(entry.beat_collection_id))
+                .filter(set_beat::position.gt(entry.position)),
+        )
+        .set(set_beat::position.eq(set_beat::position - 1))
+        .execute(conn)
+        .map(|_| ())
+    })
+}
+
+// Saves the running order of a set. entry_ids must contain every entry in the
+// set exactly once.
+pub fn reorder_set(
+    conn: &mut SqliteConnection,
+    collection_id: i32,
+    entry_ids: &[i32],
+) -> Result<(), DieselError> {
+    use crate::schema::set_beat;
+
+    conn.transaction(|conn| {
+        let mut current: Vec<i32> = set_beat::table
+            .filter(set_beat::beat_collection_id.eq(collection_id))
+            .select(set_beat::id)
+            .load(conn)?;
+        let mut ordered = entry_ids.to_vec();
+        current.sort_unstable();
+        ordered.sort_unstable();
+        if current != ordered {
+            return Err(DieselError::QueryBuilderError(
+                "Set order must contain every entry in the set exactly once".into(),
+            ));
+        }
+
+        for (position, entry_id) in entry_ids.iter().enumerate() {
+            diesel::update(set_beat::table.find(*entry_id))
+                .set(set_beat::position.eq(position as i32))
+                .execute(conn)?;
+        }
+        Ok(())
+    })
+}
+
+pub fn get_set_entry_details(
+    conn: &mut SqliteConnection,
+    entry_id: i32,
+) -> Result<Option<SetEntryDetails>, DieselError> {
+    use crate::schema::set_entry_details;
+    set_entry_details::table
+        .find(entry_id)
+        .select(SetEntryDetails::as_select())
+        .first(conn)
+        .optional()
+}
+
+// Creates or replaces the performance plan of a set entry.
+pub fn save_set_entry_details(
+    conn: &mut SqliteConnection,
+    details: SetEntryDetailsChangeset,
+) -> Result<SetEntryDetails, DieselError> {
+    use crate::schema::set_entry_details;
+    let now = Utc::now().naive_utc();
+    diesel::insert_into(set_entry_details::table)
+        .values((&details, set_entry_details::date_updated.eq(now)))
+        .on_conflict(set_entry_details::set_beat_id)
+        .do_update()
+        .set((&details, set_entry_details::date_updated.eq(now)))
+        .returning(SetEntryDetails::as_returning())
+        .get_result(conn)
+}
+
+pub fn delete_set_entry_details(conn: &mut SqliteConnection, entry_id: i32) -> Result<(), DieselError> {
+    use crate::schema::set_entry_details;
+    diesel::delete(set_entry_details::table.find(entry_id))
         .execute(conn)
         .map(|_| ())
 }
 
+pub fn get_beat_grid(conn: &mut SqliteConnection, beat_id: i32) -> Result<Option<BeatGridRecord>, DieselError> {
+    use crate::schema::beat_grids;
+    beat_grids::table
+        .find(beat_id)
+        .select(BeatGridRecord::as_select())
+        .first(conn)
+        .optional()
+}
+
+// Creates or replaces the beat grid of a beat.
+pub fn save_beat_grid(conn: &mut SqliteConnection, record: &BeatGridRecord) -> Result<BeatGridRecord, DieselError> {
+    use crate::schema::beat_grids;
+    diesel::insert_into(beat_grids::table)
+        .values(record)
+        .on_conflict(beat_grids::beat_id)
+        .do_update()
+        .set(record)
+        .returning(BeatGridRecord::as_returning())
+        .get_result(conn)
+}
+
+pub fn get_cue_points(conn: &mut SqliteConnection, beat_id: i32) -> Result<Vec<CuePoint>, DieselError> {
+    use crate::schema::cue_points;
+    cue_points::table
+        .filter(cue_points::beat_id.eq(beat_id))
+        .order((cue_points::position_ms, cue_points::id))
+        .select(CuePoint::as_select())
+        .load(conn)
+}
+
+// Cue points grouped by beat, for the given beats or for every beat when None.
+pub fn get_cue_points_by_beat(
+    conn: &mut SqliteConnection,
+    beat_ids: Option<&[i32]>,
+) -> Result<HashMap<i32, Vec<CuePoint>>, DieselError> {
+    use crate::schema::cue_points;
+    let mut query = cue_points::table
+        .order((cue_points::beat_id, cue_points::position_ms, cue_points::id))
+        .select(CuePoint::as_select())
+        .into_boxed();
+    if let Some(beat_ids) = beat_ids {
+        query = query.filter(cue_points::beat_id.eq_any(beat_ids));
+    }
+
+    let mut grouped: HashMap<i32, Vec<CuePoint>> = HashMap::new();
+    for cue in query.load(conn)? {
+        grouped.entry(cue.beat_id).or_default().push(cue);
+    }
+    Ok(grouped)
+}
+
+// Adds a cue point. Hot cues without a pad get the first free one.
+pub fn add_cue_point(conn: &mut SqliteConnection, mut cue: CuePointChangeset) -> Result<CuePoint, DieselError> {
+    use crate::schema::cue_points;
+    conn.transaction(|conn| {
+        if cue.kind == cue_kind::HOT {
+            cue.hot_cue_index = Some(match cue.hot_cue_index {
+                Some(index) => {
+                    check_hot_cue_free(conn, cue.beat_id, index, None)?;
+                    index
+                }
+                None => first_free_hot_cue(conn, cue.beat_id)?,
+            });
+        }
+        diesel::insert_into(cue_points::table)
+            .values(&cue)
+            .returning(CuePoint::as_returning())
+            .get_result(conn)
+    })
+}
+
+pub fn update_cue_point(
+    conn: &mut SqliteConnection,
+    id: i32,
+    mut cue: CuePointChangeset,
+) -> Result<CuePoint, DieselError> {
+    use crate::schema::cue_points;
+    conn.transaction(|conn| {
+        if cue.kind == cue_kind::HOT {
+            cue.hot_cue_index = Some(match cue.hot_cue_index {
+                Some(index) => {
+                    check_hot_cue_free(conn, cue.beat_id, index, Some(id))?;
+                    index
+                }
+                None => first_free_hot_cue(conn, cue.beat_id)?,
+            });
+        }
+        // A cue point can't be moved to another beat
+        diesel::update(
+            cue_points::table
+                .filter(cue_points::id.eq(id))
+                .filter(cue_points::beat_id.eq(cue.beat_id)),
+        )
+        .set(&cue)
+        .returning(CuePoint::as_returning())
+        .get_result(conn)
+    })
+}
+
+pub fn delete_cue_point(conn: &mut SqliteConnection, id: i32) -> Result<(), DieselError> {
+    use crate::schema::cue_points;
+    diesel::delete(cue_points::table.find(id))
+        .execute(conn)
+        .map(|_| ())
+}
+
+// Swaps every cue point of a beat for `cues`, e.g. the ones read from DJ software.
+pub fn replace_cue_points(
+    conn: &mut SqliteConnection,
+    beat_id: i32,
+    cues: &[CuePointChangeset],
+) -> Result<Vec<CuePoint>, DieselError> {
+    use crate::schema::cue_points;
+    conn.transaction(|conn| {
+        diesel::delete(cue_points::table.filter(cue_points::beat_id.eq(beat_id))).execute(conn)?;
+        for cue in cues {
+            add_cue_point(conn, CuePointChangeset { beat_id, ..cue.clone() })?;
+        }
+        get_cue_points(conn, beat_id)
+    })
+}
+
+fn check_hot_cue_free(
+    conn: &mut SqliteConnection,
+    beat_id: i32,
+    index: i32,
+    except_id: Option<i32>,
+) -> Result<(), DieselError> {
+    use crate::schema::cue_points;
+    if !(0..HOT_CUE_COUNT).contains(&index) {
+        return Err(DieselError::QueryBuilderError(
+            format!("Hot cue pads are numbered 0 to {}", HOT_CUE_COUNT - 1).into(),
+        ));
+    }
+    let taken: i64 = cue_points::table
+        .filter(cue_points::beat_id.eq(beat_id))
+        .filter(cue_points::hot_cue_index.eq(index))
+        .filter(cue_points::id.ne(except_id.unwrap_or(-1)))
+        .count()
+        .get_result(conn)?;
+    if taken > 0 {
+        return Err(DieselError::QueryBuilderError(
+            format!("Hot cue {} is already taken", index).into(),
+        ));
+    }
+    Ok(())
+}
+
+fn first_free_hot_cue(conn: &mut SqliteConnection, beat_id: i32) -> Result<i32, DieselError> {
+    use crate::schema::cue_points;
+    let taken: Vec<Option<i32>> = cue_points::table
+        .filter(cue_points::beat_id.eq(beat_id))
+        .filter(cue_points::hot_cue_index.is_not_null())
+        .select(cue_points::hot_cue_index)
+        .load(conn)?;
+    (0..HOT_CUE_COUNT)
+        .find(|index| !taken.contains(&Some(*index)))
+        .ok_or_else(|| DieselError::QueryBuilderError("Every hot cue pad is taken".into()))
+}
+
+pub fn add_beat_comment(conn: &mut SqliteConnection, comment: &NewBeatComment) -> Result<BeatComment, DieselError> {
+    use crate::schema::beat_comments;
+    diesel::insert_into(beat_comments::table)
+        .values(comment)
+        .returning(BeatComment::as_returning())
+        .get_result(conn)
+}
+
+// Comments on a beat in track order, leaving out resolved ones unless asked for.
+pub fn get_beat_comments(
+    conn: &mut SqliteConnection,
+    beat_id: i32,
+    include_resolved: bool,
+) -> Result<Vec<BeatComment>, DieselError> {
+    use crate::schema::beat_comments;
+    let mut query = beat_comments::table
+        .filter(beat_comments::beat_id.eq(beat_id))
+        .order((beat_comments::timestamp_ms, beat_comments::id))
+        .select(BeatComment::as_select())
+        .into_boxed();
+    if !include_resolved {
+        query = query.filter(beat_comments::resolved.eq(false));
+    }
+    query.load(conn)
+}
+
+// Marks a comment as resolved, or opens it again.
+pub fn resolve_beat_comment(
+    conn: &mut SqliteConnection,
+    id: i32,
+    resolved: bool,
+) -> Result<BeatComment, DieselError> {
+    use crate::schema::beat_comments;
+    diesel::update(beat_comments::table.find(id))
+        .set(beat_comments::resolved.eq(resolved))
+        .returning(BeatComment::as_returning())
+        .get_result(conn)
+}
+
+// Number of comments on every beat that has any.
+pub fn count_beat_comments(conn: &mut SqliteConnection) -> Result<HashMap<i32, CommentCounts>, DieselError> {
+    use crate::schema::beat_comments;
+    use diesel::dsl::count_star;
+
+    let rows: Vec<(i32, bool, i64)> = beat_comments::table
+        .group_by((beat_comments::beat_id, beat_comments::resolved))
+        .select((beat_comments::beat_id, beat_comments::resolved, count_star()))
+        .load(conn)?;
+
+    let mut counts: HashMap<i32, CommentCounts> = HashMap::new();
+    for (beat_id, resolved, count) in rows {
+        let entry = counts.entry(beat_id).or_default();
+        entry.total += count;
+        if !resolved {
+            entry.unresolved += count;
+        }
+    }
+    Ok(counts)
+}
+
+// Renumbers positions from 0 without changing the order, e.g. after entries
+// were removed by a cascading delete.
+fn compact_set_positions(conn: &mut SqliteConnection, collection_id: i32) -> Result<(), DieselError> {
+    use crate::schema::set_beat;
+
+    let entry_ids: Vec<i32> = set_beat::table
+        .filter(set_beat::beat_collection_id.eq(collection_id))
+        .order((set_beat::position, set_beat::id))
+        .select(set_beat::id)
+        .load(conn)?;
+    reorder_set(conn, collection_id, &entry_ids)
+}
+
+fn count_set_entries(conn: &mut SqliteConnection, collection_id: i32) -> Result<i32, DieselError> {
+    use crate::schema::set_beat;
+    set_beat::table
+        .filter(set_beat::beat_collection_id.eq(collection_id))
+        .count()
+        .get_result::<i64>(conn)
+        .map(|count| count as i32)
+}
+
 pub fn get_beat_collection(
     conn: &mut SqliteConnection,
     id: i32,
@@ -180,34 +727,755 @@ pub fn get_beat_collection(
         .first::<BeatCollection>(conn)
 }
 
+// Loudness of every entry in a set, repeated beats included.
+pub fn get_collection_track_loudness(
+    conn: &mut SqliteConnection,
+    collection_id: i32,
+) -> Result<Vec<TrackLoudness>, DieselError> {
+    use crate::schema::beats;
+    use crate::schema::set_beat;
+    set_beat::table
+        .filter(set_beat::beat_collection_id.eq(collection_id))
+        .inner_join(beats::table)
+        .select(TrackLoudness::as_select())
+        .load(conn)
+}
+
 pub fn get_beats_in_collection(
     conn: &mut SqliteConnection,
     collection_id: i32,
-) -> Result<Vec<Beat>, diesel::result::Error> {
+) -> Result<Vec<(BeatInCollection, Beat, Option<SetEntryDetails>)>, diesel::result::Error> {
     use crate::schema::beats;
     use crate::schema::set_beat;
+    use crate::schema::set_entry_details;
     set_beat::table
         .filter(set_beat::dsl::beat_collection_id.eq(collection_id))
         .inner_join(beats::table)
+        .left_join(set_entry_details::table)
+        .order((set_beat::position, set_beat::id))
         .select((
-            beats::dsl::id,
-            beats::dsl::title,
-            beats::dsl::artist.nullable(),
-            beats::dsl::album.nullable(),
-            beats::dsl::genre.nullable(),
-            beats::dsl::year.nullable(),
-            beats::dsl::track_number.nullable(),
-            beats::dsl::duration.nullable(),
-            beats::dsl::composer.nullable(),
-            beats::dsl::lyricist.nullable(),
-            beats::dsl::cover_art.nullable(),
-            beats::dsl::comments.nullable(),
-            beats::dsl::file_path,
-            beats::dsl::bpm.nullable(),
-            beats::dsl::musical_key.nullable(),
-            beats::dsl::date_created,
+            BeatInCollection::as_select(),
+            Beat::as_select(),
+            Option::<SetEntryDetails>::as_select(),
+        ))
+        .load::<(BeatInCollection, Beat, Option<SetEntryDetails>)>(conn)
+}
+
+pub fn get_beat(conn: &mut SqliteConnection, id: i32) -> Result<Beat, DieselError> {
+    use crate::schema::beats;
+    beats::table.find(id).select(Beat::as_select()).first(conn)
+}
+
+// Beats with their beat grids, in the order of `beat_ids`. Ids that don't exist are skipped.
+pub fn get_beats_with_grids(
+    conn: &mut SqliteConnection,
+    beat_ids: &[i32],
+) -> Result<Vec<(Beat, Option<BeatGridRecord>)>, DieselError> {
+    use crate::schema::{beat_grids, beats};
+    let mut rows: Vec<(Beat, Option<BeatGridRecord>)> = beats::table
+        .left_join(beat_grids::table)
+        .filter(beats::id.eq_any(beat_ids))
+        .select((Beat::as_select(), Option::<BeatGridRecord>::as_select()))
+        .load(conn)?;
+    rows.sort_by_key(|(beat, _)| beat_ids.iter().position(|id| *id == beat.id));
+    Ok(rows)
+}
+
+// Id and file path of every beat, for matching files from elsewhere to beats.
+pub fn get_beat_file_paths(conn: &mut SqliteConnection) -> Result<Vec<(i32, String)>, DieselError> {
+    use crate::schema::beats;
+    beats::table.select((beats::id, beats::file_path)).load(conn)
+}
+
+// Beats among beat_ids that have no artwork yet.
+pub fn get_beats_without_cover_art(conn: &mut SqliteConnection, beat_ids: &[i32]) -> Result<Vec<Beat>, DieselError> {
+    use crate::schema::beats;
+    beats::table
+        .filter(beats::id.eq_any(beat_ids))
+        .filter(beats::cover_art.is_null())
+        .select(Beat::as_select())
+        .load(conn)
+}
+
+// Parses the file names of the given beats, or of every beat when None, and
+// fills in what was found. Bpm and key only replace analyzed values or ones
+// parsed before, title and artist only the file name or nothing the beat
+// was added with, so tags and edits made by hand are kept.
+pub fn parse_filenames(
+    conn: &mut SqliteConnection,
+    beat_ids: Option<&[i32]>,
+    parser: &FilenameParser,
+) -> Result<FilenameParseSummary, DieselError> {
+    use crate::schema::beats;
+
+    conn.transaction(|conn| {
+        let mut query = beats::table.select(Beat::as_select()).into_boxed();
+        if let Some(beat_ids) = beat_ids {
+            query = query.filter(beats::id.eq_any(beat_ids));
+        }
+        let all_beats = query.load(conn)?;
+
+        let mut summary = FilenameParseSummary::default();
+        for beat in all_beats {
+            summary.beats += 1;
+            let parsed = parser.parse_path(&beat.file_path);
+            let replaceable =
+                |source: Option<&str>| matches!(source, None | Some(value_source::ANALYSIS | value_source::FILENAME));
+            let file_stem = Path::new(&beat.file_path).file_stem().map(|stem| stem.to_string_lossy().into_owned());
+
+            let changes = FilenameChangeset {
+                title: parsed
+                    .title
+                    .filter(|title| Some(&beat.title) == file_stem.as_ref() && *title != beat.title),
+                artist: parsed.artist.filter(|_| beat.artist.is_none()),
+                bpm: parsed
+                    .bpm
+                    .filter(|bpm| replaceable(beat.bpm_source.as_deref()) && Some(*bpm) != beat.bpm),
+                bpm_source: None,
+                musical_key: parsed
+                    .musical_key
+                    .filter(|key| replaceable(beat.key_source.as_deref()) && Some(key) != beat.musical_key.as_ref()),
+                key_source: None,
+                key_confidence: None,
+                version: parsed.version.filter(|version| Some(version) != beat.version.as_ref()),
+            };
+            if changes.is_empty() {
+                continue;
+            }
+            let changes = FilenameChangeset {
+                bpm_source: changes.bpm.map(|_| value_source::FILENAME.to_string()),
+                key_source: changes.musical_key.as_ref().map(|_| value_source::FILENAME.to_string()),
+                key_confidence: changes.musical_key.as_ref().map(|_| None),
+                ..changes
+            };
+            summary.titles += changes.title.is_some() as usize;
+            summary.artists += changes.artist.is_some() as usize;
+            summary.bpms += changes.bpm.is_some() as usize;
+            summary.keys += changes.musical_key.is_some() as usize;
+            summary.versions += changes.version.is_some() as usize;
+
+            diesel::update(beats::table.find(beat.id))
+                .set(&changes)
+                .execute(conn)?;
+        }
+        Ok(summary)
+    })
+}
+
+// Updates a beat after its file changed on disk: the duration, and bpm and
+// key from the new tags unless they were set by hand or imported. The beat
+// counts as present again.
+pub fn refresh_beat_file(
+    conn: &mut SqliteConnection,
+    beat_id: i32,
+    metadata: &FileMetadata,
+) -> Result<(), DieselError> {
+    use crate::schema::beats;
+
+    conn.transaction(|conn| {
+        let beat = get_beat(conn, beat_id)?;
+        let replaceable =
+            |source: Option<&str>| !matches!(source, Some(value_source::MANUAL | value_source::IMPORTED));
+        // Tags holding the stored value are usually ones we wrote back ourselves,
+        // relabeling those as TAG would keep re-analysis from ever updating them.
+        // MP4 only holds whole bpm, so a rounded one counts as the same.
+        let bpm = metadata.tags.bpm.filter(|tag_bpm| {
+            replaceable(beat.bpm_source.as_deref())
+                && !beat
+                    .bpm
+                    .is_some_and(|bpm| (bpm - tag_bpm).abs() < 0.005 || bpm.round() == *tag_bpm)
+        });
+        let musical_key = metadata.tags.musical_key.as_deref().filter(|tag_key| {
+            replaceable(beat.key_source.as_deref()) && beat.musical_key.as_deref() != Some(*tag_key)
+        });
+
+        diesel::update(beats::table.find(beat_id))
+            .set((
+                beats::duration.eq(metadata.duration.or(beat.duration)),
+                beats::file_size.eq(metadata.file_size.or(beat.file_size)),
+                beats::file_status.eq(file_status::OK),
+                bpm.map(|bpm| (beats::bpm.eq(bpm), beats::bpm_source.eq(value_source::TAG))),
+                musical_key.map(|key| {
+                    (
+                        beats::musical_key.eq(key),
+                        beats::key_source.eq(value_source::TAG),
+                        beats::key_confidence.eq(None::<f64>),
+                    )
+                }),
+            ))
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+// Stored audio hashes of the beats among beat_ids, for those that have one.
+pub fn get_audio_hashes(conn: &mut SqliteConnection, beat_ids: &[i32]) -> Result<HashMap<i32, String>, DieselError> {
+    use crate::schema::beats;
+    let rows: Vec<(i32, Option<String>)> = beats::table
+        .filter(beats::id.eq_any(beat_ids))
+        .filter(beats::audio_hash.is_not_null())
+        .select((beats::id, beats::audio_hash))
+        .load(conn)?;
+    Ok(rows.into_iter().filter_map(|(id, hash)| Some((id, hash?))).collect())
+}
+
+// Marks beats as present or missing. Returns the number changed.
+pub fn set_file_status(conn: &mut SqliteConnection, beat_ids: &[i32], status: &str) -> Result<usize, DieselError> {
+    use crate::schema::beats;
+    diesel::update(
+        beats::table
+            .filter(beats::id.eq_any(beat_ids))
+            .filter(beats::file_status.ne(status)),
+    )
+    .set(beats::file_status.eq(status))
+    .execute(conn)
+}
+
+// Where every beat's file is, for checking they are all still there.
+pub fn get_beat_files(conn: &mut SqliteConnection) -> Result<Vec<BeatFile>, DieselError> {
+    use crate::schema::beats;
+    beats::table.select(BeatFile::as_select()).load(conn)
+}
+
+// Applies changes to the files of several beats at once, e.g. after they
+// were moved. Returns the number of beats changed.
+pub fn update_beat_files(
+    conn: &mut SqliteConnection,
+    changes: &[(i32, BeatFileChangeset)],
+) -> Result<usize, DieselError> {
+    use crate::schema::beats;
+
+    conn.transaction(|conn| {
+        let mut changed = 0;
+        for (beat_id, change) in changes {
+            changed += diesel::update(beats::table.find(beat_id)).set(change).execute(conn)?;
+        }
+        Ok(changed)
+    })
+}
+
+// Stores the audio hash and fingerprint of a beat, replacing any older ones.
+pub fn save_audio_identity(
+    conn: &mut SqliteConnection,
+    beat_id: i32,
+    identity: &AudioIdentity,
+) -> Result<(), DieselError> {
+    use crate::schema::{beat_fingerprints, beats};
+
+    conn.transaction(|conn| {
+        diesel::update(beats::table.find(beat_id))
+            .set(beats::audio_hash.eq(&identity.hash))
+            .execute(conn)?;
+        let record = BeatFingerprint {
+            beat_id,
+            fingerprint: identity.fingerprint.clone(),
+            date_updated: Utc::now().naive_utc(),
+        };
+        diesel::insert_into(beat_fingerprints::table)
+            .values(&record)
+            .on_conflict(beat_fingerprints::beat_id)
+            .do_update()
+            .set(&record)
+            .execute(conn)?;
+        Ok(())
+    })
+}
+
+// Id and file path of present beats that have no audio hash yet, e.g. ones
+// analyzed before hashes were stored.
+pub fn get_beats_without_audio_hash(conn: &mut SqliteConnection) -> Result<Vec<(i32, String)>, DieselError> {
+    use crate::schema::beats;
+    beats::table
+        .filter(beats::audio_hash.is_null())
+        .filter(beats::file_status.eq(file_status::OK))
+        .select((beats::id, beats::file_path))
+        .load(conn)
+}
+
+// Every beat with an audio hash, the candidates for finding duplicates.
+pub fn get_beats_with_audio_hash(conn: &mut SqliteConnection) -> Result<Vec<Beat>, DieselError> {
+    use crate::schema::beats;
+    beats::table
+        .filter(beats::audio_hash.is_not_null())
+        .select(Beat::as_select())
+        .load(conn)
+}
+
+// Every stored fingerprint, by beat id.
+pub fn get_fingerprints(conn: &mut SqliteConnection) -> Result<HashMap<i32, Vec<u8>>, DieselError> {
+    use crate::schema::beat_fingerprints;
+    Ok(beat_fingerprints::table
+        .select((beat_fingerprints::beat_id, beat_fingerprints::fingerprint))
+        .load::<(i32, Vec<u8>)>(conn)?
+        .into_iter()
+        .collect())
+}
+
+// Folds duplicates into the beat that is kept. Their set entries and review
+// comments move over, so sets keep their running order, and so do cue points
+// at positions the kept beat has none at. Then the duplicates are deleted from
+// the library along with their beat grids (their files are left alone).
+// Returns the number of set entries moved.
+pub fn merge_beats(conn: &mut SqliteConnection, keep_id: i32, duplicate_ids: &[i32]) -> Result<usize, DieselError> {
+    use crate::schema::{beat_comments, beats, cue_points, set_beat};
+
+    conn.transaction(|conn| {
+        let keep = get_beat(conn, keep_id)?;
+        let duplicates: Vec<Beat> = beats::table
+            .filter(beats::id.eq_any(duplicate_ids))
+            .filter(beats::id.ne(keep_id))
+            .select(Beat::as_select())
+            .load(conn)?;
+        let ids: Vec<i32> = duplicates.iter().map(|beat| beat.id).collect();
+
+        let moved = diesel::update(set_beat::table.filter(set_beat::beat_id.eq_any(&ids)))
+            .set(set_beat::beat_id.eq(keep_id))
+            .execute(conn)?;
+        diesel::update(beat_comments::table.filter(beat_comments::beat_id.eq_any(&ids)))
+            .set(beat_comments::beat_id.eq(keep_id))
+            .execute(conn)?;
+
+        // Cue points move over unless the kept beat already has one at that
+        // position. Hot cues whose pad is taken get a free one, and are dropped
+        // with the duplicate when every pad is.
+        let kept_cues = get_cue_points(conn, keep_id)?;
+        let mut positions: HashSet<i32> = kept_cues.iter().map(|cue| cue.position_ms).collect();
+        let mut pads: HashSet<i32> = kept_cues.iter().filter_map(|cue| cue.hot_cue_index).collect();
+        let duplicate_cues: Vec<CuePoint> = cue_points::table
+            .filter(cue_points::beat_id.eq_any(&ids))
+            .order((cue_points::beat_id, cue_points::position_ms, cue_points::id))
+            .select(CuePoint::as_select())
+            .load(conn)?;
+        for cue in duplicate_cues {
+            if positions.contains(&cue.position_ms) {
+                continue;
+            }
+            let pad = match cue.hot_cue_index {
+                Some(index) if pads.contains(&index) => match (0..HOT_CUE_COUNT).find(|pad| !pads.contains(pad)) {
+                    Some(free) => Some(free),
+                    None => continue,
+                },
+                index => index,
+            };
+            diesel::update(cue_points::table.find(cue.id))
+                .set((cue_points::beat_id.eq(keep_id), cue_points::hot_cue_index.eq(pad)))
+                .execute(conn)?;
+            positions.insert(cue.position_ms);
+            pads.extend(pad);
+        }
+
+        // Artwork the kept beat is missing can come from a duplicate
+        if keep.cover_art.is_none() {
+            if let Some(cover_art) = duplicates.iter().find_map(|beat| beat.cover_art.as_deref()) {
+                set_cover_art(conn, &[keep_id], Some(cover_art))?;
+            }
+        }
+
+        diesel::delete(beats::table.filter(beats::id.eq_any(&ids))).execute(conn)?;
+        Ok(moved)
+    })
+}
+
+// Sets or clears the artwork of several beats. Returns the number changed.
+pub fn set_cover_art(
+    conn: &mut SqliteConnection,
+    beat_ids: &[i32],
+    cover_art: Option<&str>,
+) -> Result<usize, DieselError> {
+    use crate::schema::beats;
+    diesel::update(beats::table.filter(beats::id.eq_any(beat_ids)))
+        .set(beats::cover_art.eq(cover_art))
+        .execute(conn)
+}
+
+// Every beat with a key, the candidates for harmonic matching.
+pub fn get_beats_with_key(conn: &mut SqliteConnection) -> Result<Vec<Beat>, DieselError> {
+    use crate::schema::beats;
+    beats::table
+        .filter(beats::musical_key.is_not_null())
+        .select(Beat::as_select())
+        .load(conn)
+}
+
+// Queues a beat for background analysis and marks it as pending. Forced jobs
+// overwrite values the user edited by hand.
+pub fn enqueue_analysis(
+    conn: &mut SqliteConnection,
+    beat_id: i32,
+    force: bool,
+) -> Result<AnalysisJob, DieselError> {
+    use crate::schema::{analysis_jobs, beats};
+
+    conn.transaction(|conn| {
+        // Only one live job per beat, a newer request replaces the old one
+        diesel::update(
+            analysis_jobs::table
+                .filter(analysis_jobs::beat_id.eq(beat_id))
+                .filter(
+                    analysis_jobs::status
+                        .eq(job_status::PENDING)
+                        .or(analysis_jobs::status.eq(job_status::RUNNING)),
+                ),
+        )
+        .set((
+            analysis_jobs::status.eq(job_status::CANCELED),
+            analysis_jobs::date_updated.eq(Utc::now().naive_utc()),
+        ))
+        .execute(conn)?;
+
+        diesel::update(beats::table.find(beat_id))
+            .set(beats::analysis_status.eq(job_status::PENDING))
+            .execute(conn)?;
+
+        diesel::insert_into(analysis_jobs::table)
+            .values((
+                analysis_jobs::beat_id.eq(beat_id),
+                analysis_jobs::status.eq(job_status::PENDING),
+                analysis_jobs::force.eq(force),
+            ))
+            .returning(AnalysisJob::as_returning())
+            .get_result(conn)
+    })
+}
+
+// Takes the oldest pending job and marks it as running, returning it with the
+// beat to analyze. Returns None when the queue is empty.
+pub fn claim_next_analysis_job(
+    conn: &mut SqliteConnection,
+) -> Result<Option<(AnalysisJob, Beat)>, DieselError> {
+    use crate::schema::{analysis_jobs, beats};
+
+    conn.transaction(|conn| {
+        let next = analysis_jobs::table
+            .inner_join(beats::table)
+            .filter(analysis_jobs::status.eq(job_status::PENDING))
+            .order(analysis_jobs::id.asc())
+            .select((AnalysisJob::as_select(), Beat::as_select()))
+            .first::<(AnalysisJob, Beat)>(conn)
+            .optional()?;
+
+        let Some((job, beat)) = next else {
+            return Ok(None);
+        };
+
+        let now = Utc::now().naive_utc();
+        let job = diesel::update(analysis_jobs::table.find(job.id))
+            .set((
+                analysis_jobs::status.eq(job_status::RUNNING),
+                analysis_jobs::date_updated.eq(now),
+            ))
+            .returning(AnalysisJob::as_returning())
+            .get_result(conn)?;
+        diesel::update(beats::table.find(job.beat_id))
+            .set(beats::analysis_status.eq(job_status::RUNNING))
+            .execute(conn)?;
+
+        Ok(Some((job, beat)))
+    })
+}
+
+// Stores the analysis result for a running job. Returns false without touching
+// the beat if the job was canceled while it was being analyzed.
+// Manually edited, imported, tagged and parsed values are kept unless the job was forced.
+pub fn complete_analysis_job(
+    conn: &mut SqliteConnection,
+    job_id: i32,
+    mut changes: BeatAnalysisChangeset,
+    mut beat_grid: Option<BeatGrid>,
+    identity: Option<&AudioIdentity>,
+) -> Result<bool, DieselError> {
+    use crate::schema::{analysis_jobs, beat_grids, beats};
+
+    conn.transaction(|conn| {
+        let job: AnalysisJob = analysis_jobs::table
+            .find(job_id)
+            .select(AnalysisJob::as_select())
+            .first(conn)?;
+        if job.status != job_status::RUNNING {
+            return Ok(false);
+        }
+
+        let (stored_bpm, bpm_source, key_source): (Option<f64>, Option<String>, Option<String>) = beats::table
+            .find(job.beat_id)
+            .select((beats::bpm, beats::bpm_source, beats::key_source))
+            .first(conn)?;
+        if !job.force && value_source::is_user_provided(bpm_source.as_deref()) {
+            // The grid follows the analyzed bpm, it has to follow the kept one instead
+            if let (Some(grid), Some(stored), Some(analyzed)) = (beat_grid.as_mut(), stored_bpm, changes.bpm) {
+                if analyzed > 0.0 {
+                    grid.scale_tempo(stored / analyzed);
+                }
+            }
+            changes.bpm = None;
+        }
+        if !job.force && value_source::is_user_provided(key_source.as_deref()) {
+            changes.musical_key = None;
+            changes.key_confidence = None;
+        }
+        if changes.bpm.is_some() {
+            changes.bpm_source = Some(value_source::ANALYSIS.to_string());
+        }
+        if changes.musical_key.is_some() {
+            changes.key_source = Some(value_source::ANALYSIS.to_string());
+        }
+
+        if !changes.is_empty() {
+            diesel::update(beats::table.find(job.beat_id))
+                .set(&changes)
+                .execute(conn)?;
+        }
+        // None fields are skipped, so a new key without a confidence would keep the old one's
+        if changes.musical_key.is_some() {
+            diesel::update(beats::table.find(job.beat_id))
+                .set(beats::key_confidence.eq(changes.key_confidence))
+                .execute(conn)?;
+        }
+
+        if let Some(grid) = beat_grid {
+            let grid_source: Option<String> = beat_grids::table
+                .find(job.beat_id)
+                .select(beat_grids::source)
+                .first(conn)
+                .optional()?;
+            if job.force || !value_source::is_user_provided(grid_source.as_deref()) {
+                save_beat_grid(conn, &BeatGridRecord::new(job.beat_id, &grid, value_source::ANALYSIS))?;
+            }
+        }
+        // Measured from the audio itself, there is nothing to protect
+        if let Some(identity) = identity {
+            save_audio_identity(conn, job.beat_id, identity)?;
+        }
+        diesel::update(beats::table.find(job.beat_id))
+            .set(beats::analysis_status.eq(job_status::COMPLETE))
+            .execute(conn)?;
+        diesel::update(analysis_jobs::table.find(job_id))
+            .set((
+                analysis_jobs::status.eq(job_status::COMPLETE),
+                analysis_jobs::date_updated.eq(Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+
+        Ok(true)
+    })
+}
+
+// Records why a running job failed. Canceled jobs are left as canceled.
+pub fn fail_analysis_job(
+    conn: &mut SqliteConnection,
+    job_id: i32,
+    error: &str,
+) -> Result<bool, DieselError> {
+    use crate::schema::{analysis_jobs, beats};
+
+    conn.transaction(|conn| {
+        let job: AnalysisJob = analysis_jobs::table
+            .find(job_id)
+            .select(AnalysisJob::as_select())
+            .first(conn)?;
+        if job.status != job_status::RUNNING {
+            return Ok(false);
+        }
+
+        diesel::update(beats::table.find(job.beat_id))
+            .set(beats::analysis_status.eq(job_status::FAILED))
+            .execute(conn)?;
+        diesel::update(analysis_jobs::table.find(job_id))
+            .set((
+                analysis_jobs::status.eq(job_status::FAILED),
+                analysis_jobs::error.eq(error),
+                analysis_jobs::date_updated.eq(Utc::now().naive_utc()),
+            ))
+            .execute(conn)?;
+
+        Ok(true)
+    })
+}
+
+// Cancels pending and running jobs, for one beat or for the whole queue.
+// Returns the number of jobs canceled.
+pub fn cancel_analysis(conn: &mut SqliteConnection, beat_id: Option<i32>) -> Result<usize, DieselError> {
+    use crate::schema::{analysis_jobs, beats};
+
+    conn.transaction(|conn| {
+        let mut live_jobs = analysis_jobs::table
+            .filter(
+                analysis_jobs::status
+                    .eq(job_status::PENDING)
+                    .or(analysis_jobs::status.eq(job_status::RUNNING)),
+            )
+            .select(analysis_jobs::beat_id)
+            .into_boxed();
+        if let Some(beat_id) = beat_id {
+            live_jobs = live_jobs.filter(analysis_jobs::beat_id.eq(beat_id));
+        }
+        let beat_ids: Vec<i32> = live_jobs.load(conn)?;
+
+        diesel::update(
+            analysis_jobs::table
+                .filter(analysis_jobs::beat_id.eq_any(&beat_ids))
+                .filter(
+                    analysis_jobs::status
+                        .eq(job_status::PENDING)
+                        .or(analysis_jobs::status.eq(job_status::RUNNING)),
+                ),
+        )
+        .set((
+            analysis_jobs::status.eq(job_status::CANCELED),
+            analysis_jobs::date_updated.eq(Utc::now().naive_utc()),
         ))
-        .load::<Beat>(conn)
+        .execute(conn)?;
+
+        diesel::update(beats::table.filter(beats::id.eq_any(&beat_ids)))
+            .set(beats::analysis_status.eq(job_status::CANCELED))
+            .execute(conn)?;
+
+        Ok(beat_ids.len())
+    })
+}
+
+// Jobs left running when the app closed never finished, so put them back in the queue.
+pub fn requeue_interrupted_analysis(conn: &mut SqliteConnection) -> Result<usize, DieselError> {
+    use crate::schema::{analysis_jobs, beats};
+
+    conn.transaction(|conn| {
+        let beat_ids: Vec<i32> = analysis_jobs::table
+            .filter(analysis_jobs::status.eq(job_status::RUNNING))
+            .select(analysis_jobs::beat_id)
+            .load(conn)?;
+
+        diesel::update(analysis_jobs::table.filter(analysis_jobs::status.eq(job_status::RUNNING)))
+            .set(analysis_jobs::status.eq(job_status::PENDING))
+            .execute(conn)?;
+        diesel::update(beats::table.filter(beats::id.eq_any(&beat_ids)))
+            .set(beats::analysis_status.eq(job_status::PENDING))
+            .execute(conn)?;
+
+        Ok(beat_ids.len())
+    })
+}
+
+// Multiplies the bpm of several beats, e.g. by 2.0 or 0.5 to fix half/double
+// tempo detections, along with their beat grids. The result counts as a manual
+// edit, bpm_raw keeps the tempo analysis detected.
+pub fn scale_bpm(conn: &mut SqliteConnection, beat_ids: &[i32], factor: f64) -> Result<usize, DieselError> {
+    use crate::schema::beats;
+
+    conn.transaction(|conn| {
+        let current: Vec<(i32, Option<f64>)> = beats::table
+            .filter(beats::id.eq_any(beat_ids))
+            .filter(beats::bpm.is_not_null())
+            .select((beats::id, beats::bpm))
+            .load(conn)?;
+
+        for (beat_id, bpm) in &current {
+            let Some(bpm) = bpm else { continue };
+            let scaled = (bpm * factor * 100.0).round() / 100.0;
+            diesel::update(beats::table.find(beat_id))
+                .set((beats::bpm.eq(scaled), beats::bpm_source.eq(value_source::MANUAL)))
+                .execute(conn)?;
+
+            // The grid follows, so the beats drawn still match the bpm
+            if let Some(record) = get_beat_grid(conn, *beat_id)? {
+                let mut grid = record.grid();
+                grid.scale_tempo(factor);
+                save_beat_grid(conn, &BeatGridRecord::new(*beat_id, &grid, value_source::MANUAL))?;
+            }
+        }
+        Ok(current.len())
+    })
+}
+
+// Rewrites every stored musical_key in the normalized form, so keys entered
+// before MusicalKey existed ("Fmin", "11A", ...) match new ones. Values that
+// can't be parsed are left alone. Returns the number of rows changed.
+pub fn normalize_musical_keys(conn: &mut SqliteConnection) -> Result<usize, DieselError> {
+    use crate::schema::beats;
+
+    conn.transaction(|conn| {
+        let stored: Vec<(i32, Option<String>)> = beats::table
+            .filter(beats::musical_key.is_not_null())
+            .select((beats::id, beats::musical_key))
+            .load(conn)?;
+
+        let mut changed = 0;
+        for (beat_id, key) in stored {
+            let Some(key) = key else { continue };
+            let Some(parsed) = MusicalKey::parse(&key) else {
+                println!("Leaving unrecognized key {:?} on beat {}", key, beat_id);
+                continue;
+            };
+            let normalized = parsed.to_string();
+            if normalized != key {
+                diesel::update(beats::table.find(beat_id))
+                    .set(beats::musical_key.eq(normalized))
+                    .execute(conn)?;
+                changed += 1;
+            }
+        }
+        Ok(changed)
+    })
+}
+
+// Bits of PRAGMA user_version marking one-time data fixes that already ran.
+// Diesel's migrations don't use user_version, so it's free for this.
+const KEYS_NORMALIZED: i32 = 1;
+
+// Runs normalize_musical_keys the first time a database is opened, keys saved
+// after that are normalized on the way in. Returns the number of rows changed.
+pub fn normalize_musical_keys_once(conn: &mut SqliteConnection) -> Result<usize, DieselError> {
+    use diesel::dsl::sql;
+    use diesel::sql_types::Integer;
+
+    conn.transaction(|conn| {
+        let done: i32 = diesel::select(sql::<Integer>("user_version FROM pragma_user_version")).get_result(conn)?;
+        if done & KEYS_NORMALIZED != 0 {
+            return Ok(0);
+        }
+        let changed = normalize_musical_keys(conn)?;
+        diesel::sql_query(format!("PRAGMA user_version = {}", done | KEYS_NORMALIZED)).execute(conn)?;
+        Ok(changed)
+    })
+}
+
+// Ids of every beat in a collection, for queueing them all at once.
+pub fn get_beat_ids_in_collection(
+    conn: &mut SqliteConnection,
+    collection_id: i32,
+) -> Result<Vec<i32>, DieselError> {
+    use crate::schema::set_beat;
+    set_beat::table
+        .filter(set_beat::beat_collection_id.eq(collection_id))
+        .select(set_beat::beat_id)
+        .distinct()
+        .load(conn)
+}
+
+// Ids of beats that are missing a bpm, key or loudness and aren't already queued.
+pub fn get_beat_ids_missing_analysis(conn: &mut SqliteConnection) -> Result<Vec<i32>, DieselError> {
+    use crate::schema::beats;
+    beats::table
+        .filter(
+            beats::bpm
+                .is_null()
+                .or(beats::musical_key.is_null())
+                .or(beats::integrated_loudness.is_null()),
+        )
+        .filter(beats::analysis_status.ne(job_status::PENDING))
+        .filter(beats::analysis_status.ne(job_status::RUNNING))
+        .select(beats::id)
+        .load(conn)
+}
+
+pub fn count_pending_analysis(conn: &mut SqliteConnection) -> Result<i64, DieselError> {
+    use crate::schema::analysis_jobs;
+    analysis_jobs::table
+        .filter(
+            analysis_jobs::status
+                .eq(job_status::PENDING)
+                .or(analysis_jobs::status.eq(job_status::RUNNING)),
+        )
+        .count()
+        .get_result(conn)
 }
 
 // pub fn get_all_beats(conn: &mut SqliteConnection) -> QueryResult<Vec<Beat>> {
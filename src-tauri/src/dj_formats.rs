@@ -0,0 +1,636 @@
+/*
+ * dj_formats.rs
+ *
+ * Reads and writes library data in the formats DJ software uses, so prep done
+ * here carries over to the decks and prep done there carries over here.
+ *
+ * Formats:
+ * - Rekordbox collection XML: A DJ_PLAYLISTS document with a TRACK per beat,
+ *   a TEMPO element per tempo marker and a POSITION_MARK per cue point.
+ *   Rekordbox imports it from Preferences > Advanced > rekordbox xml and
+ *   exports it from File > Export Collection in xml format.
+ * - Serato BeatGrid: The payload of the "Serato BeatGrid" GEOB tag Serato
+ *   stores in the audio file.
+ * - Serato Markers2: The "Serato Markers2" GEOB tag holding hot cues and
+ *   saved loops. Serato has no memory cues, so those are left out.
+ *
+ * Serato tags are only read from ID3 tags, i.e. MP3, WAV and AIFF files.
+ */
+
+use base64::alphabet;
+use base64::engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig};
+use base64::Engine;
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use std::error::Error;
+use std::path::Path;
+
+use crate::beat_grid::{BeatGrid, TempoMarker};
+use crate::models::{cue_kind, Beat, CuePoint, CuePointChangeset};
+use crate::musical_key::MusicalKey;
+
+// Rekordbox POSITION_MARK types
+const MARK_CUE: &str = "0";
+const MARK_LOOP: &str = "4";
+
+// Serato pads its Markers2 tag to at least this many bytes
+const SERATO_MARKERS_MIN_SIZE: usize = 470;
+const SERATO_BASE64_LINE: usize = 72;
+// The color Serato gives saved loops, it can't be changed there
+const SERATO_LOOP_COLOR: [u8; 3] = [0x27, 0xaa, 0xe1];
+
+// Serato leaves the padding off and doesn't always zero the trailing bits
+const SERATO_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::Indifferent)
+        .with_decode_allow_trailing_bits(true),
+);
+
+// One beat with everything the DJ formats carry
+pub struct DjTrack {
+    pub beat: Beat,
+    pub grid: Option<BeatGrid>,
+    pub cue_points: Vec<CuePoint>,
+}
+
+// A track read from a Rekordbox collection. Cue points have beat_id 0 until
+// the track is matched to a beat.
+pub struct RekordboxTrack {
+    pub file_path: String,
+    pub grid: Option<BeatGrid>,
+    pub cue_points: Vec<CuePointChangeset>,
+}
+
+// What an import from DJ software brought in
+#[derive(Serialize, Default, Debug)]
+pub struct DjImportSummary {
+    pub tracks: usize,
+    pub matched: usize,
+    pub cue_points: usize,
+    pub beat_grids: usize,
+    // Locations of tracks that aren't in the library
+    pub unmatched: Vec<String>,
+    // Cue points that aren't valid here, the rest of their track is still imported
+    pub skipped_cue_points: Vec<SkippedCuePoint>,
+}
+
+// A cue point an import left out, e.g. a loop that ends before it starts
+#[derive(Serialize, Debug)]
+pub struct SkippedCuePoint {
+    pub file_path: String,
+    pub position_ms: i32,
+    pub reason: String,
+}
+
+// Cue points and grid found in a file's Serato tags
+pub struct SeratoTags {
+    pub cue_points: Vec<CuePointChangeset>,
+    pub grid: Option<BeatGrid>,
+}
+
+// Rekordbox collection XML for `tracks`, with their beat grids and cue points.
+pub fn rekordbox_xml(tracks: &[DjTrack]) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<DJ_PLAYLISTS Version=\"1.0.0\">\n");
+    xml.push_str(&format!(
+        "  <PRODUCT Name=\"beatbank\" Version=\"{}\" Company=\"\"/>\n",
+        env!("CARGO_PKG_VERSION")
+    ));
+    xml.push_str(&format!("  <COLLECTION Entries=\"{}\">\n", tracks.len()));
+
+    for DjTrack { beat, grid, cue_points } in tracks {
+        let mut attributes = vec![
+            ("TrackID", beat.id.to_string()),
+            ("Name", beat.title.clone()),
+            ("Artist", beat.artist.clone().unwrap_or_default()),
+            ("Album", beat.album.clone().unwrap_or_default()),
+            ("Genre", beat.genre.clone().unwrap_or_default()),
+            ("Kind", file_kind(&beat.file_path)),
+            ("TotalTime", beat.duration.unwrap_or_default().to_string()),
+            ("Year", beat.year.map(|y| y.to_string()).unwrap_or_default()),
+            ("Comments", beat.comments.clone().unwrap_or_default()),
+            ("Location", file_url(&beat.file_path)),
+        ];
+        if let Some(bpm) = grid.as_ref().map(|g| g.bpm()).or(beat.bpm) {
+            attributes.push(("AverageBpm", format!("{:.2}", bpm)));
+        }
+        if let Some(key) = beat.musical_key.as_deref().and_then(MusicalKey::parse) {
+            attributes.push(("Tonality", key.tag_name()));
+        }
+
+        xml.push_str("    <TRACK");
+        for (name, value) in &attributes {
+            xml.push_str(&format!(" {}=\"{}\"", name, escape_xml(value)));
+        }
+        if grid.is_none() && cue_points.is_empty() {
+            xml.push_str("/>\n");
+            continue;
+        }
+        xml.push_str(">\n");
+
+        if let Some(grid) = grid {
+            xml.push_str(&tempo_elements(grid));
+        }
+        for cue in cue_points {
+            xml.push_str(&position_mark(cue));
+        }
+        xml.push_str("    </TRACK>\n");
+    }
+
+    xml.push_str("  </COLLECTION>\n");
+    xml.push_str("  <PLAYLISTS>\n    <NODE Type=\"0\" Name=\"ROOT\" Count=\"0\"/>\n  </PLAYLISTS>\n");
+    xml.push_str("</DJ_PLAYLISTS>\n");
+    xml
+}
+
+// A TEMPO element per marker, Battito is where the marker falls in its bar.
+fn tempo_elements(grid: &BeatGrid) -> String {
+    grid.markers
+        .iter()
+        .zip(grid.marker_beats_in_bar())
+        .map(|(marker, beat_in_bar)| {
+            format!(
+                "      <TEMPO Inizio=\"{:.3}\" Bpm=\"{:.2}\" Metro=\"{}/4\" Battito=\"{}\"/>\n",
+                marker.position.max(0.0),
+                marker.bpm,
+                grid.beats_per_bar,
+                beat_in_bar
+            )
+        })
+        .collect()
+}
+
+// Memory cues and memory loops have Num -1, hot cues and hot loops the pad
+// number. Only hot cues carry a color.
+fn position_mark(cue: &CuePoint) -> String {
+    let is_loop = cue.loop_length_ms.is_some_and(|length| length > 0);
+    let start = cue.position_ms as f64 / 1000.0;
+    let mut mark = format!(
+        "      <POSITION_MARK Name=\"{}\" Type=\"{}\" Start=\"{:.3}\"",
+        escape_xml(cue.label.as_deref().unwrap_or_default()),
+        if is_loop { MARK_LOOP } else { MARK_CUE },
+        start
+    );
+    if let Some(length) = cue.loop_length_ms.filter(|_| is_loop) {
+        mark.push_str(&format!(" End=\"{:.3}\"", start + length as f64 / 1000.0));
+    }
+    match cue.hot_cue_index.filter(|_| cue.kind == cue_kind::HOT) {
+        Some(index) => {
+            mark.push_str(&format!(" Num=\"{}\"", index));
+            if let Some([red, green, blue]) = cue.color.as_deref().and_then(parse_color) {
+                mark.push_str(&format!(" Red=\"{}\" Green=\"{}\" Blue=\"{}\"", red, green, blue));
+            }
+        }
+        None => mark.push_str(" Num=\"-1\""),
+    }
+    mark.push_str("/>\n");
+    mark
+}
+
+// Every track in a Rekordbox collection XML with its grid and cue points.
+pub fn parse_rekordbox_xml(xml: &str) -> Result<Vec<RekordboxTrack>, Box<dyn Error>> {
+    let document = roxmltree::Document::parse(xml)?;
+    let collection = document
+        .descendants()
+        .find(|node| node.has_tag_name("COLLECTION"))
+        .ok_or("Not a Rekordbox collection, there is no COLLECTION element")?;
+
+    let mut tracks = Vec::new();
+    for track in collection.children().filter(|node| node.has_tag_name("TRACK")) {
+        let Some(location) = track.attribute("Location") else {
+            continue;
+        };
+        let duration = track
+            .attribute("TotalTime")
+            .and_then(|time| time.parse::<f64>().ok())
+            .unwrap_or_default();
+
+        let tempos: Vec<_> = track.children().filter(|node| node.has_tag_name("TEMPO")).collect();
+        let markers = tempos
+            .iter()
+            .filter_map(|tempo| {
+                Some(TempoMarker {
+                    position: tempo.attribute("Inizio")?.parse().ok()?,
+                    bpm: tempo.attribute("Bpm")?.parse().ok()?,
+                })
+            })
+            .collect();
+        let first_tempo = tempos.first();
+        let beats_per_bar = first_tempo
+            .and_then(|tempo| tempo.attribute("Metro"))
+            .and_then(|metro| metro.split('/').next()?.trim().parse().ok())
+            .unwrap_or(4);
+        let first_beat_in_bar = first_tempo
+            .and_then(|tempo| tempo.attribute("Battito")?.parse().ok())
+            .unwrap_or(1);
+
+        let cue_points = track
+            .children()
+            .filter(|node| node.has_tag_name("POSITION_MARK"))
+            .filter_map(|mark| {
+                let start: f64 = mark.attribute("Start")?.parse().ok()?;
+                let end: Option<f64> = mark.attribute("End").and_then(|end| end.parse().ok());
+                let num: i32 = mark.attribute("Num").and_then(|num| num.parse().ok()).unwrap_or(-1);
+                let loop_length_ms = match mark.attribute("Type") {
+                    Some(MARK_LOOP) => end.map(|end| ((end - start) * 1000.0).round() as i32),
+                    Some(MARK_CUE) => None,
+                    // Fade-in, fade-out and load markers have no equivalent here
+                    _ => return None,
+                };
+                let color = ["Red", "Green", "Blue"]
+                    .iter()
+                    .map(|name| mark.attribute(*name)?.parse::<u8>().ok())
+                    .collect::<Option<Vec<u8>>>()
+                    .map(|rgb| format_color([rgb[0], rgb[1], rgb[2]]));
+                let kind = match (num >= 0, loop_length_ms) {
+                    (true, _) => cue_kind::HOT,
+                    (false, Some(_)) => cue_kind::LOOP,
+                    (false, None) => cue_kind::MEMORY,
+                };
+                Some(CuePointChangeset {
+                    beat_id: 0,
+                    kind: kind.to_string(),
+                    position_ms: (start * 1000.0).round() as i32,
+                    loop_length_ms,
+                    hot_cue_index: (num >= 0).then_some(num),
+                    label: mark.attribute("Name").filter(|name| !name.is_empty()).map(String::from),
+                    color,
+                })
+            })
+            .collect();
+
+        tracks.push(RekordboxTrack {
+            file_path: file_path_from_url(location),
+            grid: BeatGrid::from_markers(markers, first_beat_in_bar, beats_per_bar, duration),
+            cue_points,
+        });
+    }
+    Ok(tracks)
+}
+
+// Serato BeatGrid tag payload: a version header, every marker but the last as
+// position and beat count to the next marker, the last one as position and
+// tempo, and a footer byte. Numbers are big endian.
+pub fn serato_beatgrid(grid: &BeatGrid) -> Vec<u8> {
+    let mut payload = vec![0x01, 0x00];
+    payload.extend_from_slice(&(grid.markers.len() as u32).to_be_bytes());
+
+    for (i, marker) in grid.markers.iter().enumerate() {
+        payload.extend_from_slice(&(marker.position.max(0.0) as f32).to_be_bytes());
+        match grid.markers.get(i + 1) {
+            Some(next) => {
+                let beats = ((next.position - marker.position) * marker.bpm / 60.0).round().max(1.0);
+                payload.extend_from_slice(&(beats as u32).to_be_bytes());
+            }
+            None => payload.extend_from_slice(&(marker.bpm as f32).to_be_bytes()),
+        }
+    }
+
+    payload.push(0x00);
+    payload
+}
+
+// Reverse of serato_beatgrid. Serato markers are always downbeats of 4/4 bars.
+pub fn parse_serato_beatgrid(payload: &[u8], duration: f64) -> Option<BeatGrid> {
+    if payload.get(..2)? != [0x01, 0x00] {
+        return None;
+    }
+    let count = u32::from_be_bytes(payload.get(2..6)?.try_into().ok()?) as usize;
+    let entries: Vec<(f32, [u8; 4])> = (0..count)
+        .map(|i| {
+            let entry = payload.get(6 + i * 8..14 + i * 8)?;
+            Some((f32::from_be_bytes(entry[..4].try_into().ok()?), entry[4..].try_into().ok()?))
+        })
+        .collect::<Option<_>>()?;
+
+    let mut markers = Vec::with_capacity(count);
+    for (i, (position, value)) in entries.iter().enumerate() {
+        let bpm = match entries.get(i + 1) {
+            Some((next, _)) => u32::from_be_bytes(*value) as f64 * 60.0 / (next - position) as f64,
+            None => f32::from_be_bytes(*value) as f64,
+        };
+        markers.push(TempoMarker {
+            position: *position as f64,
+            bpm,
+        });
+    }
+    BeatGrid::from_markers(markers, 1, 4, duration)
+}
+
+// Content of the "Serato Markers2" tag: a version header and a base64 encoded
+// list of named entries, each a null terminated name, a big endian u32 length
+// and the entry data. Hot cues are written as CUE entries and loops as LOOP
+// entries, memory cues are skipped.
+pub fn serato_markers(cue_points: &[CuePoint]) -> Vec<u8> {
+    let mut entries = vec![0x01, 0x01];
+    let mut loop_index = 0u8;
+
+    for cue in cue_points {
+        let label = cue.label.as_deref().unwrap_or_default().as_bytes();
+        let start = cue.position_ms.max(0) as u32;
+        let length = cue.loop_length_ms.filter(|length| *length > 0);
+
+        let (name, mut data) = match (cue.kind.as_str(), length) {
+            (cue_kind::HOT, None) => {
+                let Some(index) = cue.hot_cue_index else { continue };
+                let color = cue.color.as_deref().and_then(parse_color).unwrap_or([0xcc, 0x00, 0x00]);
+                let mut data = vec![0x00, index as u8];
+                data.extend_from_slice(&start.to_be_bytes());
+                data.push(0x00);
+                data.extend_from_slice(&color);
+                data.extend_from_slice(&[0x00, 0x00]);
+                ("CUE", data)
+            }
+            (cue_kind::HOT | cue_kind::LOOP, Some(length)) => {
+                let mut data = vec![0x00, loop_index];
+                loop_index += 1;
+                data.extend_from_slice(&start.to_be_bytes());
+                data.extend_from_slice(&(start + length as u32).to_be_bytes());
+                data.extend_from_slice(&[0xff, 0xff, 0xff, 0xff, 0x00]);
+                data.extend_from_slice(&SERATO_LOOP_COLOR);
+                // Not locked
+                data.push(0x00);
+                ("LOOP", data)
+            }
+            _ => continue,
+        };
+        data.extend_from_slice(label);
+        data.push(0x00);
+
+        entries.extend_from_slice(name.as_bytes());
+        entries.push(0x00);
+        entries.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        entries.extend_from_slice(&data);
+    }
+    entries.push(0x00);
+
+    let encoded = SERATO_BASE64.encode(&entries);
+    let mut content = vec![0x01, 0x01];
+    for (i, line) in encoded.as_bytes().chunks(SERATO_BASE64_LINE).enumerate() {
+        if i > 0 {
+            content.push(b'\n');
+        }
+        content.extend_from_slice(line);
+    }
+    if content.len() < SERATO_MARKERS_MIN_SIZE {
+        content.resize(SERATO_MARKERS_MIN_SIZE, 0x00);
+    }
+    content
+}
+
+// Reverse of serato_markers. Cue points have beat_id 0.
+pub fn parse_serato_markers(content: &[u8]) -> Option<Vec<CuePointChangeset>> {
+    if content.get(..2)? != [0x01, 0x01] {
+        return None;
+    }
+    let encoded: Vec<u8> = content[2..]
+        .iter()
+        .copied()
+        .filter(|byte| byte.is_ascii_alphanumeric() || *byte == b'+' || *byte == b'/')
+        .collect();
+    let payload = SERATO_BASE64.decode(encoded).ok()?;
+    if payload.get(..2)? != [0x01, 0x01] {
+        return None;
+    }
+
+    let mut cue_points = Vec::new();
+    let mut offset = 2;
+    while let Some(name_length) = payload.get(offset..)?.iter().position(|byte| *byte == 0) {
+        if name_length == 0 {
+            break;
+        }
+        let name = String::from_utf8_lossy(&payload[offset..offset + name_length]);
+        offset += name_length + 1;
+        let length = u32::from_be_bytes(payload.get(offset..offset + 4)?.try_into().ok()?) as usize;
+        offset += 4;
+        let data = payload.get(offset..offset + length)?;
+        offset += length;
+
+        match name.as_ref() {
+            "CUE" if data.len() >= 13 => {
+                let position = u32::from_be_bytes(data[2..6].try_into().ok()?);
+                cue_points.push(CuePointChangeset {
+                    beat_id: 0,
+                    kind: cue_kind::HOT.to_string(),
+                    position_ms: position as i32,
+                    loop_length_ms: None,
+                    hot_cue_index: Some(data[1] as i32),
+                    label: null_terminated(&data[12..]),
+                    color: Some(format_color([data[7], data[8], data[9]])),
+                });
+            }
+            "LOOP" if data.len() >= 20 => {
+                let start = u32::from_be_bytes(data[2..6].try_into().ok()?);
+                let end = u32::from_be_bytes(data[6..10].try_into().ok()?);
+                cue_points.push(CuePointChangeset {
+                    beat_id: 0,
+                    kind: cue_kind::LOOP.to_string(),
+                    position_ms: start as i32,
+                    loop_length_ms: Some(end.saturating_sub(start) as i32),
+                    hot_cue_index: None,
+                    label: null_terminated(&data[19..]),
+                    color: None,
+                });
+            }
+            // Track color, bpm lock and anything newer
+            _ => {}
+        }
+    }
+    Some(cue_points)
+}
+
+// Reads the Serato cue points and beat grid stored in an audio file's ID3 tag.
+pub fn read_serato_tags(file_path: &str, duration: f64) -> Result<SeratoTags, Box<dyn Error>> {
+    let extension = Path::new(file_path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    if !matches!(extension.as_str(), "mp3" | "wav" | "aif" | "aiff") {
+        return Err(format!("Serato tags can only be read from MP3, WAV and AIFF files: {}", file_path).into());
+    }
+
+    let tag = match id3::Tag::read_from_path(file_path) {
+        Ok(tag) => tag,
+        Err(id3::Error {
+            kind: id3::ErrorKind::NoTag,
+            ..
+        }) => {
+            return Ok(SeratoTags {
+                cue_points: Vec::new(),
+                grid: None,
+            })
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let object = |description: &str| {
+        tag.encapsulated_objects()
+            .find(|object| object.description == description)
+            .map(|object| object.data.as_slice())
+    };
+    Ok(SeratoTags {
+        cue_points: object("Serato Markers2")
+            .and_then(parse_serato_markers)
+            .unwrap_or_default(),
+        grid: object("Serato BeatGrid").and_then(|payload| parse_serato_beatgrid(payload, duration)),
+    })
+}
+
+fn file_kind(file_path: &str) -> String {
+    let extension = Path::new(file_path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_uppercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "AIF" => "AIFF File".to_string(),
+        "" => "Audio File".to_string(),
+        other => format!("{} File", other),
+    }
+}
+
+// file://localhost/ URL the way Rekordbox writes them, percent encoding
+// everything but unreserved characters and path separators.
+fn file_url(file_path: &str) -> String {
+    let normalized = file_path.replace('\\', "/");
+    let mut url = String::from("file://localhost");
+    if !normalized.starts_with('/') {
+        url.push('/');
+    }
+    for byte in normalized.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' | b':' => {
+                url.push(byte as char)
+            }
+            _ => url.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    url
+}
+
+// Reverse of file_url, with forward slashes. Windows paths come out as "C:/...".
+fn file_path_from_url(url: &str) -> String {
+    let path = url
+        .strip_prefix("file://localhost")
+        .or_else(|| url.strip_prefix("file://"))
+        .unwrap_or(url);
+    let decoded = percent_decode_str(path).decode_utf8_lossy().to_string();
+    let bytes = decoded.as_bytes();
+    if bytes.len() > 2 && bytes[0] == b'/' && bytes[1].is_ascii_alphabetic() && bytes[2] == b':' {
+        decoded[1..].to_string()
+    } else {
+        decoded
+    }
+}
+
+fn parse_color(color: &str) -> Option<[u8; 3]> {
+    let hex = color.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+    Some([channel(0)?, channel(2)?, channel(4)?])
+}
+
+fn format_color([red, green, blue]: [u8; 3]) -> String {
+    format!("#{:02X}{:02X}{:02X}", red, green, blue)
+}
+
+fn null_terminated(bytes: &[u8]) -> Option<String> {
+    let end = bytes.iter().position(|byte| *byte == 0).unwrap_or(bytes.len());
+    Some(String::from_utf8_lossy(&bytes[..end]).to_string()).filter(|label| !label.is_empty())
+}
+
+fn escape_xml(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 120 bpm from the downbeat at 0.5 s, 126 bpm from the downbeat at 32.5 s
+    fn two_tempo_grid(beats_per_bar: u32) -> BeatGrid {
+        BeatGrid {
+            markers: vec![
+                TempoMarker { position: 0.5, bpm: 120.0 },
+                TempoMarker { position: 32.5, bpm: 126.0 },
+            ],
+            beats_per_bar,
+            duration: 60.0,
+        }
+    }
+
+    fn assert_same_grid(actual: &BeatGrid, expected: &BeatGrid) {
+        assert_eq!(actual.beats_per_bar, expected.beats_per_bar);
+        assert_eq!(actual.markers.len(), expected.markers.len());
+        for (a, e) in actual.markers.iter().zip(&expected.markers) {
+            assert!((a.position - e.position).abs() < 1e-3, "marker at {} instead of {}", a.position, e.position);
+            assert!((a.bpm - e.bpm).abs() < 0.01, "{} bpm instead of {}", a.bpm, e.bpm);
+        }
+    }
+
+    fn rekordbox_round_trip(grid: &BeatGrid) -> BeatGrid {
+        let xml = format!(
+            "<DJ_PLAYLISTS><COLLECTION><TRACK Location=\"{}\" TotalTime=\"60\">\n{}</TRACK></COLLECTION></DJ_PLAYLISTS>",
+            file_url("/music/beat.wav"),
+            tempo_elements(grid)
+        );
+        let mut tracks = parse_rekordbox_xml(&xml).unwrap();
+        assert_eq!(tracks.len(), 1);
+        assert_eq!(tracks[0].file_path, "/music/beat.wav");
+        tracks.remove(0).grid.unwrap()
+    }
+
+    #[test]
+    fn rekordbox_keeps_tempo_changes() {
+        let grid = two_tempo_grid(4);
+        assert_same_grid(&rekordbox_round_trip(&grid), &grid);
+    }
+
+    #[test]
+    fn rekordbox_keeps_the_meter() {
+        let grid = BeatGrid::constant(1.25, 140.0, 3, 60.0);
+        assert_same_grid(&rekordbox_round_trip(&grid), &grid);
+    }
+
+    #[test]
+    fn rekordbox_first_marker_off_the_downbeat() {
+        // Rekordbox puts the first marker on the first beat, here the third of its bar
+        let xml = "<DJ_PLAYLISTS><COLLECTION><TRACK Location=\"file://localhost/a.mp3\" TotalTime=\"60\">\
+                   <TEMPO Inizio=\"2.000\" Bpm=\"120.00\" Metro=\"4/4\" Battito=\"3\"/>\
+                   </TRACK></COLLECTION></DJ_PLAYLISTS>";
+        let grid = parse_rekordbox_xml(xml).unwrap().remove(0).grid.unwrap();
+        assert!((grid.first_downbeat() - 1.0).abs() < 1e-6, "first downbeat at {}", grid.first_downbeat());
+    }
+
+    #[test]
+    fn serato_keeps_tempo_changes() {
+        let grid = two_tempo_grid(4);
+        let parsed = parse_serato_beatgrid(&serato_beatgrid(&grid), grid.duration).unwrap();
+        assert_same_grid(&parsed, &grid);
+    }
+
+    #[test]
+    fn serato_rejects_unknown_versions() {
+        let mut payload = serato_beatgrid(&BeatGrid::constant(0.0, 120.0, 4, 60.0));
+        payload[0] = 0x02;
+        assert!(parse_serato_beatgrid(&payload, 60.0).is_none());
+        assert!(parse_serato_beatgrid(&[0x01, 0x00, 0x00], 60.0).is_none());
+    }
+
+    #[test]
+    fn file_urls_round_trip() {
+        for path in ["/Users/me/Beats/dark trap #3.wav", "C:/Beats/Ünïcode.mp3"] {
+            assert_eq!(file_path_from_url(&file_url(path)), path);
+        }
+        assert_eq!(file_path_from_url(&file_url("C:\\Beats\\a.mp3")), "C:/Beats/a.mp3");
+    }
+}
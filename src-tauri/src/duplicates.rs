@@ -0,0 +1,200 @@
+/*
+ * duplicates.rs
+ *
+ * Finds beats that are in the library more than once. Identical audio is
+ * found by the hash of the decoded samples, so copies that only differ in
+ * tags or file name still match. Near-duplicates, like two bounces of the same
+ * beat, are found by comparing acoustic fingerprints. Both are stored when a
+ * beat is analyzed, beats analyzed before that are identified first.
+ *
+ * Events emitted to the frontend:
+ * - duplicate-scan-progress: A beat without a hash was identified.
+ */
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::path::Path;
+use tauri::{AppHandle, Manager};
+
+use crate::audio_analysis::{self, decode_fingerprint};
+use crate::models::{Beat, BeatDetails, DisplayOptions};
+use crate::{db, AppState};
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DuplicateKind {
+    // Every beat has the same decoded audio
+    Identical,
+    // At least one beat sounds alike but isn't the same audio
+    Similar,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DuplicateGroup {
+    pub kind: DuplicateKind,
+    // Lowest similarity between two matched beats of the group, 1.0 for identical ones
+    pub similarity: f64,
+    // Oldest first, the one to keep unless the user picks another
+    pub beats: Vec<BeatDetails>,
+}
+
+#[derive(Serialize, Clone)]
+struct DuplicateScanProgress {
+    beat_id: i32,
+    // Beats identified so far, out of total
+    processed: usize,
+    total: usize,
+}
+
+// Hashes and fingerprints every present beat that has no hash yet. Files
+// that can't be decoded are skipped. Returns how many were identified.
+pub fn identify_missing(app: &AppHandle) -> Result<usize, Box<dyn Error>> {
+    let state = app.state::<AppState>();
+    let beats = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beats_without_audio_hash(&mut conn_guard.conn)?
+    };
+
+    let total = beats.len();
+    let mut identified = 0;
+    for (i, (beat_id, file_path)) in beats.into_iter().enumerate() {
+        // Decoding is the slow part, the database stays unlocked meanwhile
+        match audio_analysis::identify_audio(Path::new(&file_path)) {
+            Ok(identity) => {
+                let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+                db::save_audio_identity(&mut conn_guard.conn, beat_id, &identity)?;
+                identified += 1;
+            }
+            Err(e) => println!("Could not identify {}: {}", file_path, e),
+        }
+        let _ = app.emit_all(
+            "duplicate-scan-progress",
+            DuplicateScanProgress {
+                beat_id,
+                processed: i + 1,
+                total,
+            },
+        );
+    }
+    Ok(identified)
+}
+
+// Groups beats with the same audio hash or fingerprints at least
+// min_similarity alike. Beats without either are never grouped.
+pub fn group_duplicates(
+    beats: Vec<Beat>,
+    fingerprints: &HashMap<i32, Vec<u8>>,
+    min_similarity: f64,
+    display: DisplayOptions,
+) -> Vec<DuplicateGroup> {
+    let mut groups = DisjointSet::new(beats.len());
+    let mut lowest_similarity = vec![1.0f64; beats.len()];
+
+    let mut by_hash: HashMap<&str, usize> = HashMap::new();
+    for (index, beat) in beats.iter().enumerate() {
+        if let Some(hash) = beat.audio_hash.as_deref() {
+            let first = *by_hash.entry(hash).or_insert(index);
+            groups.union(first, index);
+        }
+    }
+
+    // Only one copy of identical audio needs comparing, the others follow it
+    let representatives: Vec<usize> = by_hash.values().copied().collect();
+    let (indexes, decoded): (Vec<usize>, Vec<Vec<u32>>) = representatives
+        .into_iter()
+        .filter_map(|index| {
+            fingerprints
+                .get(&beats[index].id)
+                .map(|data| (index, decode_fingerprint(data)))
+        })
+        .unzip();
+    for (a, b, similarity) in audio_analysis::find_similar(&decoded, min_similarity) {
+        let (a, b) = (indexes[a], indexes[b]);
+        groups.union(a, b);
+        lowest_similarity[a] = lowest_similarity[a].min(similarity);
+        lowest_similarity[b] = lowest_similarity[b].min(similarity);
+    }
+
+    let mut members: HashMap<usize, Vec<usize>> = HashMap::new();
+    for index in 0..beats.len() {
+        members.entry(groups.find(index)).or_default().push(index);
+    }
+    let mut grouped: Vec<(DuplicateKind, f64, Vec<usize>)> = members
+        .into_values()
+        .filter(|indexes| indexes.len() > 1)
+        .map(|indexes| {
+            let identical = indexes
+                .iter()
+                .all(|index| beats[*index].audio_hash == beats[indexes[0]].audio_hash);
+            if identical {
+                (DuplicateKind::Identical, 1.0, indexes)
+            } else {
+                let similarity = indexes
+                    .iter()
+                    .map(|index| lowest_similarity[*index])
+                    .fold(1.0, f64::min);
+                (DuplicateKind::Similar, (similarity * 1000.0).round() / 1000.0, indexes)
+            }
+        })
+        .collect();
+    for (_, _, indexes) in &mut grouped {
+        indexes.sort_by_key(|index| (beats[*index].date_created, beats[*index].id));
+    }
+    // Identical groups first, then the most alike
+    grouped.sort_by(|x, y| {
+        (x.0 == DuplicateKind::Similar)
+            .cmp(&(y.0 == DuplicateKind::Similar))
+            .then_with(|| y.1.total_cmp(&x.1))
+            .then_with(|| beats[x.2[0]].id.cmp(&beats[y.2[0]].id))
+    });
+
+    let mut beats: Vec<Option<Beat>> = beats.into_iter().map(Some).collect();
+    grouped
+        .into_iter()
+        .map(|(kind, similarity, indexes)| DuplicateGroup {
+            kind,
+            similarity,
+            beats: indexes
+                .into_iter()
+                .filter_map(|index| beats[index].take())
+                .map(|beat| BeatDetails::new(beat, display))
+                .collect(),
+        })
+        .collect()
+}
+
+// Union-find over indexes, for collecting pairs into groups
+struct DisjointSet {
+    parents: Vec<usize>,
+}
+
+impl DisjointSet {
+    fn new(size: usize) -> Self {
+        DisjointSet {
+            parents: (0..size).collect(),
+        }
+    }
+
+    fn find(&mut self, index: usize) -> usize {
+        let mut root = index;
+        while self.parents[root] != root {
+            root = self.parents[root];
+        }
+        // Point everything on the way straight at the root
+        let mut current = index;
+        while self.parents[current] != root {
+            let next = self.parents[current];
+            self.parents[current] = root;
+            current = next;
+        }
+        root
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (a, b) = (self.find(a), self.find(b));
+        if a != b {
+            self.parents[b] = a;
+        }
+    }
+}
@@ -0,0 +1,488 @@
+/*
+ * filename_parser.rs
+ *
+ * Pulls title, artist, bpm, key and version out of file names like
+ * "Dark Trap - 142bpm - F#m - v3.wav", the way producers tend to name beats.
+ *
+ * File names are first matched against the templates in the settings, e.g.
+ * "{artist} - {title} - {bpm}bpm". Placeholders are {title}, {artist}, {bpm},
+ * {key}, {version} and {ignore} for anything to skip, everything else has to
+ * match as written, ignoring case and with any amount of space around it.
+ * The first template that matches wins.
+ *
+ * Whatever no template covered is left to a heuristic. It splits the name at
+ * " - " and brackets and takes pieces that are a bpm ("142", "142bpm"), a key
+ * ("F#m", "8A", "Key C minor") or a version ("v3", "ver 2") out of the title.
+ * Inside a longer piece it only takes a key when there is a bpm or the word
+ * "key" next to it, so titles like "I Am" keep their words.
+ */
+
+use regex::Regex;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::LazyLock;
+
+use crate::musical_key::MusicalKey;
+
+// Tempos outside this range are more likely part of the title
+const MIN_BPM: f64 = 40.0;
+const MAX_BPM: f64 = 300.0;
+
+const PLACEHOLDERS: [&str; 6] = ["title", "artist", "bpm", "key", "version", "ignore"];
+
+// Text in brackets, and what separates the pieces of a name
+static BRACKETS: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"([\(\[\{])([^\)\]\}]*)([\)\]\}])").expect("valid regex"));
+static SEPARATOR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s+[-–—]+\s+|\s*\|\s*").expect("valid regex"));
+
+#[derive(Serialize, Default, Debug, Clone, PartialEq)]
+pub struct ParsedFilename {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub bpm: Option<f64>,
+    // Normalized like every other stored key
+    pub musical_key: Option<String>,
+    pub version: Option<String>,
+}
+
+pub struct FilenameParser {
+    templates: Vec<Regex>,
+}
+
+impl FilenameParser {
+    // Templates that don't compile are left out, so one typo in the settings
+    // doesn't stop imports.
+    pub fn new(templates: &[String]) -> Self {
+        let templates = templates
+            .iter()
+            .filter_map(|template| {
+                compile_template(template)
+                    .map_err(|e| println!("Ignoring filename template {:?}: {}", template, e))
+                    .ok()
+            })
+            .collect();
+        FilenameParser { templates }
+    }
+
+    // Parses the name of the file at file_path, without its extension.
+    pub fn parse_path(&self, file_path: &str) -> ParsedFilename {
+        let stem = Path::new(file_path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        self.parse(&stem)
+    }
+
+    pub fn parse(&self, name: &str) -> ParsedFilename {
+        let name = name.trim();
+        self.templates
+            .iter()
+            .find_map(|template| apply_template(template, name))
+            .unwrap_or_else(|| heuristic(name))
+    }
+}
+
+// What a template makes of a name. None when it doesn't match or what it
+// captured doesn't make sense, like a {key} that isn't a key or a {title}
+// with nothing left in it.
+fn apply_template(template: &Regex, name: &str) -> Option<ParsedFilename> {
+    let captures = template.captures(name)?;
+    let text = |group: &str| captures.name(group).map(|m| clean(m.as_str())).filter(|t| !t.is_empty());
+    let bpm = match captures.name("bpm") {
+        Some(bpm) => Some(parse_bpm(bpm.as_str())?),
+        None => None,
+    };
+    let musical_key = match captures.name("key") {
+        Some(key) => Some(MusicalKey::parse(key.as_str())?.to_string()),
+        None => None,
+    };
+
+    // The title can still hold a bpm, key or version the template doesn't cover
+    let found = heuristic(&text("title").unwrap_or_default());
+    if captures.name("title").is_some() && found.title.is_none() {
+        return None;
+    }
+    Some(ParsedFilename {
+        title: found.title,
+        artist: text("artist"),
+        bpm: bpm.or(found.bpm),
+        musical_key: musical_key.or(found.musical_key),
+        version: text("version").or(found.version),
+    })
+}
+
+// Whether a template compiles, for checking templates before they're saved.
+pub fn validate_template(template: &str) -> Result<(), String> {
+    compile_template(template).map(|_| ())
+}
+
+// A template as an anchored regex with a named group per placeholder
+fn compile_template(template: &str) -> Result<Regex, String> {
+    let mut pattern = String::from("(?i)^\\s*");
+    let mut seen = Vec::new();
+    let mut rest = template.trim();
+    while !rest.is_empty() {
+        let Some(start) = rest.find('{') else {
+            pattern.push_str(&literal(rest));
+            break;
+        };
+        pattern.push_str(&literal(&rest[..start]));
+        let end = rest[start..]
+            .find('}')
+            .map(|end| start + end)
+            .ok_or("Unclosed { in template")?;
+        let name = rest[start + 1..end].trim().to_lowercase();
+        if !PLACEHOLDERS.contains(&name.as_str()) {
+            return Err(format!("Unknown placeholder {{{}}}", name));
+        }
+        if name != "ignore" && seen.contains(&name) {
+            return Err(format!("{{{}}} appears more than once", name));
+        }
+        pattern.push_str(&match name.as_str() {
+            "bpm" => "(?P<bpm>\\d{2,3}(?:[.,]\\d+)?)".to_string(),
+            "version" => "(?:v|ver\\.?|version)?\\s*(?P<version>\\d+(?:\\.\\d+)*)".to_string(),
+            "ignore" => ".*?".to_string(),
+            group => format!("(?P<{}>.+?)", group),
+        });
+        seen.push(name);
+        rest = &rest[end + 1..];
+    }
+    if seen.is_empty() {
+        return Err("Template has no placeholders".to_string());
+    }
+    pattern.push_str("\\s*$");
+    Regex::new(&pattern).map_err(|e| e.to_string())
+}
+
+// Literal template text, any run of space in it matches any amount of space
+fn literal(text: &str) -> String {
+    let mut pattern = String::new();
+    let mut in_space = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !in_space {
+                pattern.push_str("\\s*");
+            }
+            in_space = true;
+        } else {
+            pattern.push_str(&regex::escape(c.encode_utf8(&mut [0; 4])));
+            in_space = false;
+        }
+    }
+    pattern
+}
+
+// One piece of a name between " - " separators, with what's in brackets split off
+struct Part {
+    text: String,
+    bracketed: Vec<(char, String, char)>,
+}
+
+fn heuristic(name: &str) -> ParsedFilename {
+    let mut parsed = ParsedFilename::default();
+    // Underscores stand in for spaces in a lot of names
+    let name = if name.contains(' ') { name.to_string() } else { name.replace('_', " ") };
+
+    let mut parts: Vec<Part> = SEPARATOR
+        .split(&name)
+        .map(|piece| {
+            let mut bracketed = Vec::new();
+            for captures in BRACKETS.captures_iter(piece) {
+                let open = captures[1].chars().next().unwrap_or('(');
+                let close = captures[3].chars().next().unwrap_or(')');
+                bracketed.push((open, captures[2].trim().to_string(), close));
+            }
+            Part {
+                text: clean(&BRACKETS.replace_all(piece, " ")),
+                bracketed,
+            }
+        })
+        .collect();
+
+    for part in &mut parts {
+        if take_whole(&mut parsed, &part.text) {
+            part.text.clear();
+        } else {
+            part.text = take_inline(&mut parsed, &part.text);
+        }
+        part.bracketed.retain_mut(|(_, text, _)| {
+            if take_whole(&mut parsed, text) {
+                return false;
+            }
+            *text = take_inline(&mut parsed, text);
+            !text.is_empty()
+        });
+    }
+
+    let title = parts
+        .iter()
+        .map(|part| {
+            let mut text = part.text.clone();
+            for (open, inner, close) in &part.bracketed {
+                text = format!("{} {}{}{}", text, open, inner, close).trim().to_string();
+            }
+            text
+        })
+        .filter(|text| !text.is_empty())
+        .collect::<Vec<_>>()
+        .join(" - ");
+    parsed.title = Some(title).filter(|title| !title.is_empty());
+    parsed
+}
+
+// A piece that is nothing but a bpm, key or version. Fields that are already
+// set aren't taken again, so the piece stays in the title.
+fn take_whole(parsed: &mut ParsedFilename, text: &str) -> bool {
+    let lower = text.to_lowercase();
+    if parsed.bpm.is_none() {
+        let number = lower
+            .trim_end_matches("bpm")
+            .trim_start_matches("bpm")
+            .trim_matches(|c: char| c.is_whitespace() || c == ':');
+        if let Some(bpm) = parse_bpm(number) {
+            parsed.bpm = Some(bpm);
+            return true;
+        }
+    }
+    if parsed.version.is_none() {
+        if let Some(version) = parse_version(&lower) {
+            parsed.version = Some(version);
+            return true;
+        }
+    }
+    if parsed.musical_key.is_none() {
+        let key = strip_key_marker(text).unwrap_or(text);
+        // Numbers alone are bpm or track numbers, never Camelot keys
+        if !key.chars().all(|c| c.is_ascii_digit()) {
+            if let Some(key) = MusicalKey::parse(key) {
+                parsed.musical_key = Some(key.to_string());
+                return true;
+            }
+        }
+    }
+    false
+}
+
+// Takes bpm, key and version out of a longer piece and returns what's left.
+fn take_inline(parsed: &mut ParsedFilename, text: &str) -> String {
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    let mut keep = vec![true; tokens.len()];
+    let lower: Vec<String> = tokens.iter().map(|token| token.to_lowercase()).collect();
+
+    let mut has_bpm = false;
+    for i in 0..tokens.len() {
+        if parsed.bpm.is_some() {
+            break;
+        }
+        let token = lower[i].as_str();
+        // "142bpm", "bpm142", or "142 bpm" and "bpm 142" as two tokens
+        let (number, used) = if let Some(number) = token.strip_suffix("bpm").filter(|n| !n.is_empty()) {
+            (number, vec![i])
+        } else if let Some(number) = token.strip_prefix("bpm").filter(|n| !n.is_empty()) {
+            (number.trim_start_matches(':'), vec![i])
+        } else if token == "bpm" && i > 0 && parse_bpm(&lower[i - 1]).is_some() {
+            (lower[i - 1].as_str(), vec![i - 1, i])
+        } else if token == "bpm" && i + 1 < tokens.len() {
+            (lower[i + 1].as_str(), vec![i, i + 1])
+        } else {
+            continue;
+        };
+        if let Some(bpm) = parse_bpm(number) {
+            parsed.bpm = Some(bpm);
+            has_bpm = true;
+            for index in used {
+                keep[index] = false;
+            }
+        }
+    }
+
+    for i in 0..tokens.len() {
+        if parsed.version.is_some() {
+            break;
+        }
+        if let Some(version) = parse_version(&lower[i]) {
+            parsed.version = Some(version);
+            keep[i] = false;
+        } else if matches!(lower[i].as_str(), "ver" | "ver." | "version") && i + 1 < tokens.len() {
+            if let Some(version) = parse_version(&format!("v{}", lower[i + 1])) {
+                parsed.version = Some(version);
+                keep[i] = false;
+                keep[i + 1] = false;
+            }
+        }
+    }
+
+    let has_marker = tokens.iter().any(|token| strip_key_marker(token).is_some());
+    if parsed.musical_key.is_none() && (has_bpm || has_marker) {
+        for i in 0..tokens.len() {
+            if !keep[i] {
+                continue;
+            }
+            let marked = strip_key_marker(tokens[i]);
+            // "key" on its own marks the token after it
+            if marked == Some("") {
+                continue;
+            }
+            let after_marker = i > 0 && strip_key_marker(tokens[i - 1]) == Some("");
+            let token = marked.unwrap_or(tokens[i]);
+            if token.chars().all(|c| c.is_ascii_digit()) {
+                continue;
+            }
+            // "F# minor" and "C major" come as two tokens
+            let with_mode = tokens
+                .get(i + 1)
+                .filter(|next| matches!(next.to_lowercase().as_str(), "major" | "minor" | "maj" | "min"))
+                .map(|next| format!("{} {}", token, next));
+            // A lone letter is a word more often than a key
+            let lone_letter = token.chars().count() < 2 && marked.is_none() && !after_marker;
+            if let Some(key) = with_mode.as_deref().and_then(MusicalKey::parse) {
+                parsed.musical_key = Some(key.to_string());
+                keep[i] = false;
+                keep[i + 1] = false;
+            } else if let Some(key) = MusicalKey::parse(token).filter(|_| !lone_letter) {
+                parsed.musical_key = Some(key.to_string());
+                keep[i] = false;
+            } else {
+                continue;
+            }
+            if after_marker {
+                keep[i - 1] = false;
+            }
+            break;
+        }
+    }
+
+    clean(
+        &tokens
+            .iter()
+            .zip(&keep)
+            .filter(|(_, keep)| **keep)
+            .map(|(token, _)| *token)
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+// What follows "key", "key:" or "key=" at the start of a piece or token
+fn strip_key_marker(text: &str) -> Option<&str> {
+    let rest = text.get(3..).filter(|_| text[..3].eq_ignore_ascii_case("key"))?;
+    let marked = rest.is_empty() || rest.starts_with([':', '=', ' ']);
+    marked.then(|| rest.trim_start_matches([':', '=', ' ']))
+}
+
+fn parse_bpm(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if text.is_empty() || !text.chars().all(|c| c.is_ascii_digit() || c == '.' || c == ',') {
+        return None;
+    }
+    let bpm: f64 = text.replace(',', ".").parse().ok()?;
+    (MIN_BPM..=MAX_BPM).contains(&bpm).then_some(bpm)
+}
+
+// "v3", "v2.1", "ver2", "version 3" written as one lowercase piece
+fn parse_version(text: &str) -> Option<String> {
+    let number = ["version", "ver.", "ver", "v"]
+        .iter()
+        .find_map(|prefix| text.strip_prefix(prefix))?
+        .trim();
+    let valid = !number.is_empty()
+        && number.split('.').all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit()));
+    valid.then(|| number.to_string())
+}
+
+// Collapses spaces and drops separators left dangling at either end
+fn clean(text: &str) -> String {
+    text.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_matches(|c: char| c.is_whitespace() || matches!(c, '-' | '_' | ',' | '|' | '–' | '—'))
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parsed(title: &str, bpm: Option<f64>, musical_key: Option<&str>, version: Option<&str>) -> ParsedFilename {
+        ParsedFilename {
+            title: Some(title.to_string()),
+            artist: None,
+            bpm,
+            musical_key: musical_key.map(String::from),
+            version: version.map(String::from),
+        }
+    }
+
+    fn parser(templates: &[&str]) -> FilenameParser {
+        FilenameParser::new(&templates.iter().map(|template| template.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn takes_everything_out_of_a_typical_name() {
+        let expected = parsed("Dark Trap", Some(142.0), Some("F# Minor"), Some("3"));
+        assert_eq!(heuristic("Dark Trap - 142bpm - F#m - v3"), expected);
+        assert_eq!(parser(&[]).parse_path("/beats/Dark Trap - 142bpm - F#m - v3.wav"), expected);
+    }
+
+    #[test]
+    fn heuristic_reads_brackets_and_inline_values() {
+        assert_eq!(
+            heuristic("Night Drive (140 BPM) [Cm]"),
+            parsed("Night Drive", Some(140.0), Some("C Minor"), None)
+        );
+        assert_eq!(heuristic("Sunset 128bpm Am"), parsed("Sunset", Some(128.0), Some("A Minor"), None));
+        assert_eq!(heuristic("Lost key C minor ver 2"), parsed("Lost", None, Some("C Minor"), Some("2")));
+        assert_eq!(heuristic("Glow | 8A | bpm 95"), parsed("Glow", Some(95.0), Some("A Minor"), None));
+        assert_eq!(heuristic("dark_trap_142bpm"), parsed("dark trap", Some(142.0), None, None));
+        // Brackets that aren't a value stay with the title
+        assert_eq!(heuristic("Rain (Remix) - 90"), parsed("Rain (Remix)", Some(90.0), None, None));
+    }
+
+    #[test]
+    fn heuristic_leaves_titles_alone() {
+        for name in ["I Am Legend", "A Day - B Side", "Beat - 2024", "Beat - 8", "Em Dash Only"] {
+            assert_eq!(heuristic(name), parsed(name, None, None, None), "{}", name);
+        }
+        // Inside a longer piece a bare number isn't a bpm, and a key needs a bpm or "key" next to it
+        assert_eq!(heuristic("Take Em 120"), parsed("Take Em 120", None, None, None));
+        assert_eq!(heuristic("Take Em 120bpm"), parsed("Take", Some(120.0), Some("E Minor"), None));
+        assert_eq!(heuristic(""), ParsedFilename::default());
+    }
+
+    #[test]
+    fn templates_come_first() {
+        let parser = parser(&["{artist} - {title} - {bpm}bpm", "{ignore} - {title} - {key}"]);
+        assert_eq!(
+            parser.parse("Someone - Night Drive - 128BPM"),
+            ParsedFilename {
+                artist: Some("Someone".to_string()),
+                ..parsed("Night Drive", Some(128.0), None, None)
+            }
+        );
+        assert_eq!(parser.parse("01 - Night Drive v2 - Gm"), parsed("Night Drive", None, Some("G Minor"), Some("2")));
+        // Neither template matches, or the {key} isn't a key
+        assert_eq!(parser.parse("Night Drive 128bpm"), parsed("Night Drive", Some(128.0), None, None));
+        assert_eq!(parser.parse("01 - Night Drive - Hello"), parsed("01 - Night Drive - Hello", None, None, None));
+    }
+
+    #[test]
+    fn template_placeholders() {
+        let parser = parser(&["{title}  -  {version}"]);
+        assert_eq!(parser.parse("Night Drive-ver. 2.1"), parsed("Night Drive", None, None, Some("2.1")));
+        let parser = self::parser(&["{title} ({bpm})"]);
+        assert_eq!(parser.parse("Night Drive (127,5)"), parsed("Night Drive", Some(127.5), None, None));
+        // A bpm out of range makes the template not match
+        assert_eq!(parser.parse("Night Drive (20)"), parsed("Night Drive (20)", None, None, None));
+    }
+
+    #[test]
+    fn validates_templates() {
+        assert!(validate_template("{artist} - {title} - {bpm}bpm").is_ok());
+        assert!(validate_template("{ignore} - {ignore} - {title}").is_ok());
+        for template in ["{title} - {tempo}", "{title} {Title}", "{title", "just text", ""] {
+            assert!(validate_template(template).is_err(), "{}", template);
+        }
+        // Broken templates are skipped instead of failing every parse
+        assert_eq!(parser(&["{nope}", "{title} - {bpm}"]).templates.len(), 1);
+    }
+}
@@ -0,0 +1,268 @@
+/*
+ * folder_import.rs
+ *
+ * Adds every audio file in a folder to the library. Files are filtered by the
+ * extensions symphonia is built to decode, and files already in the library
+ * are skipped by path. Tags and artwork are read before the database is
+ * locked, then all beats are inserted in a single transaction. The paths are
+ * checked again inside it, as the folder watcher or another import may have
+ * added some of the files in the meantime. A file that fails to insert rolls
+ * back on its own savepoint without taking the rest of the import with it.
+ *
+ * Events emitted to the frontend:
+ * - import-progress: A file of the folder was read or skipped.
+ */
+
+use diesel::Connection;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager};
+use walkdir::WalkDir;
+
+use crate::artwork::ArtworkStore;
+use crate::db::{self, FileMetadata};
+use crate::filename_parser::FilenameParser;
+use crate::paths::comparable_path;
+use crate::{store, AppState};
+
+// Formats enabled in symphonia's features in Cargo.toml
+pub const SUPPORTED_EXTENSIONS: [&str; 11] = [
+    "mp3", "wav", "wave", "flac", "m4a", "mp4", "aac", "ogg", "oga", "aif", "aiff",
+];
+
+#[derive(Deserialize, Debug)]
+#[serde(default)]
+pub struct ImportOptions {
+    // Only import these extensions, among the supported ones
+    pub extensions: Option<Vec<String>>,
+    // Include files and folders whose name starts with a dot
+    pub include_hidden: bool,
+    // Queue the new beats for analysis
+    pub analyze: bool,
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            extensions: None,
+            include_hidden: false,
+            analyze: true,
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ImportFailure {
+    pub file_path: String,
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ImportSummary {
+    pub added: usize,
+    // Already in the library
+    pub skipped: usize,
+    pub failed: Vec<ImportFailure>,
+}
+
+#[derive(Serialize, Clone)]
+struct ImportProgress {
+    folder: String,
+    // Files read or skipped so far, out of total
+    processed: usize,
+    total: usize,
+    file_path: String,
+}
+
+// A file read and ready to be inserted
+struct PreparedFile {
+    file_path: String,
+    metadata: FileMetadata,
+    cover_art: Option<String>,
+}
+
+pub fn import_folder(
+    app: &AppHandle,
+    folder: &str,
+    recursive: bool,
+    options: &ImportOptions,
+) -> Result<ImportSummary, Box<dyn Error>> {
+    let root = Path::new(folder);
+    if !root.is_dir() {
+        return Err(format!("{} is not a folder", folder).into());
+    }
+
+    let mut walk_failures = Vec::new();
+    let files = find_audio_files(root, recursive, options, &mut walk_failures);
+    let mut summary = import_files(app, folder, &files, options)?;
+    summary.failed.splice(0..0, walk_failures);
+    println!(
+        "Imported {}: {} added, {} skipped, {} failed",
+        folder,
+        summary.added,
+        summary.skipped,
+        summary.failed.len()
+    );
+    Ok(summary)
+}
+
+// Adds the files that aren't in the library yet. folder is only used to
+// label progress events.
+pub fn import_files(
+    app: &AppHandle,
+    folder: &str,
+    files: &[PathBuf],
+    options: &ImportOptions,
+) -> Result<ImportSummary, Box<dyn Error>> {
+    let mut summary = ImportSummary::default();
+    let total = files.len();
+
+    let state = app.state::<AppState>();
+    let existing: HashSet<String> = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beat_file_paths(&mut conn_guard.conn)?
+            .into_iter()
+            .map(|(_, file_path)| comparable_path(&file_path))
+            .collect()
+    };
+
+    // Probing files and decoding artwork is the slow part, keep it out of the lock
+    let parser = FilenameParser::new(&store::read_settings().filename_templates);
+    let artwork = app.state::<ArtworkStore>();
+    let mut prepared = Vec::new();
+    for (i, path) in files.iter().enumerate() {
+        let file_path = path.to_string_lossy().into_owned();
+        if existing.contains(&comparable_path(&file_path)) {
+            summary.skipped += 1;
+        } else {
+            match FileMetadata::read(&file_path, parser.parse_path(&file_path)) {
+                Ok(metadata) => prepared.push(PreparedFile {
+                    cover_art: artwork.import_for_file(&file_path),
+                    file_path: file_path.clone(),
+                    metadata,
+                }),
+                Err(e) => summary.failed.push(ImportFailure {
+                    file_path: file_path.clone(),
+                    reason: e.to_string(),
+                }),
+            }
+        }
+        let _ = app.emit_all(
+            "import-progress",
+            ImportProgress {
+                folder: folder.to_string(),
+                processed: i + 1,
+                total,
+                file_path,
+            },
+        );
+    }
+    if prepared.is_empty() {
+        return Ok(summary);
+    }
+
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    conn_guard.conn.transaction(|conn| {
+        // `existing` is out of date once the lock was let go
+        let mut in_library: HashSet<String> = db::get_beat_file_paths(conn)?
+            .into_iter()
+            .map(|(_, file_path)| comparable_path(&file_path))
+            .collect();
+        for file in &prepared {
+            if !in_library.insert(comparable_path(&file.file_path)) {
+                summary.skipped += 1;
+                continue;
+            }
+            // Each file gets a savepoint, so one bad insert only drops that file
+            let inserted = conn.transaction(|conn| {
+                let beat = db::add_beat(conn, &file.file_path, &file.metadata, file.cover_art.as_deref())?;
+                if options.analyze {
+                    db::enqueue_analysis(conn, beat.id, false)?;
+                }
+                Ok::<_, diesel::result::Error>(beat)
+            });
+            match inserted {
+                Ok(_) => summary.added += 1,
+                Err(e) => summary.failed.push(ImportFailure {
+                    file_path: file.file_path.clone(),
+                    reason: e.to_string(),
+                }),
+            }
+        }
+        Ok::<_, diesel::result::Error>(())
+    })?;
+    drop(conn_guard);
+
+    if options.analyze && summary.added > 0 {
+        state.analysis_queue.notify();
+    }
+    Ok(summary)
+}
+
+// Whether a file has one of the extensions being imported and isn't hidden.
+pub fn is_importable(path: &Path, options: &ImportOptions) -> bool {
+    let hidden = path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+    let extensions = import_extensions(options);
+    (options.include_hidden || !hidden)
+        && path
+            .extension()
+            .is_some_and(|extension| extensions.contains(&extension.to_string_lossy().to_lowercase()))
+}
+
+// The requested extensions that symphonia can decode, all of them by default
+fn import_extensions(options: &ImportOptions) -> Vec<String> {
+    match &options.extensions {
+        Some(extensions) => extensions
+            .iter()
+            .map(|extension| extension.trim_start_matches('.').to_lowercase())
+            .filter(|extension| SUPPORTED_EXTENSIONS.contains(&extension.as_str()))
+            .collect(),
+        None => SUPPORTED_EXTENSIONS.iter().map(|extension| extension.to_string()).collect(),
+    }
+}
+
+// Audio files under root, sorted by path. Folders that can't be read are
+// reported as failures.
+pub fn find_audio_files(
+    root: &Path,
+    recursive: bool,
+    options: &ImportOptions,
+    failed: &mut Vec<ImportFailure>,
+) -> Vec<PathBuf> {
+    let extensions = import_extensions(options);
+    let walker = WalkDir::new(root)
+        .max_depth(if recursive { usize::MAX } else { 1 })
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter()
+        // The root itself may be hidden, the user picked it
+        .filter_entry(|entry| {
+            options.include_hidden || entry.depth() == 0 || !entry.file_name().to_string_lossy().starts_with('.')
+        });
+
+    let mut files = Vec::new();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                failed.push(ImportFailure {
+                    file_path: e.path().map(|path| path.to_string_lossy().into_owned()).unwrap_or_default(),
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let is_audio = entry
+            .path()
+            .extension()
+            .is_some_and(|extension| extensions.contains(&extension.to_string_lossy().to_lowercase()));
+        if entry.file_type().is_file() && is_audio {
+            files.push(entry.into_path());
+        }
+    }
+    files
+}
@@ -0,0 +1,280 @@
+/*
+ * folder_watcher.rs
+ *
+ * Keeps the library in sync with the watch folders from settings. New audio
+ * files are imported the way import_folder does it, files that change refresh
+ * their beat, and beats whose file is removed are marked missing. A changed
+ * file is only analyzed again when its audio hash changed too, so tag edits,
+ * including the ones this app writes back, don't start an analysis. When watching starts, the folders are caught up with
+ * whatever happened while the app was closed.
+ *
+ * Filesystem events are debounced: a path is only handled once it has been
+ * quiet for DEBOUNCE, so a bounce that is still being written isn't imported
+ * half done. Paths that keep changing for longer than that are reported as
+ * continuous and left alone until they settle.
+ *
+ * Events emitted to the frontend:
+ * - watch-folder-update: Beats were added, refreshed or marked missing.
+ * - import-progress: See folder_import.rs.
+ */
+
+use diesel::Connection;
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, DebouncedEvent, DebouncedEventKind, Debouncer};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+use crate::audio_analysis;
+use crate::db::{self, FileMetadata};
+use crate::filename_parser::FilenameParser;
+use crate::folder_import::{self, ImportFailure, ImportOptions};
+use crate::models::file_status;
+use crate::paths::{comparable_path, is_under};
+use crate::{store, AppState};
+
+// How long a path has to be quiet before it's handled
+const DEBOUNCE: Duration = Duration::from_secs(3);
+
+#[derive(Serialize, Clone, Default)]
+struct WatchFolderUpdate {
+    added: usize,
+    refreshed: Vec<i32>,
+    missing: Vec<i32>,
+    failed: Vec<ImportFailure>,
+}
+
+impl WatchFolderUpdate {
+    fn is_empty(&self) -> bool {
+        self.added == 0 && self.refreshed.is_empty() && self.missing.is_empty() && self.failed.is_empty()
+    }
+}
+
+pub struct FolderWatcher {
+    debouncer: Mutex<Option<Debouncer<RecommendedWatcher>>>,
+    // Held while changes are applied, so catching up and events never import
+    // the same file twice
+    sync_lock: Arc<Mutex<()>>,
+}
+
+impl FolderWatcher {
+    pub fn new() -> Self {
+        FolderWatcher {
+            debouncer: Mutex::new(None),
+            sync_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    // Stops watching the previous folders and starts on these. Folders that
+    // can't be watched, e.g. on a drive that isn't connected, are skipped.
+    pub fn watch(&self, app: &AppHandle, folders: &[String]) -> Result<(), String> {
+        let mut debouncer_guard = self.debouncer.lock().map_err(|e| e.to_string())?;
+        // Dropping the old debouncer stops its thread
+        *debouncer_guard = None;
+        if folders.is_empty() {
+            return Ok(());
+        }
+
+        let handle = app.clone();
+        let sync_lock = Arc::clone(&self.sync_lock);
+        let mut debouncer = new_debouncer(DEBOUNCE, move |result: DebounceEventResult| match result {
+            Ok(events) => {
+                let Ok(_sync_guard) = sync_lock.lock() else {
+                    return;
+                };
+                handle_events(&handle, events);
+            }
+            Err(e) => println!("Watch folder error: {}", e),
+        })
+        .map_err(|e| e.to_string())?;
+
+        let mut watched = Vec::new();
+        for folder in folders {
+            match debouncer.watcher().watch(Path::new(folder), RecursiveMode::Recursive) {
+                Ok(()) => watched.push(folder.clone()),
+                Err(e) => println!("Could not watch {}: {}", folder, e),
+            }
+        }
+        *debouncer_guard = Some(debouncer);
+        drop(debouncer_guard);
+
+        let handle = app.clone();
+        let sync_lock = Arc::clone(&self.sync_lock);
+        thread::spawn(move || {
+            let Ok(_sync_guard) = sync_lock.lock() else {
+                return;
+            };
+            for folder in watched {
+                if let Err(e) = catch_up(&handle, &folder) {
+                    println!("Could not catch up with {}: {}", folder, e);
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+// Imports files added to a folder while it wasn't watched and updates which
+// of its beats are missing.
+fn catch_up(app: &AppHandle, folder: &str) -> Result<(), Box<dyn Error>> {
+    let summary = folder_import::import_folder(app, folder, true, &ImportOptions::default())?;
+
+    let root = comparable_path(folder);
+    let state = app.state::<AppState>();
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let mut present = Vec::new();
+    let mut missing = Vec::new();
+    for (beat_id, file_path) in db::get_beat_file_paths(&mut conn_guard.conn)? {
+        if !is_under(&comparable_path(&file_path), &root) {
+            continue;
+        }
+        if Path::new(&file_path).is_file() {
+            present.push(beat_id);
+        } else {
+            missing.push(beat_id);
+        }
+    }
+    db::set_file_status(&mut conn_guard.conn, &present, file_status::OK)?;
+    let newly_missing = db::set_file_status(&mut conn_guard.conn, &missing, file_status::MISSING)?;
+    drop(conn_guard);
+
+    if summary.added > 0 || newly_missing > 0 {
+        let _ = app.emit_all(
+            "watch-folder-update",
+            WatchFolderUpdate {
+                added: summary.added,
+                missing,
+                failed: summary.failed,
+                ..Default::default()
+            },
+        );
+    }
+    Ok(())
+}
+
+fn handle_events(app: &AppHandle, events: Vec<DebouncedEvent>) {
+    let paths: BTreeSet<PathBuf> = events
+        .into_iter()
+        .filter(|event| event.kind == DebouncedEventKind::Any)
+        .map(|event| event.path)
+        .collect();
+    match apply_changes(app, paths) {
+        Ok(update) if !update.is_empty() => {
+            println!(
+                "Watch folders: {} added, {} refreshed, {} missing",
+                update.added,
+                update.refreshed.len(),
+                update.missing.len()
+            );
+            let _ = app.emit_all("watch-folder-update", update);
+        }
+        Ok(_) => {}
+        Err(e) => println!("Could not apply watch folder changes: {}", e),
+    }
+}
+
+fn apply_changes(app: &AppHandle, paths: BTreeSet<PathBuf>) -> Result<WatchFolderUpdate, Box<dyn Error>> {
+    let options = ImportOptions::default();
+    let state = app.state::<AppState>();
+    let library = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beat_file_paths(&mut conn_guard.conn)?
+    };
+    let beats_at = |path: &Path, include_children: bool| -> Vec<i32> {
+        let path = comparable_path(&path.to_string_lossy());
+        library
+            .iter()
+            .filter(|(_, file_path)| {
+                let file_path = comparable_path(file_path);
+                file_path == path || (include_children && is_under(&file_path, &path))
+            })
+            .map(|(id, _)| *id)
+            .collect()
+    };
+
+    let mut update = WatchFolderUpdate::default();
+    let mut new_files = Vec::new();
+    let mut changed = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            // A folder moved in arrives as one event, not one per file
+            new_files.extend(folder_import::find_audio_files(&path, true, &options, &mut update.failed));
+        } else if path.is_file() {
+            if !folder_import::is_importable(&path, &options) {
+                continue;
+            }
+            let beat_ids = beats_at(&path, false);
+            if beat_ids.is_empty() {
+                new_files.push(path);
+            } else {
+                changed.extend(beat_ids.into_iter().map(|id| (id, path.clone())));
+            }
+        } else {
+            // Gone, either a file or a whole folder
+            update.missing.extend(beats_at(&path, true));
+        }
+    }
+
+    if let Some(first) = new_files.first() {
+        let folder = first.parent().map(|parent| parent.to_string_lossy().into_owned()).unwrap_or_default();
+        let summary = folder_import::import_files(app, &folder, &new_files, &options)?;
+        update.added = summary.added;
+        update.failed.extend(summary.failed);
+    }
+
+    let known_hashes = if changed.is_empty() {
+        HashMap::new()
+    } else {
+        let beat_ids: Vec<i32> = changed.iter().map(|(beat_id, _)| *beat_id).collect();
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_audio_hashes(&mut conn_guard.conn, &beat_ids)?
+    };
+
+    // Read the changed files before locking, like imports do
+    let parser = FilenameParser::new(&store::read_settings().filename_templates);
+    let mut refreshed = Vec::new();
+    for (beat_id, path) in changed {
+        let file_path = path.to_string_lossy().into_owned();
+        match FileMetadata::read(&file_path, parser.parse_path(&file_path)) {
+            Ok(metadata) => {
+                // Without a stored hash there's no telling, so it's analyzed
+                let audio_changed = match known_hashes.get(&beat_id) {
+                    Some(hash) => !audio_analysis::identify_audio(&path).is_ok_and(|identity| identity.hash == *hash),
+                    None => true,
+                };
+                refreshed.push((beat_id, metadata, audio_changed));
+            }
+            Err(e) => update.failed.push(ImportFailure {
+                file_path,
+                reason: e.to_string(),
+            }),
+        }
+    }
+
+    if !refreshed.is_empty() || !update.missing.is_empty() {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        let conn = &mut conn_guard.conn;
+        for (beat_id, metadata, audio_changed) in &refreshed {
+            // A new bounce is analyzed again. Values set by hand are kept.
+            conn.transaction(|conn| {
+                db::refresh_beat_file(conn, *beat_id, metadata)?;
+                if *audio_changed {
+                    db::enqueue_analysis(conn, *beat_id, false)?;
+                }
+                Ok::<_, diesel::result::Error>(())
+            })?;
+            update.refreshed.push(*beat_id);
+        }
+        db::set_file_status(conn, &update.missing, file_status::MISSING)?;
+        drop(conn_guard);
+    }
+    if refreshed.iter().any(|(_, _, audio_changed)| *audio_changed) {
+        state.analysis_queue.notify();
+    }
+    Ok(update)
+}
@@ -0,0 +1,61 @@
+/*
+ * gain.rs
+ *
+ * ReplayGain style playback gain. Every beat gets the offset in dB that brings
+ * its integrated loudness to the target loudness from settings, and every
+ * collection gets a single offset for the whole set (like ReplayGain album
+ * gain) so the level differences between its tracks are kept.
+ *
+ * Gains are limited so the true peak stays under PEAK_CEILING, since boosting
+ * a quiet but peaky track would otherwise clip.
+ */
+
+use crate::models::TrackLoudness;
+
+// Highest true peak a gain is allowed to push a track to, dBTP
+pub const PEAK_CEILING: f64 = -1.0;
+
+// Gain in dB that levels one track to `target` LUFS, None until it has been measured.
+pub fn track_gain(integrated_loudness: Option<f64>, true_peak: Option<f64>, target: f64) -> Option<f64> {
+    let gain = target - integrated_loudness?;
+    Some(round(limit_to_ceiling(gain, true_peak)))
+}
+
+// Combined loudness of tracks played back to back and the gain that levels
+// them to `target` LUFS as a group. Tracks that haven't been measured are left out.
+pub fn collection_gain(tracks: &[TrackLoudness], target: f64) -> (Option<f64>, Option<f64>) {
+    let measured: Vec<&TrackLoudness> = tracks
+        .iter()
+        .filter(|track| track.integrated_loudness.is_some())
+        .collect();
+    if measured.is_empty() {
+        return (None, None);
+    }
+
+    // Longer tracks contribute more, the same as measuring the whole set as one file
+    let (energy, total_weight) = measured.iter().fold((0.0, 0.0), |(energy, total), track| {
+        let weight = track.duration.filter(|d| *d > 0).unwrap_or(1) as f64;
+        let loudness = track.integrated_loudness.unwrap_or_default();
+        (energy + weight * 10f64.powf(loudness / 10.0), total + weight)
+    });
+    let loudness = 10.0 * (energy / total_weight).log10();
+
+    let peak = measured
+        .iter()
+        .filter_map(|track| track.true_peak)
+        .reduce(f64::max);
+    let gain = limit_to_ceiling(target - loudness, peak);
+
+    (Some(round(loudness)), Some(round(gain)))
+}
+
+fn limit_to_ceiling(gain: f64, true_peak: Option<f64>) -> f64 {
+    match true_peak {
+        Some(peak) => gain.min(PEAK_CEILING - peak),
+        None => gain,
+    }
+}
+
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
@@ -0,0 +1,120 @@
+/*
+ * harmonic.rs
+ *
+ * Harmonic mixing helpers. Describes the transition from one beat into another
+ * by key relation and tempo difference, and ranks the library by how well each
+ * beat mixes out of a given one.
+ */
+
+use serde::Serialize;
+
+use crate::models::{Beat, BeatDetails, DisplayOptions};
+use crate::musical_key::{KeyRelation, MusicalKey};
+
+// How much the key and tempo each count towards a transition score
+const KEY_WEIGHT: f64 = 0.7;
+const BPM_WEIGHT: f64 = 0.3;
+
+#[derive(Serialize, Debug)]
+pub struct CompatibleBeat {
+    #[serde(flatten)]
+    pub beat: BeatDetails,
+    pub key_relation: KeyRelation,
+    // Tempo difference from the source beat in percent, None if either bpm is unknown
+    pub bpm_difference: Option<f64>,
+    // 0.0 to 1.0, higher mixes better
+    pub score: f64,
+}
+
+// Key relation and tempo difference when mixing from one beat into another.
+#[derive(Clone, Copy, Debug)]
+pub struct Transition {
+    // None when both keys are known and clash
+    pub key_relation: Option<KeyRelation>,
+    // False when either beat has no key, so the relation couldn't be checked
+    pub keys_known: bool,
+    pub bpm_difference: Option<f64>,
+}
+
+impl Transition {
+    pub fn between(from: &Beat, to: &Beat) -> Self {
+        let from_key = from.musical_key.as_deref().and_then(MusicalKey::parse);
+        let to_key = to.musical_key.as_deref().and_then(MusicalKey::parse);
+        let (key_relation, keys_known) = match (from_key, to_key) {
+            (Some(from_key), Some(to_key)) => (from_key.relation_to(&to_key), true),
+            _ => (None, false),
+        };
+
+        let bpm_difference = match (from.bpm, to.bpm) {
+            (Some(from_bpm), Some(to_bpm)) if from_bpm > 0.0 && to_bpm > 0.0 => {
+                Some(bpm_difference_percent(from_bpm, to_bpm))
+            }
+            _ => None,
+        };
+
+        Transition {
+            key_relation,
+            keys_known,
+            bpm_difference,
+        }
+    }
+
+    // 0.0 to 1.0, higher mixes better. Unknown keys or tempos count as halfway.
+    pub fn score(&self, bpm_tolerance: f64) -> f64 {
+        let key_score = match (self.key_relation, self.keys_known) {
+            (Some(relation), _) => relation.score(),
+            (None, true) => 0.0,
+            (None, false) => 0.5,
+        };
+        let bpm_score = match self.bpm_difference {
+            Some(difference) if bpm_tolerance > 0.0 => (1.0 - difference / bpm_tolerance).max(0.0),
+            // A zero tolerance only accepts exact matches
+            Some(0.0) => 1.0,
+            Some(_) => 0.0,
+            None => 0.5,
+        };
+        KEY_WEIGHT * key_score + BPM_WEIGHT * bpm_score
+    }
+}
+
+// Relative tempo difference in percent. Half and double time count as the same
+// tempo, since a 70 BPM beat mixes fine into a 140 BPM one.
+pub fn bpm_difference_percent(from: f64, to: f64) -> f64 {
+    [to, to * 2.0, to / 2.0]
+        .iter()
+        .map(|candidate| (candidate - from).abs() / from * 100.0)
+        .fold(f64::INFINITY, f64::min)
+}
+
+// Beats from `candidates` that mix harmonically out of `source` and are within
+// bpm_tolerance percent of its tempo, best matches first.
+pub fn find_compatible_beats(
+    source: &Beat,
+    candidates: Vec<Beat>,
+    bpm_tolerance: f64,
+    display: DisplayOptions,
+) -> Vec<CompatibleBeat> {
+    let mut compatible: Vec<CompatibleBeat> = candidates
+        .into_iter()
+        .filter(|candidate| candidate.id != source.id)
+        .filter_map(|candidate| {
+            let transition = Transition::between(source, &candidate);
+            let key_relation = transition.key_relation?;
+
+            // Without a tempo on the source there's nothing to compare against
+            if source.bpm.is_some() && !matches!(transition.bpm_difference, Some(d) if d <= bpm_tolerance) {
+                return None;
+            }
+
+            Some(CompatibleBeat {
+                score: transition.score(bpm_tolerance),
+                key_relation,
+                bpm_difference: transition.bpm_difference.map(|d| (d * 100.0).round() / 100.0),
+                beat: BeatDetails::new(candidate, display),
+            })
+        })
+        .collect();
+
+    compatible.sort_by(|a, b| b.score.total_cmp(&a.score));
+    compatible
+}
@@ -0,0 +1,316 @@
+/*
+ * library_scan.rs
+ *
+ * Keeps beats pointing at files that exist. A scan checks the path of every
+ * beat and flags the ones whose file is gone as missing. Missing beats can
+ * then be relocated by searching folders for their files: candidates are found
+ * by file name, or by size when the file was renamed as well, and confirmed
+ * by the audio hash when the beat has one. A library that moved as a whole,
+ * e.g. to another drive, is repaired by rewriting the start of every path.
+ *
+ * Events emitted to the frontend:
+ * - relocate-progress: A missing beat was searched for.
+ */
+
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager};
+
+use crate::audio_analysis;
+use crate::db;
+use crate::folder_import::{self, ImportFailure, ImportOptions};
+use crate::models::{file_status, BeatFile, BeatFileChangeset};
+use crate::paths::{comparable_path, is_under};
+use crate::AppState;
+
+#[derive(Serialize, Debug, Default)]
+pub struct LibraryScanSummary {
+    pub checked: usize,
+    // Every beat whose file is missing now
+    pub missing: Vec<i32>,
+    // Beats that were missing and whose file is back
+    pub found: Vec<i32>,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchedBy {
+    // The decoded audio is the same, whatever the name and size
+    AudioHash,
+    // Only for beats without a hash yet
+    NameAndSize,
+    Name,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Relocation {
+    pub beat_id: i32,
+    pub old_path: String,
+    pub new_path: String,
+    pub matched_by: MatchedBy,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct RelocateSummary {
+    pub relocations: Vec<Relocation>,
+    // Missing beats no file was found for
+    pub not_found: Vec<i32>,
+    // Missing beats with more than one possible file, or one that couldn't be
+    // confirmed, left for the user to pick
+    pub ambiguous: Vec<i32>,
+    // Folders that couldn't be searched
+    pub failed: Vec<ImportFailure>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct PathRewrite {
+    pub beat_id: i32,
+    pub old_path: String,
+    pub new_path: String,
+    // Whether a file exists at new_path, rewritten beats without one stay missing
+    pub exists: bool,
+}
+
+#[derive(Serialize, Clone)]
+struct RelocateProgress {
+    beat_id: i32,
+    // Missing beats searched for so far, out of total
+    processed: usize,
+    total: usize,
+}
+
+// Checks the file of every beat and updates which are missing. Sizes are
+// filled in for present files that have none, so they can be recognized if
+// they go missing later.
+pub fn scan_library(app: &AppHandle) -> Result<LibraryScanSummary, Box<dyn Error>> {
+    let state = app.state::<AppState>();
+    let beats = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beat_files(&mut conn_guard.conn)?
+    };
+
+    // Files may be on slow or disconnected drives, keep the database unlocked meanwhile
+    let mut summary = LibraryScanSummary {
+        checked: beats.len(),
+        ..Default::default()
+    };
+    let mut changes = Vec::new();
+    for beat in beats {
+        let was_missing = beat.file_status == file_status::MISSING;
+        match std::fs::metadata(&beat.file_path).ok().filter(|metadata| metadata.is_file()) {
+            Some(metadata) => {
+                let file_size = Some(metadata.len() as i64).filter(|_| beat.file_size.is_none());
+                if was_missing {
+                    summary.found.push(beat.id);
+                }
+                if was_missing || file_size.is_some() {
+                    changes.push((
+                        beat.id,
+                        BeatFileChangeset {
+                            file_size,
+                            file_status: Some(file_status::OK.to_string()),
+                            ..Default::default()
+                        },
+                    ));
+                }
+            }
+            None => {
+                summary.missing.push(beat.id);
+                if !was_missing {
+                    changes.push((
+                        beat.id,
+                        BeatFileChangeset {
+                            file_status: Some(file_status::MISSING.to_string()),
+                            ..Default::default()
+                        },
+                    ));
+                }
+            }
+        }
+    }
+
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    db::update_beat_files(&mut conn_guard.conn, &changes)?;
+    Ok(summary)
+}
+
+// Searches the given folders for the files of missing beats. Nothing is
+// changed on a dry run, otherwise the beats found point at their new files.
+pub fn relocate_missing(app: &AppHandle, roots: &[String], dry_run: bool) -> Result<RelocateSummary, Box<dyn Error>> {
+    if let Some(root) = roots.iter().find(|root| !Path::new(root).is_dir()) {
+        return Err(format!("{} is not a folder", root).into());
+    }
+
+    let state = app.state::<AppState>();
+    let beats = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beat_files(&mut conn_guard.conn)?
+    };
+    // Files another beat already points at are never a missing beat's file
+    let mut taken: HashSet<String> = beats.iter().map(|beat| comparable_path(&beat.file_path)).collect();
+    let missing: Vec<BeatFile> = beats
+        .into_iter()
+        .filter(|beat| beat.file_status == file_status::MISSING)
+        .collect();
+
+    let mut summary = RelocateSummary::default();
+    if missing.is_empty() {
+        return Ok(summary);
+    }
+
+    // Hidden files too, the beat may have been added from anywhere
+    let options = ImportOptions {
+        include_hidden: true,
+        ..Default::default()
+    };
+    let mut by_name: HashMap<String, Vec<PathBuf>> = HashMap::new();
+    let mut by_size: HashMap<i64, Vec<PathBuf>> = HashMap::new();
+    for root in roots {
+        for path in folder_import::find_audio_files(Path::new(root), true, &options, &mut summary.failed) {
+            if let Some(name) = path.file_name() {
+                by_name.entry(name.to_string_lossy().to_lowercase()).or_default().push(path.clone());
+            }
+            if let Ok(metadata) = std::fs::metadata(&path) {
+                by_size.entry(metadata.len() as i64).or_default().push(path);
+            }
+        }
+    }
+
+    let total = missing.len();
+    let mut hashes: HashMap<PathBuf, Option<String>> = HashMap::new();
+    for (i, beat) in missing.into_iter().enumerate() {
+        let available = |paths: Option<&Vec<PathBuf>>| -> Vec<PathBuf> {
+            paths
+                .into_iter()
+                .flatten()
+                .filter(|path| !taken.contains(&comparable_path(&path.to_string_lossy())))
+                .cloned()
+                .collect()
+        };
+        let named = available(by_name.get(&file_name(&beat.file_path)));
+        let sized = available(beat.file_size.and_then(|size| by_size.get(&size)));
+
+        let found = match &beat.audio_hash {
+            Some(hash) => {
+                // Same name and size first, so the likeliest file is decoded first
+                let candidates: Vec<&PathBuf> = named
+                    .iter()
+                    .filter(|path| sized.contains(path))
+                    .chain(named.iter().filter(|path| !sized.contains(path)))
+                    .chain(sized.iter().filter(|path| !named.contains(path)))
+                    .collect();
+                let matched = candidates.iter().find(|path| {
+                    hashes
+                        .entry(path.to_path_buf())
+                        .or_insert_with(|| audio_analysis::identify_audio(path).ok().map(|identity| identity.hash))
+                        .as_ref()
+                        == Some(hash)
+                });
+                match matched {
+                    Some(path) => Some(Ok(((*path).clone(), MatchedBy::AudioHash))),
+                    // Files that could be it but whose audio differs, like a new bounce, are the user's call
+                    None if !candidates.is_empty() => Some(Err(())),
+                    None => None,
+                }
+            }
+            // Without a hash a renamed file can't be told apart from any other of that size
+            None => {
+                let same_size: Vec<&PathBuf> = named.iter().filter(|path| sized.contains(path)).collect();
+                match (same_size.as_slice(), named.as_slice()) {
+                    ([path], _) => Some(Ok(((*path).clone(), MatchedBy::NameAndSize))),
+                    ([], [path]) if beat.file_size.is_none() => Some(Ok((path.clone(), MatchedBy::Name))),
+                    ([], []) => None,
+                    _ => Some(Err(())),
+                }
+            }
+        };
+        match found {
+            Some(Ok((path, matched_by))) => {
+                let new_path = path.to_string_lossy().into_owned();
+                taken.insert(comparable_path(&new_path));
+                summary.relocations.push(Relocation {
+                    beat_id: beat.id,
+                    old_path: beat.file_path,
+                    new_path,
+                    matched_by,
+                });
+            }
+            Some(Err(())) => summary.ambiguous.push(beat.id),
+            None => summary.not_found.push(beat.id),
+        }
+        let _ = app.emit_all(
+            "relocate-progress",
+            RelocateProgress {
+                beat_id: beat.id,
+                processed: i + 1,
+                total,
+            },
+        );
+    }
+
+    if !dry_run && !summary.relocations.is_empty() {
+        let changes: Vec<(i32, BeatFileChangeset)> = summary
+            .relocations
+            .iter()
+            .map(|relocation| {
+                (
+                    relocation.beat_id,
+                    BeatFileChangeset {
+                        file_path: Some(relocation.new_path.clone()),
+                        file_size: std::fs::metadata(&relocation.new_path).ok().map(|metadata| metadata.len() as i64),
+                        file_status: Some(file_status::OK.to_string()),
+                    },
+                )
+            })
+            .collect();
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::update_beat_files(&mut conn_guard.conn, &changes)?;
+    }
+    println!(
+        "Relocated {} missing beats, {} not found, {} ambiguous",
+        summary.relocations.len(),
+        summary.not_found.len(),
+        summary.ambiguous.len()
+    );
+    Ok(summary)
+}
+
+// New paths for every beat under old_prefix, with old_prefix replaced by
+// new_prefix, e.g. "D:\Beats" to "/Volumes/Beats". Separators after the
+// prefix follow the style of new_prefix.
+pub fn rewrite_prefix(beats: &[BeatFile], old_prefix: &str, new_prefix: &str) -> Vec<PathRewrite> {
+    let old_prefix = comparable_path(old_prefix);
+    let old_prefix = old_prefix.trim_end_matches('/');
+    let new_prefix = new_prefix.trim_end_matches(['/', '\\']);
+    let backslashes = new_prefix.contains('\\') && !new_prefix.contains('/');
+
+    beats
+        .iter()
+        .filter_map(|beat| {
+            let file_path = comparable_path(&beat.file_path);
+            if !is_under(&file_path, old_prefix) {
+                return None;
+            }
+            let rest = &file_path[old_prefix.len()..];
+            let rest = if backslashes { rest.replace('/', "\\") } else { rest.to_string() };
+            let new_path = format!("{}{}", new_prefix, rest);
+            Some(PathRewrite {
+                beat_id: beat.id,
+                old_path: beat.file_path.clone(),
+                exists: Path::new(&new_path).is_file(),
+                new_path,
+            })
+        })
+        .collect()
+}
+
+// Lowercased name of the file at file_path, whichever separators it was stored with
+fn file_name(file_path: &str) -> String {
+    comparable_path(file_path)
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .to_lowercase()
+}
@@ -1,20 +1,60 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod analysis_queue;
+mod analyzer;
+mod artwork;
+mod atomic_file;
 mod audio_analysis;
+mod beat_grid;
 mod db;
+mod dj_formats;
+mod duplicates;
+mod filename_parser;
+mod folder_import;
+mod folder_watcher;
+mod gain;
+mod harmonic;
+mod library_scan;
 mod models;
+mod musical_key;
+mod paths;
 mod schema;
+mod set_order;
 mod store;
+mod tag_writer;
+mod tags;
+mod waveform_cache;
 use diesel::prelude::*;
 use serde_json;
 use std::{
+    collections::{HashMap, HashSet},
     env,
     path::Path,
     sync::{Arc, Mutex},
 };
 
-use crate::models::{Beat, BeatCollection};
+use crate::analysis_queue::AnalysisQueue;
+use crate::artwork::ArtworkStore;
+use crate::harmonic::CompatibleBeat;
+use crate::library_scan::{LibraryScanSummary, PathRewrite, RelocateSummary};
+use crate::beat_grid::BeatGrid;
+use crate::db::FileMetadata;
+use crate::dj_formats::{DjImportSummary, DjTrack, SkippedCuePoint};
+use crate::duplicates::DuplicateGroup;
+use crate::filename_parser::{FilenameParser, ParsedFilename};
+use crate::folder_import::{ImportOptions, ImportSummary};
+use crate::folder_watcher::FolderWatcher;
+use crate::models::{
+    cue_kind, file_status, value_source, HOT_CUE_COUNT, Beat, BeatCollection, BeatCollectionDetails, BeatDetails,
+    BeatComment, BeatFileChangeset, BeatGridDetails, BeatGridRecord, BeatInCollection, CuePoint, CuePointChangeset, FilenameParseSummary, SetEntry,
+    NewBeatComment, SetEntryDetails, SetEntryDetailsChangeset,
+};
+use crate::musical_key::{KeyNotation, MusicalKey};
+use crate::set_order::{EnergyCurve, SetOrderOptions, SetOrderSuggestion};
+use crate::tag_writer::{TagValues, TagWriteReport, TagWriter};
+use crate::waveform_cache::WaveformCache;
+use base64::Engine;
 use tauri::{Manager, State};
 
 struct DatabaseConnection {
@@ -36,6 +76,7 @@ impl Drop for DatabaseConnection {
 
 struct AppState {
     conn: Arc<Mutex<DatabaseConnection>>,
+    analysis_queue: Arc<AnalysisQueue>,
 }
 
 #[tauri::command]
@@ -44,13 +85,29 @@ fn greet(name: &str) -> String {
 }
 
 #[tauri::command]
-fn fetch_beats(state: State<AppState>) -> Result<String, String> {
+fn fetch_beats(state: State<AppState>, key_notation: Option<KeyNotation>) -> Result<String, String> {
+    let display = store::read_settings().display_options(key_notation);
     let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
     let conn = &mut conn_guard.conn;
     use crate::schema::beats::dsl::*;
 
+    let mut cue_points = db::get_cue_points_by_beat(conn, None).map_err(|e| e.to_string())?;
+    let comment_counts = db::count_beat_comments(conn).map_err(|e| e.to_string())?;
     beats
+        .select(Beat::as_select())
         .load::<Beat>(conn)
+        .map(|beats_result| {
+            beats_result
+                .into_iter()
+                .map(|beat| {
+                    let cues = cue_points.remove(&beat.id).unwrap_or_default();
+                    let counts = comment_counts.get(&beat.id).copied().unwrap_or_default();
+                    BeatDetails::new(beat, display)
+                        .with_cue_points(cues)
+                        .with_comment_counts(counts)
+                })
+                .collect::<Vec<_>>()
+        })
         .map_err(|e| e.to_string())
         .and_then(|beats_result| serde_json::to_string(&beats_result).map_err(|e| e.to_string()))
 }
@@ -62,75 +119,746 @@ fn fetch_column_vis() -> String {
 }
 
 #[tauri::command]
-fn add_beat(state: State<AppState>, file_path: String) -> Result<String, String> {
-    let file_name = Path::new(&file_path)
-        .file_name()
-        .and_then(|name| name.to_str())
-        .and_then(|name| name.rsplitn(2, '.').nth(1)) // Split from the end, get the part before the last period
-        .unwrap_or("Unknown")
-        .to_string();
+fn add_beat(state: State<AppState>, artwork: State<ArtworkStore>, file_path: String) -> Result<String, String> {
+    let filename = FilenameParser::new(&store::read_settings().filename_templates).parse_path(&file_path);
+    let metadata = FileMetadata::read(&file_path, filename.clone()).unwrap_or_else(|e| {
+        println!("Could not read tags from {}: {}", file_path, e);
+        FileMetadata::from_filename(filename)
+    });
+    // Decoding artwork for the thumbnail takes a moment, do it before locking the database
+    let cover_art = artwork.import_for_file(&file_path);
 
     let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
     let conn = &mut conn_guard.conn;
 
-    // Store the inserted beat result
-    let inserted_beat =
-        db::add_beat(&mut *conn, &file_name, &file_path).map_err(|e| e.to_string())?;
+    // Insert the beat and queue it for analysis together, so a beat is never left pending without a job
+    let inserted_beat = conn
+        .transaction(|conn| {
+            let beat = db::add_beat(conn, &file_path, &metadata, cover_art.as_deref())?;
+            db::enqueue_analysis(conn, beat.id, false)?;
+            Ok::<_, diesel::result::Error>(beat)
+        })
+        .map_err(|e| e.to_string())?;
+    drop(conn_guard);
 
     println!("New beat added with id: {}", inserted_beat.id);
 
-    // Analyze and update the beat synchronously
-    analyze_and_update_beat(inserted_beat.id, file_path.clone(), conn)?;
+    // Analysis runs in the background, progress is reported through events
+    state.analysis_queue.notify();
 
     Ok(format!("New beat added with id: {}", inserted_beat.id))
 }
 
-fn analyze_and_update_beat(
-    beat_id: i32, 
-    file_path: String, 
-    conn: &mut diesel::SqliteConnection // Pass connection as mutable reference
+// Adds every supported audio file in a folder, recursive by default. Progress
+// is reported through import-progress events.
+#[tauri::command]
+async fn import_folder(
+    app: tauri::AppHandle,
+    path: String,
+    recursive: Option<bool>,
+    options: Option<ImportOptions>,
+) -> Result<ImportSummary, String> {
+    // Large folders take a while, keep it off the async runtime
+    tauri::async_runtime::spawn_blocking(move || {
+        folder_import::import_folder(&app, &path, recursive.unwrap_or(true), &options.unwrap_or_default())
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// Groups beats that are in the library more than once: identical audio, and
+// fingerprints at least min_similarity alike (0.0 to 1.0, from settings when
+// left out). Beats without a hash yet are identified first, reporting
+// progress through duplicate-scan-progress events.
+#[tauri::command]
+async fn find_duplicates(
+    app: tauri::AppHandle,
+    min_similarity: Option<f64>,
+    key_notation: Option<KeyNotation>,
+) -> Result<Vec<DuplicateGroup>, String> {
+    let settings = store::read_settings();
+    let display = settings.display_options(key_notation);
+    let min_similarity = min_similarity.unwrap_or(settings.duplicate_similarity);
+    if !(0.0..=1.0).contains(&min_similarity) {
+        return Err(format!("Invalid similarity: {}", min_similarity));
+    }
+
+    // Decoding every unidentified beat takes a while, keep it off the async runtime
+    tauri::async_runtime::spawn_blocking(move || {
+        let identified = duplicates::identify_missing(&app).map_err(|e| e.to_string())?;
+        if identified > 0 {
+            println!("Identified {} beats", identified);
+        }
+
+        let state = app.state::<AppState>();
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        let conn = &mut conn_guard.conn;
+        let beats = db::get_beats_with_audio_hash(conn).map_err(|e| e.to_string())?;
+        let fingerprints = db::get_fingerprints(conn).map_err(|e| e.to_string())?;
+        drop(conn_guard);
+
+        Ok::<_, String>(duplicates::group_duplicates(beats, &fingerprints, min_similarity, display))
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// Keeps one beat of a duplicate group and removes the others from the
+// library. Their set entries, comments and cue points move to the kept beat,
+// their beat grids are dropped and the files stay on disk. Returns the number
+// of set entries moved.
+#[tauri::command]
+fn merge_duplicates(state: State<AppState>, keep_id: i32, duplicate_ids: Vec<i32>) -> Result<usize, String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::merge_beats(conn, keep_id, &duplicate_ids).map_err(|e| e.to_string())
+}
+
+// Checks that the file of every beat is still there and flags the ones that
+// aren't as missing.
+#[tauri::command]
+async fn scan_library(app: tauri::AppHandle) -> Result<LibraryScanSummary, String> {
+    tauri::async_runtime::spawn_blocking(move || library_scan::scan_library(&app).map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+// Looks for the files of missing beats in the given folders, by name, size and
+// audio hash. A dry run only reports what was found. Progress is reported
+// through relocate-progress events.
+#[tauri::command]
+async fn relocate_missing(
+    app: tauri::AppHandle,
+    roots: Vec<String>,
+    dry_run: Option<bool>,
+) -> Result<RelocateSummary, String> {
+    // Confirming candidates decodes them, keep it off the async runtime
+    tauri::async_runtime::spawn_blocking(move || {
+        library_scan::relocate_missing(&app, &roots, dry_run.unwrap_or(false)).map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+// Points one beat at a file picked by hand, e.g. for a beat relocate_missing
+// found more than one file for.
+#[tauri::command]
+fn relocate_beat(state: State<AppState>, beat_id: i32, file_path: String) -> Result<(), String> {
+    let metadata = std::fs::metadata(&file_path).map_err(|e| format!("{}: {}", file_path, e))?;
+    if !metadata.is_file() {
+        return Err(format!("{} is not a file", file_path));
+    }
+    let change = BeatFileChangeset {
+        file_path: Some(file_path),
+        file_size: Some(metadata.len() as i64),
+        file_status: Some(file_status::OK.to_string()),
+    };
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    match db::update_beat_files(&mut conn_guard.conn, &[(beat_id, change)]).map_err(|e| e.to_string())? {
+        0 => Err(format!("Beat {} not found", beat_id)),
+        _ => Ok(()),
+    }
+}
+
+// Replaces old_prefix with new_prefix in the path of every beat under it, e.g.
+// after the library moved to another drive. A dry run only reports the new
+// paths. Beats are flagged missing or present by whether their new path exists.
+#[tauri::command]
+fn rewrite_path_prefix(
+    state: State<AppState>,
+    old_prefix: String,
+    new_prefix: String,
+    dry_run: Option<bool>,
+) -> Result<Vec<PathRewrite>, String> {
+    if old_prefix.trim().is_empty() || new_prefix.trim().is_empty() {
+        return Err("Both prefixes are needed".to_string());
+    }
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    let beats = db::get_beat_files(conn).map_err(|e| e.to_string())?;
+    let rewrites = library_scan::rewrite_prefix(&beats, &old_prefix, &new_prefix);
+    if !dry_run.unwrap_or(false) {
+        let changes: Vec<(i32, BeatFileChangeset)> = rewrites
+            .iter()
+            .map(|rewrite| {
+                let status = if rewrite.exists { file_status::OK } else { file_status::MISSING };
+                (
+                    rewrite.beat_id,
+                    BeatFileChangeset {
+                        file_path: Some(rewrite.new_path.clone()),
+                        file_status: Some(status.to_string()),
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect();
+        db::update_beat_files(conn, &changes).map_err(|e| e.to_string())?;
+    }
+    Ok(rewrites)
+}
+
+// Replaces the watch folders in settings and starts watching them.
+#[tauri::command]
+fn set_watch_folders(
+    app: tauri::AppHandle,
+    watcher: State<FolderWatcher>,
+    folders: Vec<String>,
 ) -> Result<(), String> {
-    use crate::audio_analysis::analyze_audio;
+    if let Some(folder) = folders.iter().find(|folder| !Path::new(folder).is_dir()) {
+        return Err(format!("{} is not a folder", folder));
+    }
+    let mut settings = store::read_settings();
+    settings.watch_folders = folders;
+    store::write_settings(&settings)?;
+    watcher.watch(&app, &settings.watch_folders)
+}
 
-    println!("Starting analysis for file: {}", file_path);
+// Queues beats for analysis again and wakes the workers. Returns how many were queued.
+fn queue_reanalysis(state: &State<AppState>, beat_ids: Vec<i32>, force: bool) -> Result<usize, String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    conn.transaction(|conn| {
+        for beat_id in &beat_ids {
+            db::enqueue_analysis(conn, *beat_id, force)?;
+        }
+        Ok::<_, diesel::result::Error>(())
+    })
+    .map_err(|e| e.to_string())?;
+    drop(conn_guard);
 
-    // Check if the connection works before running analysis
-    if diesel::select(diesel::dsl::sql::<diesel::sql_types::Integer>("1"))
-        .load::<i32>(conn)
-        .is_err()
-    {
-        println!("Database connection test failed");
-        return Err("Connection check failed".into());
+    state.analysis_queue.notify();
+    Ok(beat_ids.len())
+}
+
+// Re-runs analysis on one beat. Manually edited, imported and tagged values are kept unless force is set.
+#[tauri::command]
+fn reanalyze_beat(state: State<AppState>, id: i32, force: Option<bool>) -> Result<usize, String> {
+    queue_reanalysis(&state, vec![id], force.unwrap_or(false))
+}
+
+// Re-runs analysis on every beat in a collection.
+#[tauri::command]
+fn reanalyze_collection(
+    state: State<AppState>,
+    collection_id: i32,
+    force: Option<bool>,
+) -> Result<usize, String> {
+    let beat_ids = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beat_ids_in_collection(&mut conn_guard.conn, collection_id)
+            .map_err(|e| e.to_string())?
+    };
+    queue_reanalysis(&state, beat_ids, force.unwrap_or(false))
+}
+
+// Queues every beat that is still missing a bpm, key or loudness, e.g. after a failed analysis.
+#[tauri::command]
+fn reanalyze_missing(state: State<AppState>) -> Result<usize, String> {
+    let beat_ids = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beat_ids_missing_analysis(&mut conn_guard.conn).map_err(|e| e.to_string())?
+    };
+    queue_reanalysis(&state, beat_ids, false)
+}
+
+// Multiplies the bpm of the selected beats, 2.0 to double and 0.5 to halve
+#[tauri::command]
+fn scale_bpm(state: State<AppState>, beat_ids: Vec<i32>, factor: f64) -> Result<usize, String> {
+    if !factor.is_finite() || factor <= 0.0 {
+        return Err(format!("Invalid bpm factor: {}", factor));
+    }
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::scale_bpm(conn, &beat_ids, factor).map_err(|e| e.to_string())
+}
+
+// Cancels queued or running analysis for a single beat
+#[tauri::command]
+fn cancel_analysis(state: State<AppState>, id: i32) -> Result<usize, String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::cancel_analysis(conn, Some(id)).map_err(|e| e.to_string())
+}
+
+// Cancels everything left in the analysis queue
+#[tauri::command]
+fn cancel_all_analysis(state: State<AppState>) -> Result<usize, String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::cancel_analysis(conn, None).map_err(|e| e.to_string())
+}
+
+// Converts a key typed in any notation to the requested one, e.g. "F#m" -> "11A"
+#[tauri::command]
+fn convert_key(key: String, notation: KeyNotation) -> Result<String, String> {
+    MusicalKey::parse(&key)
+        .map(|parsed| parsed.format(notation))
+        .ok_or_else(|| format!("Unrecognized key: {}", key))
+}
+
+// Beats that mix harmonically out of the given one, best matches first. The
+// tolerance is a percentage and falls back to the one in settings.
+#[tauri::command]
+fn find_compatible_beats(
+    state: State<AppState>,
+    beat_id: i32,
+    bpm_tolerance: Option<f64>,
+    key_notation: Option<KeyNotation>,
+) -> Result<Vec<CompatibleBeat>, String> {
+    let settings = store::read_settings();
+    let display = settings.display_options(key_notation);
+    let tolerance = bpm_tolerance.unwrap_or(settings.bpm_tolerance_percent);
+    if !tolerance.is_finite() || tolerance < 0.0 {
+        return Err(format!("Invalid bpm tolerance: {}", tolerance));
     }
-    println!("Connection check passed");
-
-    //Call your Python analysis function
-    match analyze_audio(&file_path) {
-        Ok((key, tempo)) => {
-            println!("Analysis Result: Key: {}, Tempo: {}", key, tempo); // Debug output
-            let musical_key_str = key.to_string(); // Ensure key is a String
-
-            // Update the database
-            diesel::update(crate::schema::beats::dsl::beats.find(beat_id))
-                .set((
-                    crate::schema::beats::dsl::musical_key.eq(Some(musical_key_str)),
-                    crate::schema::beats::dsl::bpm.eq(Some(tempo)),
-                ))
-                .execute(conn)
-                .map_err(|e| {
-                    println!("Error updating beat: {:?}", e); // Log the error
-                    e.to_string()
-                })?;
+
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    let source = db::get_beat(conn, beat_id).map_err(|e| e.to_string())?;
+    if source.musical_key.as_deref().and_then(MusicalKey::parse).is_none() {
+        return Err(format!("Beat {} has no key to match against", beat_id));
+    }
+    let candidates = db::get_beats_with_key(conn).map_err(|e| e.to_string())?;
+    drop(conn_guard);
+
+    Ok(harmonic::find_compatible_beats(&source, candidates, tolerance, display))
+}
+
+#[tauri::command]
+fn get_beat_grid(state: State<AppState>, beat_id: i32) -> Result<Option<BeatGridDetails>, String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::get_beat_grid(conn, beat_id)
+        .map(|record| record.map(BeatGridDetails::new))
+        .map_err(|e| e.to_string())
+}
+
+// Moves the whole beat grid by offset_ms, positive values later into the track.
+#[tauri::command]
+fn nudge_beat_grid(state: State<AppState>, beat_id: i32, offset_ms: f64) -> Result<BeatGridDetails, String> {
+    if !offset_ms.is_finite() {
+        return Err(format!("Invalid offset: {}", offset_ms));
+    }
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    let mut grid = db::get_beat_grid(conn, beat_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Beat {} has no beat grid yet", beat_id))?
+        .grid();
+    grid.nudge(offset_ms / 1000.0);
+    db::save_beat_grid(conn, &BeatGridRecord::new(beat_id, &grid, value_source::MANUAL))
+        .map(BeatGridDetails::new)
+        .map_err(|e| e.to_string())
+}
+
+// Re-anchors the beat grid so a downbeat falls exactly on `position` (seconds),
+// optionally changing the number of beats per bar. A beat without a grid gets
+// one at its bpm.
+#[tauri::command]
+fn anchor_beat_grid(
+    state: State<AppState>,
+    beat_id: i32,
+    position: f64,
+    beats_per_bar: Option<u32>,
+) -> Result<BeatGridDetails, String> {
+    if !position.is_finite() || position < 0.0 {
+        return Err(format!("Invalid position: {}", position));
+    }
+    if matches!(beats_per_bar, Some(beats) if !(1..=16).contains(&beats)) {
+        return Err("Beats per bar must be between 1 and 16".to_string());
+    }
+
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    let grid = match db::get_beat_grid(conn, beat_id).map_err(|e| e.to_string())? {
+        Some(record) => {
+            let mut grid = record.grid();
+            grid.anchor(position, beats_per_bar);
+            grid
         }
-        Err(e) => {
-            println!("Failed to analyze audio. Error: {}", e); // Log the error
-            return Err(e.to_string()); // Return the error as a Result
+        None => {
+            let beat = db::get_beat(conn, beat_id).map_err(|e| e.to_string())?;
+            let bpm = beat
+                .bpm
+                .filter(|bpm| *bpm > 0.0)
+                .ok_or_else(|| format!("Beat {} has no bpm to build a grid from", beat_id))?;
+            let duration = beat.duration.unwrap_or_default() as f64;
+            BeatGrid::constant(position, bpm, beats_per_bar.unwrap_or(4), duration)
         }
-    }
+    };
+    db::save_beat_grid(conn, &BeatGridRecord::new(beat_id, &grid, value_source::MANUAL))
+        .map(BeatGridDetails::new)
+        .map_err(|e| e.to_string())
+}
+
+// Writes a Rekordbox collection XML with the given beats, their grids and cue
+// points to file_path. Returns the number of tracks written.
+#[tauri::command]
+fn export_rekordbox_xml(state: State<AppState>, beat_ids: Vec<i32>, file_path: String) -> Result<usize, String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    let rows = db::get_beats_with_grids(conn, &beat_ids).map_err(|e| e.to_string())?;
+    let mut cue_points = db::get_cue_points_by_beat(conn, Some(&beat_ids)).map_err(|e| e.to_string())?;
+    drop(conn_guard);
+
+    let tracks: Vec<DjTrack> = rows
+        .into_iter()
+        .map(|(beat, record)| DjTrack {
+            cue_points: cue_points.remove(&beat.id).unwrap_or_default(),
+            grid: record.map(|r| r.grid()),
+            beat,
+        })
+        .collect();
+    std::fs::write(&file_path, dj_formats::rekordbox_xml(&tracks)).map_err(|e| e.to_string())?;
+    Ok(tracks.len())
+}
+
+// Reads cue points and beat grids from a Rekordbox collection XML. Tracks are
+// matched to beats by file path. Cue points of a matched beat are replaced
+// when the track has any, grids replace the stored grid.
+#[tauri::command]
+fn import_rekordbox_xml(state: State<AppState>, file_path: String) -> Result<DjImportSummary, String> {
+    let xml = std::fs::read_to_string(&file_path).map_err(|e| e.to_string())?;
+    let tracks = dj_formats::parse_rekordbox_xml(&xml).map_err(|e| e.to_string())?;
+
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    let beat_ids: HashMap<String, i32> = db::get_beat_file_paths(conn)
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .map(|(id, path)| (paths::comparable_path(&path), id))
+        .collect();
+
+    let mut summary = DjImportSummary {
+        tracks: tracks.len(),
+        ..Default::default()
+    };
+    conn.transaction(|conn| {
+        for track in tracks {
+            let Some(beat_id) = beat_ids.get(&paths::comparable_path(&track.file_path)) else {
+                summary.unmatched.push(track.file_path);
+                continue;
+            };
+            summary.matched += 1;
+            let cue_points = valid_imported_cue_points(&track.file_path, track.cue_points, &mut summary.skipped_cue_points);
+            if !cue_points.is_empty() {
+                summary.cue_points += db::replace_cue_points(conn, *beat_id, &cue_points)?.len();
+            }
+            if let Some(mut grid) = track.grid {
+                // TotalTime is left out by some exports
+                if grid.duration <= 0.0 {
+                    grid.duration = db::get_beat(conn, *beat_id)?.duration.unwrap_or_default() as f64;
+                }
+                db::save_beat_grid(conn, &BeatGridRecord::new(*beat_id, &grid, value_source::IMPORTED))?;
+                summary.beat_grids += 1;
+            }
+        }
+        Ok::<_, diesel::result::Error>(())
+    })
+    .map_err(|e| e.to_string())?;
+    Ok(summary)
+}
+
+// Payload of the "Serato BeatGrid" tag for a beat, base64 encoded.
+#[tauri::command]
+fn export_serato_beatgrid(state: State<AppState>, beat_id: i32) -> Result<String, String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    let grid = db::get_beat_grid(conn, beat_id)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Beat {} has no beat grid yet", beat_id))?
+        .grid();
+    Ok(base64::engine::general_purpose::STANDARD.encode(dj_formats::serato_beatgrid(&grid)))
+}
+
+// Content of the "Serato Markers2" tag for a beat's hot cues and loops, base64 encoded.
+#[tauri::command]
+fn export_serato_markers(state: State<AppState>, beat_id: i32) -> Result<String, String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    let cue_points = db::get_cue_points(conn, beat_id).map_err(|e| e.to_string())?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(dj_formats::serato_markers(&cue_points)))
+}
+
+// Reads the cue points and beat grid Serato stored in a beat's file. Like the
+// Rekordbox import, each only replaces what's stored when the file has one.
+#[tauri::command]
+fn import_serato_tags(state: State<AppState>, beat_id: i32) -> Result<DjImportSummary, String> {
+    let beat = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beat(&mut conn_guard.conn, beat_id).map_err(|e| e.to_string())?
+    };
+    // Reading tags touches the file, keep the database unlocked meanwhile
+    let tags = dj_formats::read_serato_tags(&beat.file_path, beat.duration.unwrap_or_default() as f64)
+        .map_err(|e| e.to_string())?;
 
+    let mut summary = DjImportSummary {
+        tracks: 1,
+        matched: 1,
+        ..Default::default()
+    };
+    let cue_points = valid_imported_cue_points(&beat.file_path, tags.cue_points, &mut summary.skipped_cue_points);
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    conn_guard
+        .conn
+        .transaction(|conn| {
+            if !cue_points.is_empty() {
+                summary.cue_points = db::replace_cue_points(conn, beat_id, &cue_points)?.len();
+            }
+            if let Some(grid) = &tags.grid {
+                db::save_beat_grid(conn, &BeatGridRecord::new(beat_id, grid, value_source::IMPORTED))?;
+                summary.beat_grids = 1;
+            }
+            Ok::<_, diesel::result::Error>(())
+        })
+        .map_err(|e| e.to_string())?;
+    Ok(summary)
+}
+
+// Keeps the imported cue points of one track that would be accepted if they
+// were added by hand and lists the others in `skipped`, so one bad mark
+// doesn't stop the whole import. They replace the stored cue points, so pads
+// are only checked against each other.
+fn valid_imported_cue_points(
+    file_path: &str,
+    cue_points: Vec<CuePointChangeset>,
+    skipped: &mut Vec<SkippedCuePoint>,
+) -> Vec<CuePointChangeset> {
+    let mut pads = HashSet::new();
+    cue_points
+        .into_iter()
+        .filter(|cue| {
+            let check = validate_cue_point(cue).and_then(|_| match cue.hot_cue_index {
+                Some(index) if !pads.insert(index) => Err(format!("Hot cue {} is already taken", index)),
+                _ => Ok(()),
+            });
+            match check {
+                Ok(()) => true,
+                Err(reason) => {
+                    skipped.push(SkippedCuePoint {
+                        file_path: file_path.to_string(),
+                        position_ms: cue.position_ms,
+                        reason,
+                    });
+                    false
+                }
+            }
+        })
+        .collect()
+}
+
+// Checks a cue point before it is saved
+fn validate_cue_point(cue: &CuePointChangeset) -> Result<(), String> {
+    if ![cue_kind::MEMORY, cue_kind::HOT, cue_kind::LOOP].contains(&cue.kind.as_str()) {
+        return Err(format!("Unknown cue point kind: {}", cue.kind));
+    }
+    if cue.position_ms < 0 {
+        return Err(format!("Invalid cue position: {}", cue.position_ms));
+    }
+    if matches!(cue.loop_length_ms, Some(length) if length <= 0) {
+        return Err("Loop length must be positive".to_string());
+    }
+    if cue.kind == cue_kind::LOOP && cue.loop_length_ms.is_none() {
+        return Err("Loops need a length".to_string());
+    }
+    if cue.kind == cue_kind::MEMORY && cue.loop_length_ms.is_some() {
+        return Err("Memory cues can't have a length, save a loop instead".to_string());
+    }
+    if cue.kind != cue_kind::HOT && cue.hot_cue_index.is_some() {
+        return Err("Only hot cues have a pad".to_string());
+    }
+    if matches!(cue.hot_cue_index, Some(index) if !(0..HOT_CUE_COUNT).contains(&index)) {
+        return Err(format!("Hot cue pads are numbered 0 to {}", HOT_CUE_COUNT - 1));
+    }
+    if let Some(color) = &cue.color {
+        let valid = color.len() == 7
+            && color.starts_with('#')
+            && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+        if !valid {
+            return Err(format!("Colors are written as #RRGGBB: {}", color));
+        }
+    }
     Ok(())
 }
 
+#[tauri::command]
+fn get_cue_points(state: State<AppState>, beat_id: i32) -> Result<Vec<CuePoint>, String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::get_cue_points(conn, beat_id).map_err(|e| e.to_string())
+}
+
+// Adds a cue point. Hot cues without a pad go on the first free one.
+#[tauri::command]
+fn add_cue_point(state: State<AppState>, cue: CuePointChangeset) -> Result<CuePoint, String> {
+    validate_cue_point(&cue)?;
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::add_cue_point(conn, cue).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn update_cue_point(state: State<AppState>, id: i32, cue: CuePointChangeset) -> Result<CuePoint, String> {
+    validate_cue_point(&cue)?;
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::update_cue_point(conn, id, cue).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_cue_point(state: State<AppState>, id: i32) -> Result<(), String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::delete_cue_point(conn, id).map_err(|e| e.to_string())
+}
+
+// Pins a review comment to a moment in a beat.
+#[tauri::command]
+fn add_beat_comment(state: State<AppState>, mut comment: NewBeatComment) -> Result<BeatComment, String> {
+    comment.author = comment.author.trim().to_string();
+    comment.body = comment.body.trim().to_string();
+    if comment.author.is_empty() || comment.body.is_empty() {
+        return Err("Comments need an author and some text".to_string());
+    }
+    if comment.timestamp_ms < 0 {
+        return Err(format!("Invalid timestamp: {}", comment.timestamp_ms));
+    }
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::add_beat_comment(conn, &comment).map_err(|e| e.to_string())
+}
+
+// Comments on a beat in the order they appear in the track. Resolved ones are
+// left out unless include_resolved is set.
+#[tauri::command]
+fn get_beat_comments(
+    state: State<AppState>,
+    beat_id: i32,
+    include_resolved: Option<bool>,
+) -> Result<Vec<BeatComment>, String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::get_beat_comments(conn, beat_id, include_resolved.unwrap_or(false)).map_err(|e| e.to_string())
+}
+
+// Marks a comment as resolved, or reopens it with resolved set to false.
+#[tauri::command]
+fn resolve_beat_comment(state: State<AppState>, id: i32, resolved: Option<bool>) -> Result<BeatComment, String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::resolve_beat_comment(conn, id, resolved.unwrap_or(true)).map_err(|e| e.to_string())
+}
+
+// Artwork of a beat as a data URL, or its thumbnail. None when it has none.
+#[tauri::command]
+fn get_cover_art(
+    state: State<AppState>,
+    artwork: State<ArtworkStore>,
+    beat_id: i32,
+    thumbnail: Option<bool>,
+) -> Result<Option<String>, String> {
+    let cover_art = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beat(&mut conn_guard.conn, beat_id)
+            .map_err(|e| e.to_string())?
+            .cover_art
+    };
+    let Some(reference) = cover_art else {
+        return Ok(None);
+    };
+    let data = artwork
+        .read(&reference, thumbnail.unwrap_or(false))
+        .map_err(|e| e.to_string())?;
+    Ok(Some(artwork::data_url(&data)))
+}
+
+// Stores the image at image_path and makes it the artwork of the given beats,
+// or clears their artwork when image_path is None.
+fn set_cover_art(
+    state: &State<AppState>,
+    artwork: &State<ArtworkStore>,
+    beat_ids: &[i32],
+    image_path: Option<String>,
+) -> Result<usize, String> {
+    let reference = image_path
+        .map(|path| artwork.store_file(Path::new(&path)))
+        .transpose()
+        .map_err(|e| e.to_string())?;
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    db::set_cover_art(&mut conn_guard.conn, beat_ids, reference.as_deref()).map_err(|e| e.to_string())
+}
+
+// Sets custom artwork for one beat, replacing whatever was found on import.
+#[tauri::command]
+fn set_beat_cover_art(
+    state: State<AppState>,
+    artwork: State<ArtworkStore>,
+    beat_id: i32,
+    image_path: Option<String>,
+) -> Result<usize, String> {
+    set_cover_art(&state, &artwork, &[beat_id], image_path)
+}
+
+// Sets the same artwork for every beat in a collection, e.g. for a set recorded as one release.
+#[tauri::command]
+fn set_collection_cover_art(
+    state: State<AppState>,
+    artwork: State<ArtworkStore>,
+    collection_id: i32,
+    image_path: Option<String>,
+) -> Result<usize, String> {
+    let beat_ids = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beat_ids_in_collection(&mut conn_guard.conn, collection_id).map_err(|e| e.to_string())?
+    };
+    set_cover_art(&state, &artwork, &beat_ids, image_path)
+}
+
+// Looks for artwork again for beats that have none, e.g. ones added before
+// artwork was read on import. Returns how many got artwork.
+#[tauri::command]
+fn extract_cover_art(state: State<AppState>, artwork: State<ArtworkStore>, beat_ids: Vec<i32>) -> Result<usize, String> {
+    let beats = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beats_without_cover_art(&mut conn_guard.conn, &beat_ids).map_err(|e| e.to_string())?
+    };
+
+    let mut found = 0;
+    for beat in beats {
+        if let Some(reference) = artwork.import_for_file(&beat.file_path) {
+            let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+            found += db::set_cover_art(&mut conn_guard.conn, &[beat.id], Some(&reference)).map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(found)
+}
+
+// Fills in title, artist, bpm, key and version of existing beats from their
+// file names, with the templates from the settings. Every beat when beat_ids is None.
+#[tauri::command]
+fn parse_filenames(state: State<AppState>, beat_ids: Option<Vec<i32>>) -> Result<FilenameParseSummary, String> {
+    let parser = FilenameParser::new(&store::read_settings().filename_templates);
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    db::parse_filenames(&mut conn_guard.conn, beat_ids.as_deref(), &parser).map_err(|e| e.to_string())
+}
+
+// What a file name parses into, to try templates out before saving them.
+// Uses the saved templates when none are given.
+#[tauri::command]
+fn parse_filename(file_name: String, templates: Option<Vec<String>>) -> Result<ParsedFilename, String> {
+    let templates = match templates {
+        Some(templates) => {
+            for template in &templates {
+                filename_parser::validate_template(template).map_err(|e| format!("{}: {}", template, e))?;
+            }
+            templates
+        }
+        None => store::read_settings().filename_templates,
+    };
+    Ok(FilenameParser::new(&templates).parse_path(&file_name))
+}
+
 #[tauri::command]
 fn delete_beat(id: i32, state: State<AppState>) -> Result<(), String> {
     let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
@@ -140,12 +868,74 @@ fn delete_beat(id: i32, state: State<AppState>) -> Result<(), String> {
 }
 use crate::models::BeatChangeset;
 #[tauri::command]
-fn update_beat(beat: BeatChangeset, state: State<AppState>) -> Result<(), String> {
-    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
-    let conn = &mut conn_guard.conn;
+fn update_beat(
+    mut beat: BeatChangeset,
+    state: State<AppState>,
+    artwork: State<ArtworkStore>,
+    tag_writer: State<TagWriter>,
+) -> Result<(), String> {
+    // Store keys in the normalized form whatever notation they were typed in,
+    // an emptied key field removes the key
+    let mut clear_key = false;
+    if let Some(key) = beat.musical_key.take() {
+        if key.trim().is_empty() {
+            clear_key = true;
+        } else {
+            let parsed = MusicalKey::parse(&key).ok_or_else(|| format!("Unrecognized key: {}", key))?;
+            beat.musical_key = Some(parsed.to_string());
+        }
+    }
+    let beat_id = beat.id;
 
-    db::update_beat(conn, beat)
-        .map_err(|e| e.to_string())
+    {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        let conn = &mut conn_guard.conn;
+        db::update_beat(conn, beat, clear_key).map_err(|e| e.to_string())?;
+    }
+
+    // Tag write-back is opt in, files are only touched when asked to
+    if store::read_settings().write_tags_on_edit {
+        let report = write_beat_tags(&state, &artwork, &tag_writer, beat_id, false)?;
+        if let Some(error) = report.error {
+            return Err(format!("The edit was saved, but writing tags to {} failed: {}", report.file_path, error));
+        }
+    }
+    Ok(())
+}
+
+fn write_beat_tags(
+    state: &State<AppState>,
+    artwork: &State<ArtworkStore>,
+    tag_writer: &State<TagWriter>,
+    beat_id: i32,
+    dry_run: bool,
+) -> Result<TagWriteReport, String> {
+    let beat = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beat(&mut conn_guard.conn, beat_id).map_err(|e| e.to_string())?
+    };
+    let image = beat
+        .cover_art
+        .as_deref()
+        .and_then(|reference| artwork.read(reference, false).ok());
+    Ok(tag_writer.write(&beat, &TagValues::new(&beat, image), dry_run))
+}
+
+// Writes title, artist, genre, comments, bpm, key and artwork of the beats
+// into their files. A dry run only reports what would change. A file that
+// can't be written doesn't stop the others, its report holds the error.
+#[tauri::command]
+fn write_tags(
+    state: State<AppState>,
+    artwork: State<ArtworkStore>,
+    tag_writer: State<TagWriter>,
+    beat_ids: Vec<i32>,
+    dry_run: Option<bool>,
+) -> Result<Vec<TagWriteReport>, String> {
+    beat_ids
+        .into_iter()
+        .map(|beat_id| write_beat_tags(&state, &artwork, &tag_writer, beat_id, dry_run.unwrap_or(false)))
+        .collect()
 }
 
 #[tauri::command]
@@ -174,19 +964,127 @@ fn new_beat_collection(
 }
 
 #[tauri::command]
-fn get_beat_collection(state: State<AppState>, id: i32) -> Result<BeatCollection, String> {
+fn get_beat_collection(state: State<AppState>, id: i32) -> Result<BeatCollectionDetails, String> {
+    let loudness_target = store::read_settings().loudness_target;
     let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
     let conn = &mut conn_guard.conn;
-    db::get_beat_collection(&mut *conn, id).map_err(|e| e.to_string())
+    let collection = db::get_beat_collection(&mut *conn, id).map_err(|e| e.to_string())?;
+    let tracks = db::get_collection_track_loudness(conn, id).map_err(|e| e.to_string())?;
+    Ok(BeatCollectionDetails::new(collection, &tracks, loudness_target))
 }
 
 #[tauri::command]
-fn get_beats_in_collection(state: State<AppState>, id: i32) -> Result<Vec<Beat>, String> {
+fn get_beats_in_collection(
+    state: State<AppState>,
+    id: i32,
+    key_notation: Option<KeyNotation>,
+) -> Result<Vec<SetEntry>, String> {
     println!("getting beats in collection");
+    let display = store::read_settings().display_options(key_notation);
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    let entries = db::get_beats_in_collection(&mut *conn, id).map_err(|e| e.to_string())?;
+    let beat_ids: Vec<i32> = entries.iter().map(|(_, beat, _)| beat.id).collect();
+    let cue_points = db::get_cue_points_by_beat(conn, Some(&beat_ids)).map_err(|e| e.to_string())?;
+    Ok(entries
+        .into_iter()
+        .map(|(entry, beat, details)| {
+            // A beat can be in a set more than once, so every entry gets its own copy
+            let cues = cue_points.get(&beat.id).cloned().unwrap_or_default();
+            SetEntry::new(entry, BeatDetails::new(beat, display).with_cue_points(cues), details)
+        })
+        .collect())
+}
+
+// Suggests a running order for a collection. Nothing is saved until the
+// frontend passes the suggested entry_ids to reorder_set.
+#[tauri::command]
+fn suggest_set_order(
+    state: State<AppState>,
+    collection_id: i32,
+    opener_id: Option<i32>,
+    closer_id: Option<i32>,
+    energy_curve: Option<EnergyCurve>,
+    bpm_tolerance: Option<f64>,
+    key_notation: Option<KeyNotation>,
+) -> Result<SetOrderSuggestion, String> {
+    let settings = store::read_settings();
+    let display = settings.display_options(key_notation);
+    let options = SetOrderOptions {
+        opener: opener_id,
+        closer: closer_id,
+        energy_curve,
+        bpm_tolerance: bpm_tolerance.unwrap_or(settings.bpm_tolerance_percent),
+    };
+
+    let entries = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beats_in_collection(&mut conn_guard.conn, collection_id).map_err(|e| e.to_string())?
+    };
+    let entries = entries.into_iter().map(|(entry, beat, _)| (entry, beat)).collect();
+    set_order::suggest_set_order(entries, &options, display)
+}
+
+// Saves the running order of a set, e.g. a suggestion from suggest_set_order
+#[tauri::command]
+fn reorder_set(state: State<AppState>, collection_id: i32, entry_ids: Vec<i32>) -> Result<(), String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::reorder_set(conn, collection_id, &entry_ids).map_err(|e| e.to_string())
+}
+
+// Moves one set entry to a new position, 0 being the opener
+#[tauri::command]
+fn move_set_entry(state: State<AppState>, entry_id: i32, index: i32) -> Result<BeatInCollection, String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::move_set_entry(conn, entry_id, index).map_err(|e| e.to_string())
+}
+
+// Removes a single occurrence of a beat from a set
+#[tauri::command]
+fn remove_set_entry(state: State<AppState>, entry_id: i32) -> Result<(), String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::remove_set_entry(conn, entry_id).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_set_entry_details(state: State<AppState>, entry_id: i32) -> Result<Option<SetEntryDetails>, String> {
     let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
     let conn = &mut conn_guard.conn;
-    db::get_beats_in_collection(&mut *conn, id).map_err(|e| e.to_string())
-    // print the result
+    db::get_set_entry_details(conn, entry_id).map_err(|e| e.to_string())
+}
+
+// Saves the transition, mix points, key/pitch plan and note for a set entry
+#[tauri::command]
+fn save_set_entry_details(
+    state: State<AppState>,
+    details: SetEntryDetailsChangeset,
+) -> Result<SetEntryDetails, String> {
+    let mix_times = [details.mix_in_time, details.mix_out_time];
+    if mix_times.iter().flatten().any(|time| !time.is_finite() || *time < 0.0) {
+        return Err("Mix times must be positive".to_string());
+    }
+    if let (Some(mix_in), Some(mix_out)) = (details.mix_in_time, details.mix_out_time) {
+        if mix_in > mix_out {
+            return Err("Mix in time must come before mix out time".to_string());
+        }
+    }
+    if details.key_shift.is_some_and(|shift| !(-12..=12).contains(&shift)) {
+        return Err("Key shift must be within an octave".to_string());
+    }
+
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::save_set_entry_details(conn, details).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn delete_set_entry_details(state: State<AppState>, entry_id: i32) -> Result<(), String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::delete_set_entry_details(conn, entry_id).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -205,10 +1103,17 @@ fn fetch_collections(state: State<AppState>) -> Result<String, String> {
 
     use crate::schema::beat_collection::dsl::*;
 
-    beat_collection
+    let loudness_target = store::read_settings().loudness_target;
+    let collections = beat_collection
         .load::<BeatCollection>(&mut *conn)
-        .map_err(|e| e.to_string())
-        .and_then(|beats_result| serde_json::to_string(&beats_result).map_err(|e| e.to_string()))
+        .map_err(|e| e.to_string())?;
+
+    let mut details = Vec::with_capacity(collections.len());
+    for collection in collections {
+        let tracks = db::get_collection_track_loudness(conn, collection.id).map_err(|e| e.to_string())?;
+        details.push(BeatCollectionDetails::new(collection, &tracks, loudness_target));
+    }
+    serde_json::to_string(&details).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -223,6 +1128,49 @@ fn add_beat_to_collection(
     Ok(())
 }
 
+// Inserts a beat into a set at the given position, or at the end without one.
+// The same beat can be inserted more than once.
+#[tauri::command]
+fn insert_beat_in_collection(
+    state: State<AppState>,
+    collection_id: i32,
+    beat_id: i32,
+    index: Option<i32>,
+) -> Result<BeatInCollection, String> {
+    let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+    let conn = &mut conn_guard.conn;
+    db::insert_beat_in_collection(conn, collection_id, beat_id, index).map_err(|e| e.to_string())
+}
+
+// Waveform overview of a beat in the binary layout described in waveform_cache.rs,
+// base64 encoded. Bands adds low/mid/high levels for a colored waveform.
+#[tauri::command]
+async fn get_waveform(
+    state: State<'_, AppState>,
+    cache: State<'_, Arc<WaveformCache>>,
+    beat_id: i32,
+    bands: Option<bool>,
+) -> Result<String, String> {
+    let file_path = {
+        let mut conn_guard = state.conn.lock().map_err(|e| e.to_string())?;
+        db::get_beat(&mut conn_guard.conn, beat_id)
+            .map_err(|e| e.to_string())?
+            .file_path
+    };
+
+    // Decoding takes a while, keep it off the async runtime
+    let cache = Arc::clone(&cache);
+    let encoded = tauri::async_runtime::spawn_blocking(move || {
+        cache
+            .get(&file_path, bands.unwrap_or(false))
+            .map_err(|e| e.to_string())
+    })
+    .await
+    .map_err(|e| e.to_string())??;
+
+    Ok(base64::engine::general_purpose::STANDARD.encode(encoded))
+}
+
 fn main() {
     println!("Starting beatbank...");
 
@@ -231,8 +1179,10 @@ fn main() {
     };
     println!("Connection established!");
 
+    let conn = Arc::new(Mutex::new(conn));
     let app_state = AppState {
-        conn: Arc::new(Mutex::new(conn)),
+        analysis_queue: AnalysisQueue::new(Arc::clone(&conn)),
+        conn,
     };
 
     tauri::Builder::default()
@@ -241,6 +1191,14 @@ fn main() {
             greet,
             fetch_beats,
             add_beat,
+            import_folder,
+            set_watch_folders,
+            find_duplicates,
+            merge_duplicates,
+            scan_library,
+            relocate_missing,
+            relocate_beat,
+            rewrite_path_prefix,
             delete_beat,
             update_beat,
             fetch_column_vis, 
@@ -248,8 +1206,47 @@ fn main() {
             fetch_collections,
             delete_beat_collection,
             add_beat_to_collection,
+            insert_beat_in_collection,
             get_beat_collection,
             get_beats_in_collection,
+            suggest_set_order,
+            reorder_set,
+            move_set_entry,
+            remove_set_entry,
+            get_set_entry_details,
+            save_set_entry_details,
+            delete_set_entry_details,
+            reanalyze_beat,
+            reanalyze_collection,
+            reanalyze_missing,
+            scale_bpm,
+            convert_key,
+            find_compatible_beats,
+            get_beat_grid,
+            nudge_beat_grid,
+            anchor_beat_grid,
+            export_rekordbox_xml,
+            import_rekordbox_xml,
+            export_serato_beatgrid,
+            export_serato_markers,
+            import_serato_tags,
+            get_cue_points,
+            add_cue_point,
+            update_cue_point,
+            delete_cue_point,
+            add_beat_comment,
+            get_beat_comments,
+            resolve_beat_comment,
+            get_waveform,
+            get_cover_art,
+            set_beat_cover_art,
+            set_collection_cover_art,
+            extract_cover_art,
+            parse_filenames,
+            parse_filename,
+            write_tags,
+            cancel_analysis,
+            cancel_all_analysis,
             store::load_settings,
             store::save_settings,
             store::get_settings_path
@@ -261,6 +1258,28 @@ fn main() {
             diesel::sql_query("PRAGMA foreign_keys = ON")
                 .execute(&mut conn_guard.conn)
                 .map_err(|e| e.to_string())?;
+
+            // Bring keys saved before MusicalKey existed into the normalized form
+            let normalized = db::normalize_musical_keys_once(&mut conn_guard.conn).map_err(|e| e.to_string())?;
+            if normalized > 0 {
+                println!("Normalized {} musical keys", normalized);
+            }
+            drop(conn_guard);
+
+            let waveform_dir = app.path_resolver().app_cache_dir().map(|dir| dir.join("waveforms"));
+            app.manage(Arc::new(WaveformCache::new(waveform_dir)));
+            let artwork_dir = app.path_resolver().app_data_dir().map(|dir| dir.join("artwork"));
+            app.manage(ArtworkStore::new(artwork_dir));
+            let tag_backup_dir = app.path_resolver().app_data_dir().map(|dir| dir.join("tag_backups"));
+            app.manage(TagWriter::new(tag_backup_dir));
+
+            // Imports go through the managed state above, so start watching last
+            let watcher = FolderWatcher::new();
+            watcher.watch(&app.handle(), &store::read_settings().watch_folders)?;
+            app.manage(watcher);
+
+            // Pick up any analysis left over from the last session
+            state.analysis_queue.start(app.handle())?;
             Ok(())
         })
         .on_window_event(|e| {
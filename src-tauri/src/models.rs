@@ -11,8 +11,13 @@ Note: To create the database with current schema, models, and migrations,
 use the following command in terminal: diesel migration run
 -----------------------------------------------------*/
 
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
+
+use crate::audio_analysis::LOW_KEY_CONFIDENCE;
+use crate::beat_grid::{BeatGrid, GridBeat, TempoMarker};
+use crate::gain;
+use crate::musical_key::{KeyNotation, MusicalKey};
 #[derive(Queryable, Selectable, Debug)]
 #[diesel(table_name = crate::schema::beats)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
@@ -34,6 +39,82 @@ pub struct Beat {
     pub bpm: Option<f64>,
     pub musical_key: Option<String>,
     pub date_created: NaiveDateTime,
+    pub key_confidence: Option<f64>,
+    pub analysis_status: String,
+    pub bpm_source: Option<String>,
+    pub key_source: Option<String>,
+    pub bpm_raw: Option<f64>,
+    pub integrated_loudness: Option<f64>,
+    pub short_term_loudness_max: Option<f64>,
+    pub loudness_range: Option<f64>,
+    pub true_peak: Option<f64>,
+    pub version: Option<String>,
+    pub file_status: String,
+    pub audio_hash: Option<String>,
+    pub file_size: Option<i64>,
+}
+
+// Settings the derived fields of BeatDetails depend on.
+#[derive(Clone, Copy, Debug)]
+pub struct DisplayOptions {
+    pub key_notation: KeyNotation,
+    // LUFS that gain levels tracks to
+    pub loudness_target: f64,
+}
+
+// Beat as it is sent to the frontend, with flags derived from the stored columns.
+#[derive(serde::Serialize, Debug)]
+pub struct BeatDetails {
+    #[serde(flatten)]
+    pub beat: Beat,
+    // True when the detected key is unreliable and should be checked by ear
+    pub key_low_confidence: bool,
+    // musical_key in the notation the frontend asked for
+    pub key_display: Option<String>,
+    // Playback gain in dB toward the loudness target, None until loudness is measured
+    pub gain: Option<f64>,
+    // Only loaded for the library and set views, left out everywhere else
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cue_points: Option<Vec<CuePoint>>,
+    // Review comments on the beat, only counted for the library view
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment_count: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unresolved_comment_count: Option<i64>,
+}
+
+impl BeatDetails {
+    pub fn new(beat: Beat, display: DisplayOptions) -> Self {
+        let key_low_confidence = beat
+            .key_confidence
+            .is_some_and(|confidence| confidence < LOW_KEY_CONFIDENCE);
+        let key_display = beat.musical_key.as_deref().map(|key| {
+            MusicalKey::parse(key)
+                .map(|parsed| parsed.format(display.key_notation))
+                .unwrap_or_else(|| key.to_string())
+        });
+        let gain = gain::track_gain(beat.integrated_loudness, beat.true_peak, display.loudness_target);
+        BeatDetails {
+            beat,
+            key_low_confidence,
+            key_display,
+            gain,
+            cue_points: None,
+            comment_count: None,
+            unresolved_comment_count: None,
+        }
+    }
+
+    pub fn with_cue_points(mut self, cue_points: Vec<CuePoint>) -> Self {
+        self.cue_points = Some(cue_points);
+        self
+    }
+
+    pub fn with_comment_counts(mut self, counts: CommentCounts) -> Self {
+        self.comment_count = Some(counts.total);
+        self.unresolved_comment_count = Some(counts.unresolved);
+        self
+    }
 }
 
 #[derive(Insertable)]
@@ -53,7 +134,12 @@ pub struct NewBeat<'a> {
     pub file_path: &'a str,
     pub bpm: Option<f64>,
     pub musical_key: Option<&'a str>,
+    pub bpm_source: Option<&'a str>,
+    pub key_source: Option<&'a str>,
+    pub version: Option<&'a str>,
     pub date_created: NaiveDateTime,
+    pub analysis_status: &'a str,
+    pub file_size: Option<i64>,
 }
 
 #[derive(Queryable, Selectable, Debug)]
@@ -70,6 +156,38 @@ pub struct BeatCollection {
     pub date_created: NaiveDateTime,
 }
 
+// Collection as it is sent to the frontend, with its combined loudness and gain.
+#[derive(serde::Serialize, Debug)]
+pub struct BeatCollectionDetails {
+    #[serde(flatten)]
+    pub collection: BeatCollection,
+    // LUFS of the whole set played through
+    pub loudness: Option<f64>,
+    // Playback gain in dB for every track in the set, like ReplayGain album gain
+    pub gain: Option<f64>,
+}
+
+impl BeatCollectionDetails {
+    pub fn new(collection: BeatCollection, tracks: &[TrackLoudness], loudness_target: f64) -> Self {
+        let (loudness, gain) = gain::collection_gain(tracks, loudness_target);
+        BeatCollectionDetails {
+            collection,
+            loudness,
+            gain,
+        }
+    }
+}
+
+// The loudness columns of a beat, for computing collection gain.
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = crate::schema::beats)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct TrackLoudness {
+    pub duration: Option<i32>,
+    pub integrated_loudness: Option<f64>,
+    pub true_peak: Option<f64>,
+}
+
 #[derive(Insertable)]
 #[diesel(table_name = crate::schema::beat_collection)]
 pub struct NewBeatCollection<'a> {
@@ -87,15 +205,242 @@ pub struct NewBeatCollection<'a> {
 pub struct NewBeatInCollection<'a> {
     pub beat_id: &'a i32,
     pub beat_collection_id: &'a i32,
+    pub position: &'a i32,
 }
 
+// One occurrence of a beat in a set. A beat can be in the same set more than once.
 #[derive(Queryable, Selectable, Debug)]
 #[diesel(table_name = crate::schema::set_beat)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 #[derive(serde::Serialize)]
 pub struct BeatInCollection {
-    pub beat_id: i32,
+    pub id: i32,
     pub beat_collection_id: i32,
+    pub beat_id: i32,
+    // Running order within the set, starting at 0
+    pub position: i32,
+}
+
+// A beat at its place in a set, as returned by get_beats_in_collection.
+#[derive(serde::Serialize, Debug)]
+pub struct SetEntry {
+    #[serde(flatten)]
+    pub beat: BeatDetails,
+    // set_beat.id, tells repeated occurrences of the same beat apart
+    pub entry_id: i32,
+    pub position: i32,
+    // Performance plan for this entry, None until one is saved
+    pub details: Option<SetEntryDetails>,
+}
+
+impl SetEntry {
+    pub fn new(entry: BeatInCollection, beat: BeatDetails, details: Option<SetEntryDetails>) -> Self {
+        SetEntry {
+            beat,
+            entry_id: entry.id,
+            position: entry.position,
+            details,
+        }
+    }
+}
+
+// Performance plan for one set entry: how to mix it and into the next track.
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = crate::schema::set_entry_details)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+#[derive(serde::Serialize)]
+pub struct SetEntryDetails {
+    pub set_beat_id: i32,
+    // Transition into the next track, e.g. "blend", "cut", "echo_out"
+    pub transition_style: Option<String>,
+    // Seconds into the track
+    pub mix_in_time: Option<f64>,
+    pub mix_out_time: Option<f64>,
+    // Semitones
+    pub key_shift: Option<i32>,
+    // Percent
+    pub pitch_adjust: Option<f64>,
+    pub note: Option<String>,
+    pub date_updated: NaiveDateTime,
+}
+
+// Saved as a whole, so fields left as None clear the stored value.
+#[derive(serde::Deserialize, Insertable, AsChangeset)]
+#[diesel(table_name = crate::schema::set_entry_details)]
+#[diesel(treat_none_as_null = true)]
+pub struct SetEntryDetailsChangeset {
+    pub set_beat_id: i32,
+    pub transition_style: Option<String>,
+    pub mix_in_time: Option<f64>,
+    pub mix_out_time: Option<f64>,
+    pub key_shift: Option<i32>,
+    pub pitch_adjust: Option<f64>,
+    pub note: Option<String>,
+}
+
+// A cue marker or saved loop on a beat.
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = crate::schema::cue_points)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+#[derive(serde::Serialize)]
+pub struct CuePoint {
+    pub id: i32,
+    pub beat_id: i32,
+    // One of cue_kind
+    pub kind: String,
+    pub position_ms: i32,
+    pub loop_length_ms: Option<i32>,
+    // Pad 0 to 7 for hot cues
+    pub hot_cue_index: Option<i32>,
+    pub label: Option<String>,
+    // "#RRGGBB"
+    pub color: Option<String>,
+    pub date_created: NaiveDateTime,
+}
+
+// Saved as a whole, so fields left as None clear the stored value.
+#[derive(serde::Deserialize, Insertable, AsChangeset, Clone, Debug)]
+#[diesel(table_name = crate::schema::cue_points)]
+#[diesel(treat_none_as_null = true)]
+pub struct CuePointChangeset {
+    pub beat_id: i32,
+    pub kind: String,
+    pub position_ms: i32,
+    pub loop_length_ms: Option<i32>,
+    // Hot cues without one get the first free pad
+    pub hot_cue_index: Option<i32>,
+    pub label: Option<String>,
+    pub color: Option<String>,
+}
+
+// A review comment pinned to a moment in a beat, e.g. "kick too loud" at 1:12.
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = crate::schema::beat_comments)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+#[derive(serde::Serialize)]
+pub struct BeatComment {
+    pub id: i32,
+    pub beat_id: i32,
+    // Milliseconds into the track
+    pub timestamp_ms: i32,
+    pub author: String,
+    pub body: String,
+    pub resolved: bool,
+    pub date_created: NaiveDateTime,
+}
+
+#[derive(serde::Deserialize, Insertable, Debug)]
+#[diesel(table_name = crate::schema::beat_comments)]
+pub struct NewBeatComment {
+    pub beat_id: i32,
+    pub timestamp_ms: i32,
+    pub author: String,
+    pub body: String,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CommentCounts {
+    pub total: i64,
+    pub unresolved: i64,
+}
+
+// Values for cue_points.kind
+pub mod cue_kind {
+    pub const MEMORY: &str = "memory";
+    pub const HOT: &str = "hot";
+    pub const LOOP: &str = "loop";
+}
+
+// Hot cue pads per beat, the same as on most controllers
+pub const HOT_CUE_COUNT: i32 = 8;
+
+// Beat grid of one beat, see beat_grid.rs. A grid with one tempo is just
+// first_downbeat and bpm, tempo_map holds the markers as JSON otherwise.
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Debug)]
+#[diesel(table_name = crate::schema::beat_grids)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+#[diesel(treat_none_as_null = true)]
+pub struct BeatGridRecord {
+    pub beat_id: i32,
+    // Seconds
+    pub first_downbeat: f64,
+    pub bpm: f64,
+    pub beats_per_bar: i32,
+    pub tempo_map: Option<String>,
+    pub duration: f64,
+    // One of value_source
+    pub source: String,
+    pub date_updated: NaiveDateTime,
+}
+
+impl BeatGridRecord {
+    pub fn new(beat_id: i32, grid: &BeatGrid, source: &str) -> Self {
+        BeatGridRecord {
+            beat_id,
+            first_downbeat: grid.first_downbeat(),
+            bpm: grid.bpm(),
+            beats_per_bar: grid.beats_per_bar as i32,
+            tempo_map: (!grid.is_constant()).then(|| serde_json::to_string(&grid.markers).unwrap_or_default()),
+            duration: grid.duration,
+            source: source.to_string(),
+            date_updated: Utc::now().naive_utc(),
+        }
+    }
+
+    pub fn grid(&self) -> BeatGrid {
+        let beats_per_bar = self.beats_per_bar.max(1) as u32;
+        let markers: Option<Vec<TempoMarker>> = self
+            .tempo_map
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok())
+            .filter(|markers: &Vec<TempoMarker>| !markers.is_empty());
+        match markers {
+            Some(markers) => BeatGrid {
+                markers,
+                beats_per_bar,
+                duration: self.duration,
+            },
+            None => BeatGrid::constant(self.first_downbeat, self.bpm, beats_per_bar, self.duration),
+        }
+    }
+}
+
+// Acoustic fingerprint of one beat, see audio_analysis/fingerprint.rs.
+#[derive(Queryable, Selectable, Insertable, AsChangeset, Debug)]
+#[diesel(table_name = crate::schema::beat_fingerprints)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct BeatFingerprint {
+    pub beat_id: i32,
+    pub fingerprint: Vec<u8>,
+    pub date_updated: NaiveDateTime,
+}
+
+// Beat grid as it is sent to the frontend, with every beat laid out.
+#[derive(serde::Serialize, Debug)]
+pub struct BeatGridDetails {
+    pub beat_id: i32,
+    #[serde(flatten)]
+    pub grid: BeatGrid,
+    pub first_downbeat: f64,
+    pub bpm: f64,
+    pub source: String,
+    pub date_updated: NaiveDateTime,
+    pub beats: Vec<GridBeat>,
+}
+
+impl BeatGridDetails {
+    pub fn new(record: BeatGridRecord) -> Self {
+        let grid = record.grid();
+        BeatGridDetails {
+            beat_id: record.beat_id,
+            first_downbeat: record.first_downbeat,
+            bpm: (record.bpm * 100.0).round() / 100.0,
+            source: record.source,
+            date_updated: record.date_updated,
+            beats: grid.beats(),
+            grid,
+        }
+    }
 }
 
 #[derive(serde::Deserialize)]#[derive(AsChangeset)]
@@ -107,4 +452,139 @@ pub struct BeatChangeset {
     pub musical_key: Option<String>,
     pub duration: Option<i32>,
     pub artist: Option<String>,
-}
\ No newline at end of file
+    pub version: Option<String>,
+}
+
+// Columns filled in from a beat's file name by parse_filenames
+#[derive(AsChangeset, Default, Debug)]
+#[diesel(table_name = crate::schema::beats)]
+pub struct FilenameChangeset {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub bpm: Option<f64>,
+    pub bpm_source: Option<String>,
+    pub musical_key: Option<String>,
+    pub key_source: Option<String>,
+    // Some(None) clears the confidence of an analyzed key being replaced
+    pub key_confidence: Option<Option<f64>>,
+    pub version: Option<String>,
+}
+
+impl FilenameChangeset {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none()
+            && self.artist.is_none()
+            && self.bpm.is_none()
+            && self.musical_key.is_none()
+            && self.version.is_none()
+    }
+}
+
+// What parse_filenames filled in
+#[derive(serde::Serialize, Default, Debug)]
+pub struct FilenameParseSummary {
+    pub beats: usize,
+    pub titles: usize,
+    pub artists: usize,
+    pub bpms: usize,
+    pub keys: usize,
+    pub versions: usize,
+}
+
+// Columns written back after a beat is analyzed. Fields left as None are skipped,
+// so a backend that can't detect something doesn't wipe an existing value.
+#[derive(AsChangeset, Default)]
+#[diesel(table_name = crate::schema::beats)]
+pub struct BeatAnalysisChangeset {
+    pub bpm: Option<f64>,
+    pub bpm_raw: Option<f64>,
+    pub musical_key: Option<String>,
+    pub key_confidence: Option<f64>,
+    pub bpm_source: Option<String>,
+    pub key_source: Option<String>,
+    pub integrated_loudness: Option<f64>,
+    pub short_term_loudness_max: Option<f64>,
+    pub loudness_range: Option<f64>,
+    pub true_peak: Option<f64>,
+}
+
+impl BeatAnalysisChangeset {
+    pub fn is_empty(&self) -> bool {
+        self.bpm.is_none()
+            && self.bpm_raw.is_none()
+            && self.musical_key.is_none()
+            && self.key_confidence.is_none()
+            && self.integrated_loudness.is_none()
+            && self.short_term_loudness_max.is_none()
+            && self.loudness_range.is_none()
+            && self.true_peak.is_none()
+    }
+}
+
+// Values for analysis_jobs.status and beats.analysis_status
+pub mod job_status {
+    pub const PENDING: &str = "pending";
+    pub const RUNNING: &str = "running";
+    pub const COMPLETE: &str = "complete";
+    pub const FAILED: &str = "failed";
+    pub const CANCELED: &str = "canceled";
+}
+
+// Values for beats.file_status
+pub mod file_status {
+    pub const OK: &str = "ok";
+    // file_path no longer points to a file
+    pub const MISSING: &str = "missing";
+}
+
+// Where a beat's file is and how to recognize it, for finding missing files.
+#[derive(Queryable, Selectable, Debug)]
+#[diesel(table_name = crate::schema::beats)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct BeatFile {
+    pub id: i32,
+    pub file_path: String,
+    pub file_size: Option<i64>,
+    pub audio_hash: Option<String>,
+    pub file_status: String,
+}
+
+// Changes to where a beat's file is. Fields left as None are skipped.
+#[derive(AsChangeset, Default, Debug)]
+#[diesel(table_name = crate::schema::beats)]
+pub struct BeatFileChangeset {
+    pub file_path: Option<String>,
+    pub file_size: Option<i64>,
+    pub file_status: Option<String>,
+}
+
+#[derive(Queryable, Selectable, Debug, Clone)]
+#[diesel(table_name = crate::schema::analysis_jobs)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+#[derive(serde::Serialize)]
+pub struct AnalysisJob {
+    pub id: i32,
+    pub beat_id: i32,
+    pub status: String,
+    pub error: Option<String>,
+    pub date_created: NaiveDateTime,
+    pub date_updated: NaiveDateTime,
+    pub force: bool,
+}
+
+// Values for beats.bpm_source and beats.key_source
+pub mod value_source {
+    pub const ANALYSIS: &str = "analysis";
+    pub const MANUAL: &str = "manual";
+    // Read from another DJ application's library or tags
+    pub const IMPORTED: &str = "imported";
+    // Read from the file's own metadata on import, e.g. TBPM and TKEY
+    pub const TAG: &str = "tag";
+    // Parsed from the file name, e.g. "142bpm" in "Dark Trap - 142bpm.wav"
+    pub const FILENAME: &str = "filename";
+
+    // Values from anywhere but analysis are only replaced by forced analysis
+    pub fn is_user_provided(source: Option<&str>) -> bool {
+        matches!(source, Some(MANUAL | IMPORTED | TAG | FILENAME))
+    }
+}
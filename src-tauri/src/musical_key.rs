@@ -0,0 +1,320 @@
+/*
+ * musical_key.rs
+ *
+ * Parsing and formatting for beats.musical_key. Keys are stored in one
+ * normalized form ("F# Minor") no matter how they were entered, and can be
+ * displayed in whichever notation the user prefers.
+ *
+ * Accepted notations:
+ * - Standard with sharps or flats: "F# Minor", "Gb minor", "F#m", "Fmin", "Bbmaj", "C"
+ * - Camelot: "11A" (minor), "8B" (major)
+ * - Open Key: "4m" (minor), "1d" (major)
+ *
+ * relation_to classifies how two keys mix on the Camelot wheel.
+ */
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const SHARP_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+const FLAT_NAMES: [&str; 12] = [
+    "C", "Db", "D", "Eb", "E", "F", "Gb", "G", "Ab", "A", "Bb", "B",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MusicalKey {
+    // Pitch class of the tonic, 0 = C through 11 = B
+    pub tonic: u8,
+    pub mode: Mode,
+}
+
+// How the key of a following track relates to the current one, in terms of
+// the moves DJs use on the Camelot wheel.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyRelation {
+    // Same number and letter
+    SameKey,
+    // Same number, other letter (A minor <-> C major)
+    Relative,
+    // One step around the wheel, same letter
+    Adjacent,
+    // Two steps up the wheel, a whole tone up
+    EnergyBoost,
+    // Seven steps up the wheel, a semitone up
+    SemitoneBoost,
+}
+
+impl KeyRelation {
+    // How smooth the transition sounds, 1.0 for the same key down to 0.5
+    pub fn score(&self) -> f64 {
+        match self {
+            KeyRelation::SameKey => 1.0,
+            KeyRelation::Relative => 0.9,
+            KeyRelation::Adjacent => 0.8,
+            KeyRelation::EnergyBoost => 0.6,
+            KeyRelation::SemitoneBoost => 0.5,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyNotation {
+    // "F# Minor", the normalized form stored in the database
+    #[default]
+    Standard,
+    // "Gb Minor"
+    Flats,
+    // "11A"
+    Camelot,
+    // "4m"
+    OpenKey,
+}
+
+impl MusicalKey {
+    pub fn new(tonic: u8, mode: Mode) -> Self {
+        MusicalKey {
+            tonic: tonic % 12,
+            mode,
+        }
+    }
+
+    // Parses any of the supported notations, ignoring case and surrounding whitespace.
+    pub fn parse(text: &str) -> Option<MusicalKey> {
+        let text = text.trim();
+        if text.is_empty() {
+            return None;
+        }
+        parse_wheel(text).or_else(|| parse_standard(text))
+    }
+
+    // Position on the Camelot wheel, 1 to 12. Relative major and minor share a number.
+    pub fn camelot_number(&self) -> u8 {
+        // Moving one step around the wheel is a fifth (7 semitones), and C major sits at 8B
+        let major_tonic = match self.mode {
+            Mode::Major => self.tonic,
+            Mode::Minor => (self.tonic + 3) % 12,
+        };
+        ((major_tonic as u32 * 7 + 7) % 12 + 1) as u8
+    }
+
+    // Open Key is the Camelot wheel rotated so C major is 1d.
+    pub fn open_key_number(&self) -> u8 {
+        (self.camelot_number() + 4) % 12 + 1
+    }
+
+    // The harmonic relation from this key to `next`, or None if they clash.
+    pub fn relation_to(&self, next: &MusicalKey) -> Option<KeyRelation> {
+        let steps = (next.camelot_number() as i32 - self.camelot_number() as i32).rem_euclid(12);
+        if self.mode != next.mode {
+            return (steps == 0).then_some(KeyRelation::Relative);
+        }
+        match steps {
+            0 => Some(KeyRelation::SameKey),
+            1 | 11 => Some(KeyRelation::Adjacent),
+            2 => Some(KeyRelation::EnergyBoost),
+            7 => Some(KeyRelation::SemitoneBoost),
+            _ => None,
+        }
+    }
+
+    pub fn format(&self, notation: KeyNotation) -> String {
+        match notation {
+            KeyNotation::Standard => format!("{} {}", SHARP_NAMES[self.tonic as usize], self.mode_name()),
+            KeyNotation::Flats => format!("{} {}", FLAT_NAMES[self.tonic as usize], self.mode_name()),
+            KeyNotation::Camelot => {
+                let letter = match self.mode {
+                    Mode::Major => 'B',
+                    Mode::Minor => 'A',
+                };
+                format!("{}{}", self.camelot_number(), letter)
+            }
+            KeyNotation::OpenKey => {
+                let letter = match self.mode {
+                    Mode::Major => 'd',
+                    Mode::Minor => 'm',
+                };
+                format!("{}{}", self.open_key_number(), letter)
+            }
+        }
+    }
+
+    // Short form used in file tags (ID3 TKEY) and by Rekordbox, e.g. "F#m" or "C".
+    pub fn tag_name(&self) -> String {
+        match self.mode {
+            Mode::Major => SHARP_NAMES[self.tonic as usize].to_string(),
+            Mode::Minor => format!("{}m", SHARP_NAMES[self.tonic as usize]),
+        }
+    }
+
+    fn mode_name(&self) -> &'static str {
+        match self.mode {
+            Mode::Major => "Major",
+            Mode::Minor => "Minor",
+        }
+    }
+
+    fn from_camelot(number: u8, mode: Mode) -> MusicalKey {
+        // Invert camelot_number: tonic of the major key is 7 * (number - 8) mod 12
+        let major_tonic = ((number as i32 - 8) * 7).rem_euclid(12) as u8;
+        let tonic = match mode {
+            Mode::Major => major_tonic,
+            Mode::Minor => (major_tonic + 9) % 12,
+        };
+        MusicalKey::new(tonic, mode)
+    }
+}
+
+// The normalized form stored in the database.
+impl fmt::Display for MusicalKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.format(KeyNotation::Standard))
+    }
+}
+
+// Camelot ("11A") or Open Key ("4m") notation.
+fn parse_wheel(text: &str) -> Option<MusicalKey> {
+    let digits: String = text.chars().take_while(|c| c.is_ascii_digit()).collect();
+    let number: u8 = digits.parse().ok()?;
+    if !(1..=12).contains(&number) {
+        return None;
+    }
+
+    let suffix = text[digits.len()..].trim().to_lowercase();
+    match suffix.as_str() {
+        "a" => Some(MusicalKey::from_camelot(number, Mode::Minor)),
+        "b" => Some(MusicalKey::from_camelot(number, Mode::Major)),
+        // Open Key 1 is Camelot 8
+        "m" => Some(MusicalKey::from_camelot((number + 6) % 12 + 1, Mode::Minor)),
+        "d" => Some(MusicalKey::from_camelot((number + 6) % 12 + 1, Mode::Major)),
+        _ => None,
+    }
+}
+
+// Note name with optional accidental and mode, e.g. "F# Minor", "Gbm", "Fmin", "C".
+fn parse_standard(text: &str) -> Option<MusicalKey> {
+    let mut chars = text.chars();
+
+    let base: i32 = match chars.next()?.to_ascii_uppercase() {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+
+    let rest: String = chars.collect();
+    let mut rest = rest.trim_start();
+    let mut offset = 0;
+    for (accidental, shift) in [
+        ("#", 1),
+        ("♯", 1),
+        ("sharp", 1),
+        ("b", -1),
+        ("♭", -1),
+        ("flat", -1),
+    ] {
+        // Right after a note letter, "b" is always a flat rather than the note B
+        if rest.to_lowercase().starts_with(accidental) {
+            offset = shift;
+            rest = rest[accidental.len()..].trim_start();
+            break;
+        }
+    }
+
+    let mode = match rest.trim() {
+        "" | "M" => Mode::Major,
+        "m" | "-" => Mode::Minor,
+        other => match other.to_lowercase().trim_end_matches('.') {
+            "maj" | "major" | "ma" | "dur" => Mode::Major,
+            "min" | "minor" | "mi" | "moll" => Mode::Minor,
+            _ => return None,
+        },
+    };
+
+    Some(MusicalKey::new((base + offset).rem_euclid(12) as u8, mode))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn every_key() -> Vec<MusicalKey> {
+        (0..12)
+            .flat_map(|tonic| [MusicalKey::new(tonic, Mode::Major), MusicalKey::new(tonic, Mode::Minor)])
+            .collect()
+    }
+
+    #[test]
+    fn parses_standard_notation() {
+        let f_sharp_minor = MusicalKey::new(6, Mode::Minor);
+        for text in ["F# Minor", "f# minor", "Gb minor", "F#m", "Gbm", " F♯ min ", "F sharp minor"] {
+            assert_eq!(MusicalKey::parse(text), Some(f_sharp_minor), "{}", text);
+        }
+        assert_eq!(MusicalKey::parse("C"), Some(MusicalKey::new(0, Mode::Major)));
+        assert_eq!(MusicalKey::parse("Bbmaj"), Some(MusicalKey::new(10, Mode::Major)));
+        assert_eq!(MusicalKey::parse("Fmin"), Some(MusicalKey::new(5, Mode::Minor)));
+        assert_eq!(MusicalKey::parse("B"), Some(MusicalKey::new(11, Mode::Major)));
+    }
+
+    #[test]
+    fn parses_camelot_and_open_key() {
+        assert_eq!(MusicalKey::parse("8B"), Some(MusicalKey::new(0, Mode::Major)));
+        assert_eq!(MusicalKey::parse("8A"), Some(MusicalKey::new(9, Mode::Minor)));
+        assert_eq!(MusicalKey::parse("11a"), Some(MusicalKey::new(6, Mode::Minor)));
+        assert_eq!(MusicalKey::parse("1d"), Some(MusicalKey::new(0, Mode::Major)));
+        assert_eq!(MusicalKey::parse("4m"), Some(MusicalKey::new(6, Mode::Minor)));
+        assert_eq!(MusicalKey::parse("12 B"), Some(MusicalKey::new(4, Mode::Major)));
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        for text in ["", "   ", "H", "13A", "0B", "11C", "F# dorian"] {
+            assert_eq!(MusicalKey::parse(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn formats_in_every_notation() {
+        let key = MusicalKey::new(6, Mode::Minor);
+        assert_eq!(key.format(KeyNotation::Standard), "F# Minor");
+        assert_eq!(key.format(KeyNotation::Flats), "Gb Minor");
+        assert_eq!(key.format(KeyNotation::Camelot), "11A");
+        assert_eq!(key.format(KeyNotation::OpenKey), "4m");
+        assert_eq!(key.tag_name(), "F#m");
+        assert_eq!(key.to_string(), "F# Minor");
+    }
+
+    #[test]
+    fn every_notation_round_trips() {
+        let notations = [KeyNotation::Standard, KeyNotation::Flats, KeyNotation::Camelot, KeyNotation::OpenKey];
+        for key in every_key() {
+            for notation in notations {
+                let text = key.format(notation);
+                assert_eq!(MusicalKey::parse(&text), Some(key), "{:?} as {}", notation, text);
+            }
+            assert_eq!(MusicalKey::parse(&key.tag_name()), Some(key));
+        }
+    }
+
+    #[test]
+    fn wheel_numbers_are_unique_per_mode() {
+        for mode in [Mode::Major, Mode::Minor] {
+            let mut camelot: Vec<u8> = (0..12).map(|tonic| MusicalKey::new(tonic, mode).camelot_number()).collect();
+            camelot.sort_unstable();
+            assert_eq!(camelot, (1..=12).collect::<Vec<u8>>());
+        }
+    }
+}
@@ -0,0 +1,19 @@
+/*
+ * paths.rs
+ *
+ * Comparing stored file paths. Beats can be added from Windows and Unix style
+ * paths and DJ software writes forward slashes, so paths are compared in one
+ * form with forward slashes only.
+ */
+
+// Normalizes a path for matching, the same form file URLs from DJ software decode to
+pub fn comparable_path(file_path: &str) -> String {
+    file_path.replace('\\', "/")
+}
+
+// Whether file_path lies inside folder, both already passed through comparable_path
+pub fn is_under(file_path: &str, folder: &str) -> bool {
+    file_path
+        .strip_prefix(folder.trim_end_matches('/'))
+        .is_some_and(|rest| rest.starts_with('/'))
+}
@@ -1,5 +1,50 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    analysis_jobs (id) {
+        id -> Integer,
+        beat_id -> Integer,
+        status -> Text,
+        error -> Nullable<Text>,
+        date_created -> Timestamp,
+        date_updated -> Timestamp,
+        force -> Bool,
+    }
+}
+
+diesel::table! {
+    beat_comments (id) {
+        id -> Integer,
+        beat_id -> Integer,
+        timestamp_ms -> Integer,
+        author -> Text,
+        body -> Text,
+        resolved -> Bool,
+        date_created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    beat_fingerprints (beat_id) {
+        beat_id -> Integer,
+        fingerprint -> Binary,
+        date_updated -> Timestamp,
+    }
+}
+
+diesel::table! {
+    beat_grids (beat_id) {
+        beat_id -> Integer,
+        first_downbeat -> Double,
+        bpm -> Double,
+        beats_per_bar -> Integer,
+        tempo_map -> Nullable<Text>,
+        duration -> Double,
+        source -> Text,
+        date_updated -> Timestamp,
+    }
+}
+
 diesel::table! {
     beat_collection (id) {
         id -> Integer,
@@ -30,21 +75,75 @@ diesel::table! {
         bpm -> Nullable<Double>,
         musical_key -> Nullable<Text>,
         date_created -> Timestamp,
+        key_confidence -> Nullable<Double>,
+        analysis_status -> Text,
+        bpm_source -> Nullable<Text>,
+        key_source -> Nullable<Text>,
+        bpm_raw -> Nullable<Double>,
+        integrated_loudness -> Nullable<Double>,
+        short_term_loudness_max -> Nullable<Double>,
+        loudness_range -> Nullable<Double>,
+        true_peak -> Nullable<Double>,
+        version -> Nullable<Text>,
+        file_status -> Text,
+        audio_hash -> Nullable<Text>,
+        file_size -> Nullable<BigInt>,
+    }
+}
+
+diesel::table! {
+    cue_points (id) {
+        id -> Integer,
+        beat_id -> Integer,
+        kind -> Text,
+        position_ms -> Integer,
+        loop_length_ms -> Nullable<Integer>,
+        hot_cue_index -> Nullable<Integer>,
+        label -> Nullable<Text>,
+        color -> Nullable<Text>,
+        date_created -> Timestamp,
     }
 }
 
 diesel::table! {
-    set_beat (beat_collection_id, beat_id) {
+    set_beat (id) {
+        id -> Integer,
         beat_collection_id -> Integer,
         beat_id -> Integer,
+        position -> Integer,
+    }
+}
+
+diesel::table! {
+    set_entry_details (set_beat_id) {
+        set_beat_id -> Integer,
+        transition_style -> Nullable<Text>,
+        mix_in_time -> Nullable<Double>,
+        mix_out_time -> Nullable<Double>,
+        key_shift -> Nullable<Integer>,
+        pitch_adjust -> Nullable<Double>,
+        note -> Nullable<Text>,
+        date_updated -> Timestamp,
     }
 }
 
+diesel::joinable!(analysis_jobs -> beats (beat_id));
+diesel::joinable!(beat_comments -> beats (beat_id));
+diesel::joinable!(beat_fingerprints -> beats (beat_id));
+diesel::joinable!(beat_grids -> beats (beat_id));
+diesel::joinable!(cue_points -> beats (beat_id));
 diesel::joinable!(set_beat -> beat_collection (beat_collection_id));
 diesel::joinable!(set_beat -> beats (beat_id));
+diesel::joinable!(set_entry_details -> set_beat (set_beat_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    analysis_jobs,
     beat_collection,
+    beat_comments,
+    beat_fingerprints,
+    beat_grids,
     beats,
+    cue_points,
     set_beat,
+    set_entry_details,
 );
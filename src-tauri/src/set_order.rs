@@ -0,0 +1,285 @@
+/*
+ * set_order.rs
+ *
+ * Suggests a running order for the beats in a collection. Every pair of beats
+ * gets a transition cost from harmonic::Transition, and a heuristic search
+ * looks for the path through all of them with the lowest total cost: a greedy
+ * nearest neighbour path from every possible opener, then 2-opt improvement of
+ * the best one.
+ *
+ * Tempo stands in for energy when following a target energy curve, since it's
+ * the one energy related value every analyzed beat has.
+ */
+
+use serde::{Deserialize, Serialize};
+
+use crate::harmonic::Transition;
+use crate::models::{Beat, BeatDetails, BeatInCollection, DisplayOptions};
+
+// How much missing the energy curve at one position costs compared to one bad transition
+const ENERGY_WEIGHT: f64 = 0.5;
+
+// Where the Peak curve tops out, as a fraction of the set
+const PEAK_AT: f64 = 2.0 / 3.0;
+
+// 2-opt converges well before this on realistic set sizes
+const MAX_IMPROVEMENT_PASSES: usize = 50;
+
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EnergyCurve {
+    // Slowest to fastest
+    Rising,
+    // Fastest to slowest
+    Falling,
+    // Builds up to a peak two thirds in, then winds down
+    Peak,
+}
+
+impl EnergyCurve {
+    // Target energy from 0.0 to 1.0 at a point in the set, progress 0.0 being the opener
+    fn target(&self, progress: f64) -> f64 {
+        match self {
+            EnergyCurve::Rising => progress,
+            EnergyCurve::Falling => 1.0 - progress,
+            EnergyCurve::Peak if progress < PEAK_AT => progress / PEAK_AT,
+            EnergyCurve::Peak => (1.0 - progress) / (1.0 - PEAK_AT),
+        }
+    }
+}
+
+pub struct SetOrderOptions {
+    // Beat ids pinned to the start and end of the set
+    pub opener: Option<i32>,
+    pub closer: Option<i32>,
+    pub energy_curve: Option<EnergyCurve>,
+    // Percent, see harmonic::bpm_difference_percent
+    pub bpm_tolerance: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct SetOrderSuggestion {
+    // set_beat ids in the suggested order, ready to pass to reorder_set
+    pub entry_ids: Vec<i32>,
+    pub beats: Vec<BeatDetails>,
+    // Transitions between two known keys that don't mix
+    pub key_clashes: usize,
+    // Transitions with a tempo change bigger than the tolerance
+    pub bpm_jumps: usize,
+    // Mean transition score, 0.0 to 1.0
+    pub score: f64,
+}
+
+struct CostModel {
+    transitions: Vec<Vec<Transition>>,
+    costs: Vec<Vec<f64>>,
+    // Tempo scaled to 0.0 to 1.0 across the set, None without a bpm
+    energy: Vec<Option<f64>>,
+    energy_curve: Option<EnergyCurve>,
+}
+
+impl CostModel {
+    fn new(beats: &[Beat], options: &SetOrderOptions) -> Self {
+        let transitions: Vec<Vec<Transition>> = beats
+            .iter()
+            .map(|from| beats.iter().map(|to| Transition::between(from, to)).collect())
+            .collect();
+        let costs = transitions
+            .iter()
+            .zip(beats)
+            .map(|(row, from)| {
+                row.iter()
+                    .zip(beats)
+                    // A beat that's in the set twice shouldn't be played back to back
+                    .map(|(t, to)| if from.id == to.id { 1.0 } else { 1.0 - t.score(options.bpm_tolerance) })
+                    .collect()
+            })
+            .collect();
+
+        let bpms: Vec<f64> = beats.iter().filter_map(|b| b.bpm).collect();
+        let min_bpm = bpms.iter().cloned().fold(f64::INFINITY, f64::min);
+        let max_bpm = bpms.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let energy = beats
+            .iter()
+            .map(|beat| {
+                beat.bpm.map(|bpm| {
+                    if max_bpm > min_bpm {
+                        (bpm - min_bpm) / (max_bpm - min_bpm)
+                    } else {
+                        0.5
+                    }
+                })
+            })
+            .collect();
+
+        CostModel {
+            transitions,
+            costs,
+            energy,
+            energy_curve: options.energy_curve,
+        }
+    }
+
+    // How far the beat at `index` is from the energy curve when played at `position`
+    fn energy_cost(&self, index: usize, position: usize, length: usize) -> f64 {
+        let (Some(curve), Some(energy)) = (self.energy_curve, self.energy[index]) else {
+            return 0.0;
+        };
+        let progress = if length > 1 {
+            position as f64 / (length - 1) as f64
+        } else {
+            0.0
+        };
+        ENERGY_WEIGHT * (energy - curve.target(progress)).abs()
+    }
+
+    fn path_cost(&self, path: &[usize]) -> f64 {
+        let transitions: f64 = path.windows(2).map(|pair| self.costs[pair[0]][pair[1]]).sum();
+        let energy: f64 = path
+            .iter()
+            .enumerate()
+            .map(|(position, &index)| self.energy_cost(index, position, path.len()))
+            .sum();
+        transitions + energy
+    }
+
+    // Nearest neighbour path from `start`, with the closer (if any) appended at the end
+    fn greedy_path(&self, start: usize, closer: Option<usize>) -> Vec<usize> {
+        let length = self.costs.len();
+        let mut path = vec![start];
+        let mut remaining: Vec<usize> = (0..length)
+            .filter(|&i| i != start && Some(i) != closer)
+            .collect();
+
+        while !remaining.is_empty() {
+            let current = *path.last().unwrap();
+            let position = path.len();
+            let (best, _) = remaining
+                .iter()
+                .enumerate()
+                .map(|(slot, &next)| {
+                    (slot, self.costs[current][next] + self.energy_cost(next, position, length))
+                })
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap();
+            path.push(remaining.swap_remove(best));
+        }
+
+        if let Some(closer) = closer {
+            if closer != start {
+                path.push(closer);
+            }
+        }
+        path
+    }
+
+    // Reverses segments of the path while that lowers the total cost, leaving
+    // pinned ends in place.
+    fn improve(&self, path: &mut [usize], pin_first: bool, pin_last: bool) {
+        if path.len() < 3 {
+            return;
+        }
+        let first = usize::from(pin_first);
+        let last = path.len() - 1 - usize::from(pin_last);
+        let mut best_cost = self.path_cost(path);
+
+        for _ in 0..MAX_IMPROVEMENT_PASSES {
+            let mut improved = false;
+            for i in first..last {
+                for j in i + 1..=last {
+                    path[i..=j].reverse();
+                    let cost = self.path_cost(path);
+                    if cost < best_cost - 1e-9 {
+                        best_cost = cost;
+                        improved = true;
+                    } else {
+                        path[i..=j].reverse();
+                    }
+                }
+            }
+            if !improved {
+                break;
+            }
+        }
+    }
+}
+
+// Suggests a play order for the entries of a set that keeps key clashes and
+// tempo jumps to a minimum, following the energy curve if one is given. An
+// opener or closer that is in the set more than once pins its first occurrence.
+pub fn suggest_set_order(
+    entries: Vec<(BeatInCollection, Beat)>,
+    options: &SetOrderOptions,
+    display: DisplayOptions,
+) -> Result<SetOrderSuggestion, String> {
+    let (entries, beats): (Vec<BeatInCollection>, Vec<Beat>) = entries.into_iter().unzip();
+    let find = |id: Option<i32>| -> Result<Option<usize>, String> {
+        id.map(|id| {
+            beats
+                .iter()
+                .position(|beat| beat.id == id)
+                .ok_or_else(|| format!("Beat {} is not in this collection", id))
+        })
+        .transpose()
+    };
+    let opener = find(options.opener)?;
+    let closer = find(options.closer)?;
+    if beats.len() > 1 && opener.is_some() && opener == closer {
+        return Err("The opener and closer must be different beats".to_string());
+    }
+
+    let model = CostModel::new(&beats, options);
+    let mut best: Option<(f64, Vec<usize>)> = None;
+    let starts: Vec<usize> = match opener {
+        Some(opener) => vec![opener],
+        None => (0..beats.len())
+            .filter(|&i| beats.len() == 1 || Some(i) != closer)
+            .collect(),
+    };
+    for start in starts {
+        let path = model.greedy_path(start, closer);
+        let cost = model.path_cost(&path);
+        if !matches!(&best, Some((best_cost, _)) if *best_cost <= cost) {
+            best = Some((cost, path));
+        }
+    }
+
+    let mut path = best.map(|(_, path)| path).unwrap_or_default();
+    model.improve(&mut path, opener.is_some(), closer.is_some());
+
+    let transitions: Vec<&Transition> = path
+        .windows(2)
+        .map(|pair| &model.transitions[pair[0]][pair[1]])
+        .collect();
+    let key_clashes = transitions
+        .iter()
+        .filter(|t| t.keys_known && t.key_relation.is_none())
+        .count();
+    let bpm_jumps = transitions
+        .iter()
+        .filter(|t| t.bpm_difference.is_some_and(|d| d > options.bpm_tolerance))
+        .count();
+    let score = if transitions.is_empty() {
+        1.0
+    } else {
+        transitions
+            .iter()
+            .map(|t| t.score(options.bpm_tolerance))
+            .sum::<f64>()
+            / transitions.len() as f64
+    };
+
+    let mut slots: Vec<Option<Beat>> = beats.into_iter().map(Some).collect();
+    let ordered: Vec<Beat> = path.iter().filter_map(|&i| slots[i].take()).collect();
+
+    Ok(SetOrderSuggestion {
+        entry_ids: path.iter().map(|&i| entries[i].id).collect(),
+        beats: ordered
+            .into_iter()
+            .map(|beat| BeatDetails::new(beat, display))
+            .collect(),
+        key_clashes,
+        bpm_jumps,
+        score: (score * 1000.0).round() / 1000.0,
+    })
+}
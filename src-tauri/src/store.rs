@@ -8,6 +8,8 @@
  * Functions:
  * - resolve_project_root_path: Constructs the path for the settings file, ensuring
  *   the directory exists.
+ * - read_settings: Reads settings.json synchronously for use inside the backend.
+ * - write_settings: Writes settings.json synchronously for use inside the backend.
  * - load_settings: Loads user settings from settings.json or creates the file with
  *   default settings if it doesn't exist.
  * - save_settings: Saves user settings to settings.json.
@@ -16,18 +18,127 @@
  */
 
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fs::{self, read_to_string, write};
 use std::path::PathBuf;
 
+use crate::models::DisplayOptions;
+use crate::musical_key::KeyNotation;
+
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct Settings {
     theme: String,
+    // Which analyzer backend the analysis queue workers run, results are saved by complete_analysis_job
+    pub analyzer: AnalyzerBackend,
+    // Interpreter and script used by the python analyzer backend, the script
+    // shipped with the app when no path is set
+    pub python_executable: String,
+    pub python_script_path: Option<String>,
+    // Detected tempos are doubled or halved into this range
+    pub bpm_range: BpmRange,
+    // Per genre overrides of bpm_range, keyed by genre name (case insensitive)
+    pub genre_bpm_ranges: BTreeMap<String, BpmRange>,
+    // How far apart in percent two tempos can be and still count as compatible
+    pub bpm_tolerance_percent: f64,
+    // Notation used for key_display when the frontend doesn't ask for one
+    pub key_notation: KeyNotation,
+    // Integrated loudness in LUFS that playback gain levels tracks to
+    pub loudness_target: f64,
+    // Write edits made in update_beat back into the tags of the file
+    pub write_tags_on_edit: bool,
+    // Tried in order on the names of imported files, e.g. "{title} - {bpm}bpm - {key}"
+    pub filename_templates: Vec<String>,
+    // Folders whose new audio files are imported automatically. Changed through
+    // set_watch_folders, which also restarts the watcher.
+    pub watch_folders: Vec<String>,
+    // How alike two fingerprints must be, 0.0 to 1.0, for find_duplicates to
+    // group beats that aren't identical
+    pub duplicate_similarity: f64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct BpmRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+impl BpmRange {
+    // Doubles or halves a tempo until it lands inside the range. When the range
+    // is narrower than an octave, the octave closest to the range wins.
+    pub fn fold(&self, bpm: f64) -> f64 {
+        if !bpm.is_finite() || bpm <= 0.0 || self.min <= 0.0 || self.max < self.min {
+            return bpm;
+        }
+
+        let mut folded = bpm;
+        while folded > self.max {
+            folded /= 2.0;
+        }
+        while folded < self.min {
+            folded *= 2.0;
+        }
+        if folded > self.max && folded - self.max > self.min - folded / 2.0 {
+            folded /= 2.0;
+        }
+        folded
+    }
+}
+
+impl Settings {
+    // The preferred tempo range for a genre, falling back to the global range.
+    pub fn bpm_range_for(&self, genre: Option<&str>) -> BpmRange {
+        genre
+            .map(|g| g.trim().to_lowercase())
+            .and_then(|g| {
+                self.genre_bpm_ranges
+                    .iter()
+                    .find(|(name, _)| name.trim().to_lowercase() == g)
+                    .map(|(_, range)| *range)
+            })
+            .unwrap_or(self.bpm_range)
+    }
+
+    // Display options for BeatDetails, with the key notation the frontend asked for if any.
+    pub fn display_options(&self, key_notation: Option<KeyNotation>) -> DisplayOptions {
+        DisplayOptions {
+            key_notation: key_notation.unwrap_or(self.key_notation),
+            loudness_target: self.loudness_target,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AnalyzerBackend {
+    Native,
+    Python,
+    Null,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
             theme: "light".to_string(),
+            analyzer: AnalyzerBackend::Native,
+            python_executable: if cfg!(target_os = "windows") {
+                "python".to_string()
+            } else {
+                "python3".to_string()
+            },
+            python_script_path: None,
+            bpm_range: BpmRange {
+                min: 80.0,
+                max: 160.0,
+            },
+            genre_bpm_ranges: BTreeMap::new(),
+            bpm_tolerance_percent: 6.0,
+            key_notation: KeyNotation::Standard,
+            loudness_target: -14.0,
+            write_tags_on_edit: false,
+            filename_templates: Vec::new(),
+            watch_folders: Vec::new(),
+            duplicate_similarity: 0.75,
         }
     }
 }
@@ -48,6 +159,23 @@ fn resolve_project_root_path(file_name: &str) -> PathBuf {
     settings_path
 }
 
+// Reads the current settings without creating the file, falling back to defaults.
+// Used by backend code that needs settings outside of a tauri command.
+pub fn read_settings() -> Settings {
+    let settings_path = resolve_project_root_path("settings.json");
+    match read_to_string(settings_path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap_or_default(),
+        Err(_) => Settings::default(),
+    }
+}
+
+// Writes the settings for backend code that changes them outside of save_settings.
+pub fn write_settings(settings: &Settings) -> Result<(), String> {
+    let settings_path = resolve_project_root_path("settings.json");
+    let contents = serde_json::to_string(settings).map_err(|e| e.to_string())?;
+    write(settings_path, contents).map_err(|e| e.to_string())
+}
+
 // Loads user settings from settings.json or creates one with the default settings if none exist
 #[tauri::command]
 pub async fn load_settings() -> Result<Settings, String> {
@@ -60,18 +188,13 @@ pub async fn load_settings() -> Result<Settings, String> {
         write(&settings_path, contents).expect("Failed to create settings file");
     }
 
-    match read_to_string(settings_path) {
-        Ok(contents) => Ok(serde_json::from_str(&contents).unwrap_or_default()),
-        Err(_) => Ok(Settings::default()),
-    }
+    Ok(read_settings())
 }
 
 // Saves user settings to settings.json using the settings_path
 #[tauri::command]
 pub async fn save_settings(settings: Settings) -> Result<(), String> {
-    let settings_path = resolve_project_root_path("settings.json");
-    let contents = serde_json::to_string(&settings).unwrap();
-    write(settings_path, contents).map_err(|e| e.to_string())
+    write_settings(&settings)
 }
 
 // Returns the path to the settings.json file
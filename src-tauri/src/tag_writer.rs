@@ -0,0 +1,529 @@
+/*
+ * tag_writer.rs
+ *
+ * Writes what the library knows about a beat back into the tags of its file,
+ * so title, artist, genre, comments, bpm, key and artwork travel with the file
+ * when it leaves the app. Each format gets its native tags: ID3v2.4 in MP3, WAV
+ * and AIFF, Vorbis comments in FLAC and iTunes atoms in MP4.
+ *
+ * Only fields the library has a value for are written and nothing is ever
+ * cleared, so tags the library doesn't track survive. A dry run compares the
+ * library with the file and reports the changes without touching it.
+ *
+ * Before a file is changed, everything in it except the audio payload is copied
+ * to the backup directory: "<name>.bin" holds those bytes back to back and
+ * "<name>.json" lists where in the original file each of them came from.
+ *
+ * Submodules:
+ * - flac: Vorbis comment and PICTURE blocks of FLAC files.
+ * - mp4: The ilst atom of MP4 and M4A files.
+ */
+
+mod flac;
+mod mp4;
+
+use chrono::Local;
+use serde::Serialize;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use crate::models::Beat;
+use crate::musical_key::MusicalKey;
+use crate::tags;
+
+// What gets written into a file. None leaves the field in the file as it is.
+#[derive(Default, Debug, Clone)]
+pub struct TagValues {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub genre: Option<String>,
+    pub comments: Option<String>,
+    pub bpm: Option<f64>,
+    pub musical_key: Option<MusicalKey>,
+    pub artwork: Option<Vec<u8>>,
+}
+
+impl TagValues {
+    // The values of a beat, with the image stored as its cover art if any.
+    pub fn new(beat: &Beat, artwork: Option<Vec<u8>>) -> Self {
+        let text = |value: Option<&str>| value.map(str::trim).filter(|v| !v.is_empty()).map(String::from);
+        TagValues {
+            title: text(Some(&beat.title)),
+            artist: text(beat.artist.as_deref()),
+            genre: text(beat.genre.as_deref()),
+            comments: text(beat.comments.as_deref()),
+            bpm: beat.bpm.filter(|bpm| bpm.is_finite() && *bpm > 0.0),
+            musical_key: beat.musical_key.as_deref().and_then(MusicalKey::parse),
+            artwork,
+        }
+    }
+
+    // BPM as written into tags, "128" or "127.5"
+    fn bpm_text(&self) -> Option<String> {
+        self.bpm.map(|bpm| {
+            let text = format!("{:.2}", bpm);
+            text.trim_end_matches('0').trim_end_matches('.').to_string()
+        })
+    }
+
+    fn key_text(&self) -> Option<String> {
+        self.musical_key.map(|key| key.tag_name())
+    }
+}
+
+// One field that differs between the library and the file
+#[derive(Serialize, Debug, Clone)]
+pub struct TagChange {
+    pub field: String,
+    // What the file has now, None when the field is missing
+    pub old: Option<String>,
+    pub new: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TagWriteReport {
+    pub beat_id: i32,
+    pub file_path: String,
+    pub changes: Vec<TagChange>,
+    // False for dry runs, files that are already up to date and failures
+    pub written: bool,
+    pub backup_path: Option<String>,
+    pub error: Option<String>,
+}
+
+// Where a backed up range came from in the original file
+#[derive(Serialize, Debug)]
+struct BackupSegment {
+    offset: u64,
+    length: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct BackupManifest {
+    file_path: String,
+    file_size: u64,
+    date_created: String,
+    segments: Vec<BackupSegment>,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum TagFormat {
+    Mp3,
+    // WAV and AIFF, ID3 in a chunk of its own
+    Id3Chunk,
+    Flac,
+    Mp4,
+}
+
+impl TagFormat {
+    fn of(path: &Path) -> Result<Self, Box<dyn Error>> {
+        let extension = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match extension.as_str() {
+            "mp3" => Ok(TagFormat::Mp3),
+            "wav" | "aif" | "aiff" => Ok(TagFormat::Id3Chunk),
+            "flac" => Ok(TagFormat::Flac),
+            "m4a" | "mp4" => Ok(TagFormat::Mp4),
+            _ => Err(format!("Writing tags to .{} files is not supported", extension).into()),
+        }
+    }
+}
+
+pub struct TagWriter {
+    // None when the platform has no app data directory. Nothing is written
+    // then, as there is nowhere to put the backup.
+    backup_dir: Option<PathBuf>,
+}
+
+impl TagWriter {
+    pub fn new(backup_dir: Option<PathBuf>) -> Self {
+        TagWriter { backup_dir }
+    }
+
+    // Compares the tags of the beat's file with `values` and, unless this is
+    // a dry run, writes the ones that differ. Failures end up in the report.
+    pub fn write(&self, beat: &Beat, values: &TagValues, dry_run: bool) -> TagWriteReport {
+        let mut report = TagWriteReport {
+            beat_id: beat.id,
+            file_path: beat.file_path.clone(),
+            changes: Vec::new(),
+            written: false,
+            backup_path: None,
+            error: None,
+        };
+        if let Err(e) = self.write_file(&mut report, values, dry_run) {
+            report.error = Some(e.to_string());
+        }
+        report
+    }
+
+    fn write_file(&self, report: &mut TagWriteReport, values: &TagValues, dry_run: bool) -> Result<(), Box<dyn Error>> {
+        let path = Path::new(&report.file_path);
+        let format = TagFormat::of(path)?;
+        // MP4 only holds whole bpm, compared that way it doesn't show up as a change every time
+        let rounded;
+        let values = if format == TagFormat::Mp4 {
+            rounded = TagValues {
+                bpm: values.bpm.map(f64::round),
+                ..values.clone()
+            };
+            &rounded
+        } else {
+            values
+        };
+        report.changes = diff(&report.file_path, values)?;
+        if dry_run || report.changes.is_empty() {
+            return Ok(());
+        }
+
+        let backup_dir = self.backup_dir.as_ref().ok_or("No directory to back up tags to")?;
+        let backup_path = back_up(backup_dir, report.beat_id, path, format)?;
+        report.backup_path = Some(backup_path.to_string_lossy().into_owned());
+
+        // Only the changed fields, so an unchanged picture isn't rewritten
+        let changed = |field: &str| report.changes.iter().any(|change| change.field == field);
+        let values = TagValues {
+            title: values.title.clone().filter(|_| changed("title")),
+            artist: values.artist.clone().filter(|_| changed("artist")),
+            genre: values.genre.clone().filter(|_| changed("genre")),
+            comments: values.comments.clone().filter(|_| changed("comments")),
+            bpm: values.bpm.filter(|_| changed("bpm")),
+            musical_key: values.musical_key.filter(|_| changed("musical_key")),
+            artwork: values.artwork.clone().filter(|_| changed("artwork")),
+        };
+        match format {
+            TagFormat::Mp3 | TagFormat::Id3Chunk => write_id3(path, &values)?,
+            TagFormat::Flac => flac::write(path, &values)?,
+            TagFormat::Mp4 => mp4::write(path, &values)?,
+        }
+        report.written = true;
+        Ok(())
+    }
+}
+
+// The fields of `values` the file doesn't already have.
+fn diff(file_path: &str, values: &TagValues) -> Result<Vec<TagChange>, Box<dyn Error>> {
+    let current = tags::read_tags(file_path)?;
+    let mut changes = Vec::new();
+    let mut compare = |field: &str, old: Option<String>, new: Option<String>, same: bool| {
+        if let Some(new) = new.filter(|_| !same) {
+            changes.push(TagChange {
+                field: field.to_string(),
+                old,
+                new,
+            });
+        }
+    };
+
+    compare("title", current.title.clone(), values.title.clone(), current.title == values.title);
+    compare("artist", current.artist.clone(), values.artist.clone(), current.artist == values.artist);
+    compare("genre", current.genre.clone(), values.genre.clone(), current.genre == values.genre);
+    compare(
+        "comments",
+        current.comments.clone(),
+        values.comments.clone(),
+        current.comments == values.comments,
+    );
+    let same_bpm = matches!((current.bpm, values.bpm), (Some(old), Some(new)) if (old - new).abs() < 0.005);
+    compare("bpm", current.bpm.map(|bpm| bpm.to_string()), values.bpm_text(), same_bpm);
+    let current_key = current.musical_key.as_deref().and_then(MusicalKey::parse);
+    compare(
+        "musical_key",
+        current_key.map(|key| key.tag_name()),
+        values.key_text(),
+        current_key == values.musical_key,
+    );
+
+    if let Some(artwork) = &values.artwork {
+        let embedded = tags::read_artwork(file_path)?;
+        compare(
+            "artwork",
+            embedded.as_deref().map(describe_image),
+            Some(describe_image(artwork)),
+            embedded.as_ref() == Some(artwork),
+        );
+    }
+    Ok(changes)
+}
+
+// "JPEG image, 82 KB"
+fn describe_image(data: &[u8]) -> String {
+    let format = image::guess_format(data)
+        .ok()
+        .and_then(|format| format.extensions_str().first())
+        .map(|extension| extension.to_uppercase())
+        .unwrap_or_else(|| "Unknown".to_string());
+    format!("{} image, {} KB", format, data.len().div_ceil(1024))
+}
+
+fn mime_type(data: &[u8]) -> &'static str {
+    image::guess_format(data)
+        .map(|format| format.to_mime_type())
+        .unwrap_or("image/jpeg")
+}
+
+fn write_id3(path: &Path, values: &TagValues) -> Result<(), Box<dyn Error>> {
+    use id3::frame::{Comment, Picture, PictureType};
+    use id3::TagLike;
+
+    let mut tag = match id3::Tag::read_from_path(path) {
+        Ok(tag) => tag,
+        Err(e) if matches!(e.kind, id3::ErrorKind::NoTag) => id3::Tag::new(),
+        Err(e) => return Err(e.into()),
+    };
+    if let Some(title) = &values.title {
+        tag.set_title(title.as_str());
+    }
+    if let Some(artist) = &values.artist {
+        tag.set_artist(artist.as_str());
+    }
+    if let Some(genre) = &values.genre {
+        tag.set_genre(genre.as_str());
+    }
+    if let Some(comments) = &values.comments {
+        // The comment without a description is the one players show
+        tag.remove_comment(Some(""), None);
+        tag.add_frame(Comment {
+            lang: "eng".to_string(),
+            description: String::new(),
+            text: comments.clone(),
+        });
+    }
+    if let Some(bpm) = values.bpm_text() {
+        tag.set_text("TBPM", bpm);
+    }
+    if let Some(key) = values.key_text() {
+        tag.set_text("TKEY", key);
+    }
+    if let Some(artwork) = &values.artwork {
+        tag.remove_picture_by_type(PictureType::CoverFront);
+        tag.add_frame(Picture {
+            mime_type: mime_type(artwork).to_string(),
+            picture_type: PictureType::CoverFront,
+            description: String::new(),
+            data: artwork.clone(),
+        });
+    }
+    tag.write_to_path(path, id3::Version::Id3v24)?;
+    Ok(())
+}
+
+// Copies everything but the audio payload of the file to the backup directory
+// and returns the path of the manifest.
+fn back_up(backup_dir: &Path, beat_id: i32, path: &Path, format: TagFormat) -> Result<PathBuf, Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+    let segments = match format {
+        TagFormat::Mp3 => id3v2_segments(&mut file)?,
+        TagFormat::Id3Chunk => chunk_segments(&mut file, file_size)?,
+        TagFormat::Flac => flac::header_segments(&mut file)?,
+        TagFormat::Mp4 => mp4::header_segments(&mut file, file_size)?,
+    };
+
+    let mut data = Vec::new();
+    for segment in &segments {
+        file.seek(SeekFrom::Start(segment.offset))?;
+        (&mut file).take(segment.length).read_to_end(&mut data)?;
+    }
+
+    let now = Local::now();
+    let name = format!("{}-{}", beat_id, now.format("%Y%m%d-%H%M%S%.3f"));
+    let manifest = BackupManifest {
+        file_path: path.to_string_lossy().into_owned(),
+        file_size,
+        date_created: now.to_rfc3339(),
+        segments,
+    };
+    fs::create_dir_all(backup_dir)?;
+    fs::write(backup_dir.join(format!("{}.bin", name)), data)?;
+    let manifest_path = backup_dir.join(format!("{}.json", name));
+    fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)?;
+    Ok(manifest_path)
+}
+
+// The ID3v2 tag at the start of an MP3 file, nothing when it has none
+fn id3v2_segments(file: &mut File) -> Result<Vec<BackupSegment>, Box<dyn Error>> {
+    let mut header = [0u8; 10];
+    if file.read_exact(&mut header).is_err() || &header[..3] != b"ID3" {
+        return Ok(Vec::new());
+    }
+    // Sizes are syncsafe, 7 bits per byte. A footer adds another 10 bytes.
+    let size = header[6..10].iter().fold(0u64, |size, byte| size << 7 | (byte & 0x7f) as u64);
+    let footer = if header[5] & 0x10 != 0 { 10 } else { 0 };
+    Ok(vec![BackupSegment {
+        offset: 0,
+        length: 10 + size + footer,
+    }])
+}
+
+// Every chunk of a WAV or AIFF file except the sample data
+fn chunk_segments(file: &mut File, file_size: u64) -> Result<Vec<BackupSegment>, Box<dyn Error>> {
+    let mut header = [0u8; 12];
+    file.read_exact(&mut header)?;
+    let big_endian = match &header[..4] {
+        b"RIFF" => false,
+        b"FORM" => true,
+        _ => return Err("Not a WAV or AIFF file".into()),
+    };
+
+    let mut segments = vec![BackupSegment { offset: 0, length: 12 }];
+    let mut offset = 12;
+    while offset + 8 <= file_size {
+        let mut chunk = [0u8; 8];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut chunk)?;
+        let size_bytes = [chunk[4], chunk[5], chunk[6], chunk[7]];
+        let size = if big_endian {
+            u32::from_be_bytes(size_bytes)
+        } else {
+            u32::from_le_bytes(size_bytes)
+        } as u64;
+        // Chunks are padded to an even length
+        let length = (8 + size + size % 2).min(file_size - offset);
+        if matches!(&chunk[..4], b"data" | b"SSND") {
+            segments.push(BackupSegment { offset, length: 8 });
+        } else {
+            segments.push(BackupSegment { offset, length });
+        }
+        offset += length;
+    }
+    Ok(segments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use id3::TagLike;
+    use std::io::Write;
+
+    // Bytes of the sample data, 0.1 s of a ramp at 8 kHz
+    const DATA_LENGTH: u32 = 1600;
+
+    // 16 bit mono PCM wav with a LIST chunk after the samples, like many
+    // editors write them
+    fn wav_file(name: &str) -> PathBuf {
+        let list = b"INFOISFT\x08\0\0\0editor\0\0";
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&(36 + DATA_LENGTH + 8 + list.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&16u32.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&8000u32.to_le_bytes());
+        bytes.extend_from_slice(&16000u32.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&DATA_LENGTH.to_le_bytes());
+        for n in 0..DATA_LENGTH as i16 / 2 {
+            bytes.extend_from_slice(&(n * 20).to_le_bytes());
+        }
+        bytes.extend_from_slice(b"LIST");
+        bytes.extend_from_slice(&(list.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(list);
+
+        let path = std::env::temp_dir().join(format!("beatbank-tags-{}.wav", name));
+        File::create(&path).unwrap().write_all(&bytes).unwrap();
+        path
+    }
+
+    fn report(path: &Path) -> TagWriteReport {
+        TagWriteReport {
+            beat_id: 7,
+            file_path: path.to_string_lossy().into_owned(),
+            changes: Vec::new(),
+            written: false,
+            backup_path: None,
+            error: None,
+        }
+    }
+
+    fn values() -> TagValues {
+        TagValues {
+            title: Some("Dark Trap".to_string()),
+            artist: Some("Someone".to_string()),
+            bpm: Some(142.5),
+            musical_key: MusicalKey::parse("F# Minor"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn id3_in_wav_round_trips() {
+        let path = wav_file("id3");
+        let original = fs::read(&path).unwrap();
+        let backup_dir = std::env::temp_dir().join("beatbank-tags-id3-backups");
+        let writer = TagWriter::new(Some(backup_dir.clone()));
+
+        let mut dry_run = report(&path);
+        writer.write_file(&mut dry_run, &values(), true).unwrap();
+        assert!(!dry_run.written);
+        assert_eq!(fs::read(&path).unwrap(), original);
+        let fields: Vec<&str> = dry_run.changes.iter().map(|change| change.field.as_str()).collect();
+        assert_eq!(fields, ["title", "artist", "bpm", "musical_key"]);
+
+        let mut written = report(&path);
+        writer.write_file(&mut written, &values(), false).unwrap();
+        assert!(written.written);
+        let tags = tags::read_tags(&written.file_path).unwrap();
+        assert_eq!(tags.title.as_deref(), Some("Dark Trap"));
+        assert_eq!(tags.artist.as_deref(), Some("Someone"));
+        assert_eq!(tags.bpm, Some(142.5));
+        assert_eq!(tags.musical_key.as_deref(), Some("F# Minor"));
+        let id3 = id3::Tag::read_from_path(&path).unwrap();
+        assert_eq!(id3.get("TKEY").and_then(|frame| frame.content().text()), Some("F#m"));
+        // Only the RIFF size changed ahead of the new chunk, the samples come through untouched
+        let samples_end = 44 + DATA_LENGTH as usize;
+        assert!(fs::read(&path).unwrap()[8..samples_end] == original[8..samples_end]);
+
+        // Nothing left to change the second time
+        let mut again = report(&path);
+        writer.write_file(&mut again, &values(), false).unwrap();
+        assert!(again.changes.is_empty() && !again.written);
+
+        fs::remove_file(&path).ok();
+        fs::remove_dir_all(&backup_dir).ok();
+    }
+
+    #[test]
+    fn backup_holds_everything_but_the_samples() {
+        let path = wav_file("backup");
+        let original = fs::read(&path).unwrap();
+        let backup_dir = std::env::temp_dir().join("beatbank-tags-backup-backups");
+        let mut report = report(&path);
+        TagWriter::new(Some(backup_dir.clone()))
+            .write_file(&mut report, &values(), false)
+            .unwrap();
+
+        let manifest_path = PathBuf::from(report.backup_path.unwrap());
+        let manifest: serde_json::Value = serde_json::from_slice(&fs::read(&manifest_path).unwrap()).unwrap();
+        assert_eq!(manifest["file_path"], report.file_path.as_str());
+        assert_eq!(manifest["file_size"], original.len() as u64);
+        let segments: Vec<(u64, u64)> = manifest["segments"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|segment| (segment["offset"].as_u64().unwrap(), segment["length"].as_u64().unwrap()))
+            .collect();
+        // RIFF header, fmt, the data chunk's header and LIST
+        let list_offset = 44 + DATA_LENGTH as u64;
+        let list_length = original.len() as u64 - list_offset;
+        assert_eq!(segments, [(0, 12), (12, 24), (36, 8), (list_offset, list_length)]);
+
+        // The .bin holds those ranges of the original back to back
+        let bin = fs::read(manifest_path.with_extension("bin")).unwrap();
+        let expected: Vec<u8> = segments
+            .iter()
+            .flat_map(|(offset, length)| original[*offset as usize..(offset + length) as usize].to_vec())
+            .collect();
+        assert_eq!(bin, expected);
+
+        fs::remove_file(&path).ok();
+        fs::remove_dir_all(&backup_dir).ok();
+    }
+}
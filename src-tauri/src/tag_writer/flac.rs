@@ -0,0 +1,348 @@
+/*
+ * flac.rs
+ *
+ * FLAC metadata: "fLaC" followed by metadata blocks, each with a 4 byte header
+ * holding a last block flag, the block type and a 24 bit length. Fields go into
+ * the VORBIS_COMMENT block and the front cover into a PICTURE block.
+ *
+ * When the new blocks fit in the space of the old ones and their padding, the
+ * file is updated in place. Otherwise it's rewritten with some padding so the
+ * next edit fits.
+ */
+
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::{mime_type, BackupSegment, TagValues};
+use crate::atomic_file::replace_file;
+
+const STREAMINFO: u8 = 0;
+const PADDING: u8 = 1;
+const VORBIS_COMMENT: u8 = 4;
+const PICTURE: u8 = 6;
+
+// PICTURE type of a front cover
+const FRONT_COVER: u32 = 3;
+// Padding left after the metadata when the file has to be rewritten
+const NEW_PADDING: usize = 8192;
+const MAX_BLOCK_LENGTH: usize = (1 << 24) - 1;
+
+struct Block {
+    kind: u8,
+    data: Vec<u8>,
+}
+
+// Metadata blocks and where the audio frames start
+fn read_blocks(file: &mut File) -> Result<(Vec<Block>, u64), Box<dyn Error>> {
+    let mut marker = [0u8; 4];
+    file.read_exact(&mut marker)?;
+    if &marker != b"fLaC" {
+        return Err("Not a FLAC file".into());
+    }
+
+    let mut blocks = Vec::new();
+    let mut end = 4;
+    loop {
+        let mut header = [0u8; 4];
+        file.read_exact(&mut header)?;
+        let length = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let mut data = vec![0u8; length];
+        file.read_exact(&mut data)?;
+        end += 4 + length as u64;
+        blocks.push(Block {
+            kind: header[0] & 0x7f,
+            data,
+        });
+        if header[0] & 0x80 != 0 {
+            break;
+        }
+    }
+    if blocks.first().map(|block| block.kind) != Some(STREAMINFO) {
+        return Err("FLAC file doesn't start with a STREAMINFO block".into());
+    }
+    Ok((blocks, end))
+}
+
+// Everything ahead of the audio frames
+pub(super) fn header_segments(file: &mut File) -> Result<Vec<BackupSegment>, Box<dyn Error>> {
+    let (_, end) = read_blocks(file)?;
+    Ok(vec![BackupSegment { offset: 0, length: end }])
+}
+
+pub(super) fn write(path: &Path, values: &TagValues) -> Result<(), Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let (mut blocks, audio_start) = read_blocks(&mut file)?;
+    drop(file);
+
+    set_comments(&mut blocks, values)?;
+    if let Some(artwork) = &values.artwork {
+        blocks.retain(|block| !(block.kind == PICTURE && picture_type(&block.data) == Some(FRONT_COVER)));
+        // Right after the comments, where other taggers put it too
+        let position = blocks
+            .iter()
+            .position(|block| block.kind == VORBIS_COMMENT)
+            .map_or(blocks.len(), |i| i + 1);
+        blocks.insert(
+            position,
+            Block {
+                kind: PICTURE,
+                data: picture_block(artwork)?,
+            },
+        );
+    }
+    blocks.retain(|block| block.kind != PADDING);
+    if let Some(block) = blocks.iter().find(|block| block.data.len() > MAX_BLOCK_LENGTH) {
+        return Err(format!("FLAC metadata block of {} bytes is too large", block.data.len()).into());
+    }
+
+    let metadata_length: usize = 4 + blocks.iter().map(|block| 4 + block.data.len()).sum::<usize>();
+    let available = audio_start as usize;
+    // A padding block needs at least its 4 byte header
+    if metadata_length == available || metadata_length + 4 <= available {
+        let padding = available.saturating_sub(metadata_length + 4);
+        let metadata = encode(&blocks, (metadata_length < available).then_some(padding));
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.write_all(&metadata)?;
+        file.sync_all()?;
+        return Ok(());
+    }
+
+    let metadata = encode(&blocks, Some(NEW_PADDING));
+    replace_file(path, |partial| {
+        let mut original = File::open(path)?;
+        original.seek(SeekFrom::Start(audio_start))?;
+        partial.write_all(&metadata)?;
+        io::copy(&mut original, partial)?;
+        Ok(())
+    })
+}
+
+// "fLaC" and the blocks, with a padding block of `padding` bytes at the end
+fn encode(blocks: &[Block], padding: Option<usize>) -> Vec<u8> {
+    let padding_block = padding.map(|length| Block {
+        kind: PADDING,
+        data: vec![0; length],
+    });
+    let all: Vec<&Block> = blocks.iter().chain(padding_block.as_ref()).collect();
+
+    let mut data = b"fLaC".to_vec();
+    for (i, block) in all.iter().enumerate() {
+        let last = if i + 1 == all.len() { 0x80 } else { 0 };
+        data.push(last | block.kind);
+        data.extend_from_slice(&(block.data.len() as u32).to_be_bytes()[1..]);
+        data.extend_from_slice(&block.data);
+    }
+    data
+}
+
+// Replaces the comments for the fields being written, adding a
+// VORBIS_COMMENT block when the file has none.
+fn set_comments(blocks: &mut Vec<Block>, values: &TagValues) -> Result<(), Box<dyn Error>> {
+    // Every name a field is found under is dropped, the first one is written
+    let fields: [(&[&str], Option<String>); 6] = [
+        (&["TITLE"], values.title.clone()),
+        (&["ARTIST"], values.artist.clone()),
+        (&["GENRE"], values.genre.clone()),
+        (&["COMMENT", "DESCRIPTION"], values.comments.clone()),
+        (&["BPM"], values.bpm_text()),
+        (&["INITIALKEY", "KEY"], values.key_text()),
+    ];
+    if fields.iter().all(|(_, value)| value.is_none()) {
+        return Ok(());
+    }
+
+    let index = match blocks.iter().position(|block| block.kind == VORBIS_COMMENT) {
+        Some(index) => index,
+        None => {
+            blocks.insert(
+                1,
+                Block {
+                    kind: VORBIS_COMMENT,
+                    data: encode_comments("beatbank", &[]),
+                },
+            );
+            1
+        }
+    };
+    let (vendor, mut comments) = parse_comments(&blocks[index].data)?;
+    for (names, value) in &fields {
+        let Some(value) = value else {
+            continue;
+        };
+        comments.retain(|comment| {
+            let name = comment.split('=').next().unwrap_or_default();
+            !names.iter().any(|n| n.eq_ignore_ascii_case(name))
+        });
+        comments.push(format!("{}={}", names[0], value));
+    }
+    blocks[index].data = encode_comments(&vendor, &comments);
+    Ok(())
+}
+
+// Vendor string and "NAME=value" comments. Lengths are little endian here,
+// unlike the rest of FLAC.
+fn parse_comments(data: &[u8]) -> Result<(String, Vec<String>), Box<dyn Error>> {
+    let mut position = 0;
+    let vendor = read_string(data, &mut position)?;
+    let count = read_u32_le(data, &mut position)?;
+    let comments = (0..count)
+        .map(|_| read_string(data, &mut position))
+        .collect::<Result<_, _>>()?;
+    Ok((vendor, comments))
+}
+
+fn encode_comments(vendor: &str, comments: &[String]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&(vendor.len() as u32).to_le_bytes());
+    data.extend_from_slice(vendor.as_bytes());
+    data.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        data.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        data.extend_from_slice(comment.as_bytes());
+    }
+    data
+}
+
+fn read_u32_le(data: &[u8], position: &mut usize) -> Result<u32, Box<dyn Error>> {
+    let bytes = data.get(*position..*position + 4).ok_or("Truncated Vorbis comment")?;
+    *position += 4;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_string(data: &[u8], position: &mut usize) -> Result<String, Box<dyn Error>> {
+    let length = read_u32_le(data, position)? as usize;
+    let text = data
+        .get(*position..*position + length)
+        .ok_or("Truncated Vorbis comment")?;
+    *position += length;
+    Ok(String::from_utf8_lossy(text).into_owned())
+}
+
+fn picture_type(data: &[u8]) -> Option<u32> {
+    let bytes = data.get(..4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+// A front cover PICTURE block. Its header carries the image's size and bit depth.
+fn picture_block(artwork: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let image = image::load_from_memory(artwork)?;
+    let mime_type = mime_type(artwork);
+
+    let mut data = Vec::new();
+    data.extend_from_slice(&FRONT_COVER.to_be_bytes());
+    data.extend_from_slice(&(mime_type.len() as u32).to_be_bytes());
+    data.extend_from_slice(mime_type.as_bytes());
+    // No description
+    data.extend_from_slice(&0u32.to_be_bytes());
+    data.extend_from_slice(&image.width().to_be_bytes());
+    data.extend_from_slice(&image.height().to_be_bytes());
+    data.extend_from_slice(&(image.color().bits_per_pixel() as u32).to_be_bytes());
+    // Colors used, only for indexed images
+    data.extend_from_slice(&0u32.to_be_bytes());
+    data.extend_from_slice(&(artwork.len() as u32).to_be_bytes());
+    data.extend_from_slice(artwork);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::musical_key::MusicalKey;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // Stands in for the audio frames, only that its bytes survive matters
+    const AUDIO: &[u8] = b"\xff\xf8 audio frames that have to come through untouched";
+
+    // "fLaC", a STREAMINFO, comments with a title and an album, `padding`
+    // bytes of padding and AUDIO
+    fn flac_file(name: &str, padding: Option<usize>) -> PathBuf {
+        let comments = ["TITLE=Old".to_string(), "ALBUM=Kept".to_string()];
+        let blocks = [
+            Block {
+                kind: STREAMINFO,
+                data: vec![0; 34],
+            },
+            Block {
+                kind: VORBIS_COMMENT,
+                data: encode_comments("reference", &comments),
+            },
+        ];
+        let mut bytes = encode(&blocks, padding);
+        bytes.extend_from_slice(AUDIO);
+        let path = std::env::temp_dir().join(format!("beatbank-flac-{}.flac", name));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    // The blocks, the comments and what follows the metadata
+    fn read_back(path: &Path) -> (Vec<Block>, Vec<String>, Vec<u8>) {
+        let (blocks, audio_start) = read_blocks(&mut File::open(path).unwrap()).unwrap();
+        let comment_block = blocks.iter().find(|block| block.kind == VORBIS_COMMENT).unwrap();
+        let (_, comments) = parse_comments(&comment_block.data).unwrap();
+        let audio = fs::read(path).unwrap()[audio_start as usize..].to_vec();
+        fs::remove_file(path).ok();
+        (blocks, comments, audio)
+    }
+
+    fn values() -> TagValues {
+        TagValues {
+            title: Some("New".to_string()),
+            bpm: Some(140.0),
+            musical_key: MusicalKey::parse("F#m"),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fits_into_the_padding() {
+        let path = flac_file("in-place", Some(1024));
+        let length = fs::metadata(&path).unwrap().len();
+        write(&path, &values()).unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().len(), length);
+        let (blocks, comments, audio) = read_back(&path);
+        assert_eq!(comments, ["ALBUM=Kept", "TITLE=New", "BPM=140", "INITIALKEY=F#m"]);
+        assert_eq!(audio, AUDIO);
+        // What the comments grew by came out of the padding
+        let padding = blocks.last().unwrap();
+        assert_eq!(padding.kind, PADDING);
+        assert!(padding.data.len() < 1024);
+    }
+
+    #[test]
+    fn rewrites_with_new_padding_when_it_doesnt_fit() {
+        let path = flac_file("rewrite", None);
+        write(&path, &values()).unwrap();
+
+        assert!(!path.with_extension("partial").exists());
+        let (blocks, comments, audio) = read_back(&path);
+        assert_eq!(comments, ["ALBUM=Kept", "TITLE=New", "BPM=140", "INITIALKEY=F#m"]);
+        assert_eq!(audio, AUDIO);
+        let kinds: Vec<u8> = blocks.iter().map(|block| block.kind).collect();
+        assert_eq!(kinds, [STREAMINFO, VORBIS_COMMENT, PADDING]);
+        assert_eq!(blocks[2].data.len(), NEW_PADDING);
+    }
+
+    #[test]
+    fn adds_comments_to_a_file_without_them() {
+        let mut bytes = encode(
+            &[Block {
+                kind: STREAMINFO,
+                data: vec![0; 34],
+            }],
+            Some(256),
+        );
+        bytes.extend_from_slice(AUDIO);
+        let path = std::env::temp_dir().join("beatbank-flac-no-comments.flac");
+        fs::write(&path, bytes).unwrap();
+        write(&path, &values()).unwrap();
+
+        let (blocks, comments, audio) = read_back(&path);
+        assert_eq!(blocks[1].kind, VORBIS_COMMENT);
+        assert_eq!(comments, ["TITLE=New", "BPM=140", "INITIALKEY=F#m"]);
+        assert_eq!(audio, AUDIO);
+    }
+}
@@ -0,0 +1,505 @@
+/*
+ * mp4.rs
+ *
+ * iTunes style tags in MP4 and M4A files. They live in moov > udta > meta >
+ * ilst, one atom per field holding a "data" atom with the value. The key has
+ * no atom of its own and goes into a "----" freeform atom named initialkey.
+ *
+ * The sample tables in moov point at the audio in mdat with absolute file
+ * offsets, so when a bigger moov ahead of mdat pushes the audio back they are
+ * shifted along. A free atom right after moov is used up first, and a moov at
+ * the end of the file is rewritten in place.
+ */
+
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::{BackupSegment, TagValues};
+use crate::atomic_file::replace_file;
+
+// Types of "data" atoms
+const DATA_UTF8: u32 = 1;
+const DATA_JPEG: u32 = 13;
+const DATA_PNG: u32 = 14;
+const DATA_INTEGER: u32 = 21;
+
+const FREEFORM_MEAN: &str = "com.apple.iTunes";
+const FREEFORM_KEY: &str = "initialkey";
+
+// Atoms on the way from moov down to the sample tables
+const SAMPLE_TABLE_PATH: [&[u8; 4]; 4] = [b"trak", b"mdia", b"minf", b"stbl"];
+
+// A top level atom of the file
+struct TopAtom {
+    kind: [u8; 4],
+    offset: u64,
+    // Including the header
+    length: u64,
+}
+
+fn top_atoms(file: &mut File, file_size: u64) -> Result<Vec<TopAtom>, Box<dyn Error>> {
+    let mut atoms = Vec::new();
+    let mut offset = 0;
+    while offset + 8 <= file_size {
+        let mut header = [0u8; 8];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut header)?;
+        let kind = [header[4], header[5], header[6], header[7]];
+        let length = match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+            // Runs to the end of the file
+            0 => file_size - offset,
+            // 64 bit length after the type
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large)?;
+                u64::from_be_bytes(large)
+            }
+            length => length as u64,
+        };
+        if length < 8 || offset + length > file_size {
+            return Err(format!("Broken MP4 atom at offset {}", offset).into());
+        }
+        atoms.push(TopAtom { kind, offset, length });
+        offset += length;
+    }
+    if !atoms.iter().any(|atom| &atom.kind == b"moov") {
+        return Err("Not an MP4 file, there is no moov atom".into());
+    }
+    Ok(atoms)
+}
+
+// Every top level atom except the audio in mdat, of which only the header is kept
+pub(super) fn header_segments(file: &mut File, file_size: u64) -> Result<Vec<BackupSegment>, Box<dyn Error>> {
+    Ok(top_atoms(file, file_size)?
+        .into_iter()
+        .map(|atom| BackupSegment {
+            offset: atom.offset,
+            length: if &atom.kind == b"mdat" { atom.length.min(16) } else { atom.length },
+        })
+        .collect())
+}
+
+pub(super) fn write(path: &Path, values: &TagValues) -> Result<(), Box<dyn Error>> {
+    let mut file = File::open(path)?;
+    let file_size = file.metadata()?.len();
+    let atoms = top_atoms(&mut file, file_size)?;
+    let index = atoms.iter().position(|atom| &atom.kind == b"moov").unwrap_or_default();
+    let moov = &atoms[index];
+
+    let mut data = vec![0u8; moov.length as usize];
+    file.seek(SeekFrom::Start(moov.offset))?;
+    file.read_exact(&mut data)?;
+    drop(file);
+    let header_length = if u32::from_be_bytes([data[0], data[1], data[2], data[3]]) == 1 { 16 } else { 8 };
+
+    let content = set_items(&data[header_length..], values)?;
+    let mut new_moov = atom(b"moov", &content);
+    let delta = new_moov.len() as i64 - moov.length as i64;
+
+    // Space that can be taken without moving anything after it
+    let following_free = atoms
+        .get(index + 1)
+        .filter(|atom| matches!(&atom.kind, b"free" | b"skip"))
+        .map_or(0, |atom| atom.length);
+    let at_end = atoms[index + 1..].iter().all(|atom| matches!(&atom.kind, b"free" | b"skip"));
+
+    if at_end {
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start(moov.offset))?;
+        file.write_all(&new_moov)?;
+        file.set_len(moov.offset + new_moov.len() as u64)?;
+        file.sync_all()?;
+        return Ok(());
+    }
+
+    let room = moov.length + following_free;
+    let leftover = room as i64 - new_moov.len() as i64;
+    // A free atom needs at least its 8 byte header
+    if leftover == 0 || leftover >= 8 {
+        if leftover > 0 {
+            new_moov.extend_from_slice(&atom(b"free", &vec![0; leftover as usize - 8]));
+        }
+        let mut file = OpenOptions::new().write(true).open(path)?;
+        file.seek(SeekFrom::Start(moov.offset))?;
+        file.write_all(&new_moov)?;
+        file.sync_all()?;
+        return Ok(());
+    }
+
+    // Audio after moov moves by the growth of moov
+    let mdat_after = atoms[index + 1..].iter().any(|atom| &atom.kind == b"mdat");
+    if mdat_after {
+        shift_chunk_offsets(&mut new_moov[8..], delta)?;
+    }
+    let moov_offset = moov.offset;
+    let moov_end = moov.offset + moov.length;
+    replace_file(path, |partial| {
+        let mut original = File::open(path)?;
+        io::copy(&mut (&mut original).take(moov_offset), partial)?;
+        partial.write_all(&new_moov)?;
+        original.seek(SeekFrom::Start(moov_end))?;
+        io::copy(&mut original, partial)?;
+        Ok(())
+    })
+}
+
+// An atom with a 32 bit length
+fn atom(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(8 + content.len());
+    data.extend_from_slice(&(8 + content.len() as u32).to_be_bytes());
+    data.extend_from_slice(kind);
+    data.extend_from_slice(content);
+    data
+}
+
+// An atom inside moov: its type and the whole atom with header
+type ChildAtom<'a> = ([u8; 4], &'a [u8]);
+
+fn children(content: &[u8]) -> Result<Vec<ChildAtom<'_>>, Box<dyn Error>> {
+    let mut atoms = Vec::new();
+    let mut position = 0;
+    while position + 8 <= content.len() {
+        let header = &content[position..position + 8];
+        let length = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+        if length < 8 || position + length > content.len() {
+            return Err("Broken MP4 atom inside moov".into());
+        }
+        let kind = [header[4], header[5], header[6], header[7]];
+        atoms.push((kind, &content[position..position + length]));
+        position += length;
+    }
+    Ok(atoms)
+}
+
+// Content of a container with the child of type `kind` replaced by what
+// `update` makes of the child's content, or added when there is none.
+fn update_child(
+    content: &[u8],
+    kind: &[u8; 4],
+    update: impl FnOnce(Option<&[u8]>) -> Result<Vec<u8>, Box<dyn Error>>,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let atoms = children(content)?;
+    let mut updated = Vec::with_capacity(content.len());
+    let mut update = Some(update);
+    for (child_kind, child) in &atoms {
+        match update.take() {
+            Some(update) if child_kind == kind => {
+                updated.extend_from_slice(&atom(kind, &update(Some(&child[8..]))?))
+            }
+            not_yet => {
+                update = not_yet;
+                updated.extend_from_slice(child);
+            }
+        }
+    }
+    if let Some(update) = update {
+        updated.extend_from_slice(&atom(kind, &update(None)?));
+    }
+    Ok(updated)
+}
+
+// moov content with the ilst items for the fields being written replaced
+fn set_items(moov: &[u8], values: &TagValues) -> Result<Vec<u8>, Box<dyn Error>> {
+    update_child(moov, b"udta", |udta| {
+        update_child(udta.unwrap_or_default(), b"meta", |meta| {
+            // meta is a full atom with 4 bytes of version and flags ahead of
+            // its children, except in some QuickTime files
+            let meta = meta.unwrap_or(&[0; 4]);
+            let (flags, children_content) = match meta.get(4..8) {
+                Some(b"hdlr") => (&[][..], meta),
+                _ => meta.split_at(meta.len().min(4)),
+            };
+            let mut children_content = children_content.to_vec();
+            if !children(&children_content)?.iter().any(|(kind, _)| kind == b"hdlr") {
+                children_content.splice(0..0, handler());
+            }
+            let children_content = update_child(&children_content, b"ilst", |ilst| {
+                set_ilst_items(ilst.unwrap_or_default(), values)
+            })?;
+            let mut content = flags.to_vec();
+            content.extend_from_slice(&children_content);
+            Ok(content)
+        })
+    })
+}
+
+// The handler iTunes puts in meta, without it players ignore the tags
+fn handler() -> Vec<u8> {
+    let mut content = vec![0u8; 8];
+    content.extend_from_slice(b"mdir");
+    content.extend_from_slice(b"appl");
+    content.extend_from_slice(&[0u8; 9]);
+    atom(b"hdlr", &content)
+}
+
+fn set_ilst_items(ilst: &[u8], values: &TagValues) -> Result<Vec<u8>, Box<dyn Error>> {
+    let text_item = |text: &str| data_atom(DATA_UTF8, text.as_bytes());
+    let mut items: Vec<(&[u8; 4], Vec<u8>)> = Vec::new();
+    if let Some(title) = &values.title {
+        items.push((b"\xa9nam", text_item(title)));
+    }
+    if let Some(artist) = &values.artist {
+        items.push((b"\xa9ART", text_item(artist)));
+    }
+    if let Some(genre) = &values.genre {
+        items.push((b"\xa9gen", text_item(genre)));
+    }
+    if let Some(comments) = &values.comments {
+        items.push((b"\xa9cmt", text_item(comments)));
+    }
+    // tmpo is a whole number
+    if let Some(bpm) = values.bpm {
+        let bpm = bpm.round().clamp(0.0, u16::MAX as f64) as u16;
+        items.push((b"tmpo", data_atom(DATA_INTEGER, &bpm.to_be_bytes())));
+    }
+    if let Some(artwork) = &values.artwork {
+        let kind = match image::guess_format(artwork) {
+            Ok(image::ImageFormat::Png) => DATA_PNG,
+            _ => DATA_JPEG,
+        };
+        items.push((b"covr", data_atom(kind, artwork)));
+    }
+    let key = values.key_text();
+
+    let mut content = Vec::with_capacity(ilst.len());
+    for (kind, item) in children(ilst)? {
+        let replaced = items.iter().any(|(k, _)| **k == kind)
+            || (key.is_some() && &kind == b"----" && is_freeform_key(&item[8..])?);
+        if !replaced {
+            content.extend_from_slice(item);
+        }
+    }
+    for (kind, item) in &items {
+        content.extend_from_slice(&atom(kind, item));
+    }
+    if let Some(key) = key {
+        let mut freeform = full_atom(b"mean", FREEFORM_MEAN.as_bytes());
+        freeform.extend_from_slice(&full_atom(b"name", FREEFORM_KEY.as_bytes()));
+        freeform.extend_from_slice(&text_item(&key));
+        content.extend_from_slice(&atom(b"----", &freeform));
+    }
+    Ok(content)
+}
+
+// A "data" atom: type, a locale that is always 0 and the value
+fn data_atom(kind: u32, value: &[u8]) -> Vec<u8> {
+    let mut content = Vec::with_capacity(8 + value.len());
+    content.extend_from_slice(&kind.to_be_bytes());
+    content.extend_from_slice(&0u32.to_be_bytes());
+    content.extend_from_slice(value);
+    atom(b"data", &content)
+}
+
+// An atom with zero version and flags ahead of its content
+fn full_atom(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+    let mut full = vec![0u8; 4];
+    full.extend_from_slice(content);
+    atom(kind, &full)
+}
+
+fn is_freeform_key(freeform: &[u8]) -> Result<bool, Box<dyn Error>> {
+    Ok(children(freeform)?.iter().any(|(kind, name)| {
+        kind == b"name"
+            && name
+                .get(12..)
+                .is_some_and(|name| String::from_utf8_lossy(name).eq_ignore_ascii_case(FREEFORM_KEY))
+    }))
+}
+
+// Adds `delta` to every chunk offset in the stco and co64 atoms under moov.
+fn shift_chunk_offsets(content: &mut [u8], delta: i64) -> Result<(), Box<dyn Error>> {
+    let mut position = 0;
+    while position + 8 <= content.len() {
+        let length = u32::from_be_bytes([
+            content[position],
+            content[position + 1],
+            content[position + 2],
+            content[position + 3],
+        ]) as usize;
+        if length < 8 || position + length > content.len() {
+            return Err("Broken MP4 atom inside moov".into());
+        }
+        let kind = [
+            content[position + 4],
+            content[position + 5],
+            content[position + 6],
+            content[position + 7],
+        ];
+        let child = &mut content[position + 8..position + length];
+        if SAMPLE_TABLE_PATH.iter().any(|container| kind == **container) {
+            shift_chunk_offsets(child, delta)?;
+        } else if &kind == b"stco" || &kind == b"co64" {
+            let wide = &kind == b"co64";
+            let width = if wide { 8 } else { 4 };
+            let count = child.get(4..8).map_or(0, |b| u32::from_be_bytes([b[0], b[1], b[2], b[3]])) as usize;
+            for entry in child[8..].chunks_exact_mut(width).take(count) {
+                if wide {
+                    let offset = u64::from_be_bytes(entry.try_into()?) as i64 + delta;
+                    entry.copy_from_slice(&(offset as u64).to_be_bytes());
+                } else {
+                    let offset = u32::from_be_bytes(entry.try_into()?) as i64 + delta;
+                    let offset = u32::try_from(offset).map_err(|_| "Chunk offset out of range, the file is too large")?;
+                    entry.copy_from_slice(&offset.to_be_bytes());
+                }
+            }
+        }
+        position += length;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // Three chunks of "audio". The stco points at the first two, the co64 at the third.
+    const CHUNKS: [&[u8; 4]; 3] = [b"AAAA", b"BBBB", b"CCCC"];
+    const FREE_LENGTH: usize = 4096;
+
+    // moov with two tracks whose sample tables point at `offsets`
+    fn moov(offsets: &[u64; 3]) -> Vec<u8> {
+        let stbl = |table: Vec<u8>| atom(b"trak", &atom(b"mdia", &atom(b"minf", &atom(b"stbl", &table))));
+        let mut stco = vec![0u8; 4];
+        stco.extend_from_slice(&2u32.to_be_bytes());
+        for offset in &offsets[..2] {
+            stco.extend_from_slice(&(*offset as u32).to_be_bytes());
+        }
+        let mut co64 = vec![0u8; 4];
+        co64.extend_from_slice(&1u32.to_be_bytes());
+        co64.extend_from_slice(&offsets[2].to_be_bytes());
+
+        let mut content = stbl(atom(b"stco", &stco));
+        content.extend_from_slice(&stbl(atom(b"co64", &co64)));
+        atom(b"moov", &content)
+    }
+
+    // ftyp followed by the atoms of `layout`, any of moov, free and mdat
+    fn mp4_file(name: &str, layout: &[&[u8; 4]]) -> PathBuf {
+        let ftyp = atom(b"ftyp", b"M4A \0\0\0\0M4A mp42");
+        let mdat_content: Vec<u8> = CHUNKS.iter().flat_map(|chunk| chunk.iter().copied()).collect();
+        let length = |kind: &[u8; 4]| match kind {
+            b"moov" => moov(&[0; 3]).len(),
+            b"free" => 8 + FREE_LENGTH,
+            _ => 8 + mdat_content.len(),
+        };
+        let mdat_offset = ftyp.len() + layout.iter().take_while(|kind| **kind != b"mdat").map(|kind| length(kind)).sum::<usize>();
+        let offsets = [0, 4, 8].map(|i| (mdat_offset + 8 + i) as u64);
+
+        let mut bytes = ftyp;
+        for kind in layout {
+            match *kind {
+                b"moov" => bytes.extend_from_slice(&moov(&offsets)),
+                b"free" => bytes.extend_from_slice(&atom(b"free", &[0; FREE_LENGTH])),
+                _ => bytes.extend_from_slice(&atom(b"mdat", &mdat_content)),
+            }
+        }
+        let path = std::env::temp_dir().join(format!("beatbank-mp4-{}.m4a", name));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    // Content of the first child of type `kind`
+    fn child<'a>(content: &'a [u8], kind: &[u8; 4]) -> &'a [u8] {
+        let (_, found) = children(content).unwrap().into_iter().find(|(k, _)| k == kind).unwrap();
+        &found[8..]
+    }
+
+    fn chunk_offsets(content: &[u8], offsets: &mut Vec<u64>) {
+        for (kind, found) in children(content).unwrap() {
+            let content = &found[8..];
+            if SAMPLE_TABLE_PATH.iter().any(|container| kind == **container) {
+                chunk_offsets(content, offsets);
+            } else if &kind == b"stco" {
+                offsets.extend(content[8..].chunks_exact(4).map(|e| u32::from_be_bytes(e.try_into().unwrap()) as u64));
+            } else if &kind == b"co64" {
+                offsets.extend(content[8..].chunks_exact(8).map(|e| u64::from_be_bytes(e.try_into().unwrap())));
+            }
+        }
+    }
+
+    // The top level atoms, moov's content and the bytes of the file
+    fn read_back(path: &Path) -> (Vec<[u8; 4]>, Vec<u8>, Vec<u8>) {
+        let bytes = fs::read(path).unwrap();
+        let mut file = File::open(path).unwrap();
+        let atoms = top_atoms(&mut file, bytes.len() as u64).unwrap();
+        let moov = atoms.iter().find(|atom| &atom.kind == b"moov").unwrap();
+        let content = bytes[moov.offset as usize + 8..(moov.offset + moov.length) as usize].to_vec();
+        fs::remove_file(path).ok();
+        (atoms.iter().map(|atom| atom.kind).collect(), content, bytes)
+    }
+
+    fn offsets_of(moov: &[u8]) -> Vec<u64> {
+        let mut offsets = Vec::new();
+        chunk_offsets(moov, &mut offsets);
+        offsets
+    }
+
+    // Every chunk offset still leads to the same audio
+    fn assert_chunks_found(moov: &[u8], bytes: &[u8]) {
+        for (offset, chunk) in offsets_of(moov).into_iter().zip(CHUNKS) {
+            assert_eq!(&bytes[offset as usize..offset as usize + 4], chunk);
+        }
+    }
+
+    fn title(moov: &[u8]) -> String {
+        let meta = child(child(moov, b"udta"), b"meta");
+        let data = child(child(child(&meta[4..], b"ilst"), b"\xa9nam"), b"data");
+        String::from_utf8(data[8..].to_vec()).unwrap()
+    }
+
+    fn values() -> TagValues {
+        TagValues {
+            title: Some("New title".to_string()),
+            bpm: Some(128.0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn shifts_chunk_offsets_when_moov_grows_ahead_of_mdat() {
+        let path = mp4_file("moov-first", &[b"moov", b"mdat"]);
+        let (_, old_moov, old_bytes) = read_back(&path);
+        fs::write(&path, &old_bytes).unwrap();
+        write(&path, &values()).unwrap();
+
+        let (kinds, moov, bytes) = read_back(&path);
+        assert_eq!(kinds, [*b"ftyp", *b"moov", *b"mdat"]);
+        assert_eq!(title(&moov), "New title");
+        let delta = (moov.len() - old_moov.len()) as u64;
+        assert_eq!(bytes.len() as u64, old_bytes.len() as u64 + delta);
+        let shifted: Vec<u64> = offsets_of(&old_moov).iter().map(|offset| offset + delta).collect();
+        assert_eq!(offsets_of(&moov), shifted);
+        assert_chunks_found(&moov, &bytes);
+    }
+
+    #[test]
+    fn uses_the_free_atom_after_moov() {
+        let path = mp4_file("free", &[b"moov", b"free", b"mdat"]);
+        let length = fs::metadata(&path).unwrap().len();
+        write(&path, &values()).unwrap();
+
+        let (kinds, moov, bytes) = read_back(&path);
+        assert_eq!(bytes.len() as u64, length);
+        assert_eq!(kinds, [*b"ftyp", *b"moov", *b"free", *b"mdat"]);
+        assert_eq!(title(&moov), "New title");
+        assert_chunks_found(&moov, &bytes);
+    }
+
+    #[test]
+    fn rewrites_moov_at_the_end_in_place() {
+        let path = mp4_file("moov-last", &[b"mdat", b"moov"]);
+        let (_, old_moov, old_bytes) = read_back(&path);
+        fs::write(&path, &old_bytes).unwrap();
+        write(&path, &values()).unwrap();
+
+        let (kinds, moov, bytes) = read_back(&path);
+        assert_eq!(kinds, [*b"ftyp", *b"mdat", *b"moov"]);
+        assert_eq!(title(&moov), "New title");
+        assert_eq!(offsets_of(&moov), offsets_of(&old_moov));
+        assert_chunks_found(&moov, &bytes);
+    }
+}
@@ -0,0 +1,252 @@
+/*
+ * tags.rs
+ *
+ * Reads the metadata embedded in audio files so imported beats start out with
+ * their title, artist, bpm and so on instead of just a file name.
+ *
+ * Tags are read through symphonia, which covers ID3v2 in MP3, Vorbis comments
+ * in FLAC and Ogg, iTunes atoms in MP4 and RIFF INFO chunks in WAV. DJ software
+ * writes ID3 chunks into WAV and AIFF files as well, which symphonia skips, so
+ * those are read with the id3 crate. When a file has both, symphonia's values
+ * come first. Embedded artwork (APIC, FLAC PICTURE, MP4 covr) is read the same
+ * way by read_artwork.
+ */
+
+use std::error::Error;
+use std::fs::File;
+use std::path::Path;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, MetadataRevision, StandardTagKey, StandardVisualKey};
+use symphonia::core::probe::{Hint, ProbeResult};
+
+use crate::musical_key::MusicalKey;
+
+// Everything the beats table can take from a file's tags. Values are trimmed
+// and empty ones left out.
+#[derive(Default, Debug, Clone)]
+pub struct EmbeddedTags {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub genre: Option<String>,
+    pub year: Option<i32>,
+    pub track_number: Option<i32>,
+    pub composer: Option<String>,
+    pub lyricist: Option<String>,
+    pub comments: Option<String>,
+    pub bpm: Option<f64>,
+    // Normalized like every other stored key
+    pub musical_key: Option<String>,
+}
+
+// Fields of EmbeddedTags, for mapping tag names onto them
+#[derive(Clone, Copy)]
+enum Field {
+    Title,
+    Artist,
+    Album,
+    Genre,
+    Year,
+    TrackNumber,
+    Composer,
+    Lyricist,
+    Comments,
+    Bpm,
+    Key,
+}
+
+impl EmbeddedTags {
+    // Sets a field from a tag value unless it already has one. Values that
+    // don't parse are ignored.
+    fn set(&mut self, field: Field, value: &str) {
+        let value = value.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+        if value.is_empty() {
+            return;
+        }
+        let text = || Some(value.to_string());
+        match field {
+            Field::Title => self.title = self.title.take().or_else(text),
+            Field::Artist => self.artist = self.artist.take().or_else(text),
+            Field::Album => self.album = self.album.take().or_else(text),
+            Field::Genre => self.genre = self.genre.take().or_else(text),
+            Field::Composer => self.composer = self.composer.take().or_else(text),
+            Field::Lyricist => self.lyricist = self.lyricist.take().or_else(text),
+            Field::Comments => {
+                if !is_itunes_data(value) {
+                    self.comments = self.comments.take().or_else(text)
+                }
+            }
+            // Dates come as "2019", "2019-04-12" or "2019-04-12T10:00:00"
+            Field::Year => self.year = self.year.or_else(|| value.get(..4)?.parse().ok()),
+            // "3" or "3/12"
+            Field::TrackNumber => {
+                self.track_number = self
+                    .track_number
+                    .or_else(|| value.split('/').next()?.trim().parse().ok())
+            }
+            Field::Bpm => {
+                self.bpm = self.bpm.or_else(|| {
+                    let bpm: f64 = value.replace(',', ".").parse().ok()?;
+                    (bpm.is_finite() && bpm > 0.0).then(|| (bpm * 100.0).round() / 100.0)
+                })
+            }
+            Field::Key => {
+                self.musical_key = self
+                    .musical_key
+                    .take()
+                    .or_else(|| MusicalKey::parse(value).map(|key| key.to_string()))
+            }
+        }
+    }
+}
+
+// Reads the tags of the audio file at file_path. A file without tags gives
+// an empty EmbeddedTags, only unreadable files are an error.
+pub fn read_tags(file_path: &str) -> Result<EmbeddedTags, Box<dyn Error>> {
+    let path = Path::new(file_path);
+    let mut probed = probe(path)?;
+
+    let mut tags = EmbeddedTags::default();
+    // Tags inside the container first, then any found ahead of it like ID3v2 in MP3
+    if let Some(revision) = probed.format.metadata().skip_to_latest() {
+        apply_revision(&mut tags, revision);
+    }
+    if let Some(mut metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.skip_to_latest() {
+            apply_revision(&mut tags, revision);
+        }
+    }
+
+    if has_id3_chunk(path) {
+        apply_id3_chunk(&mut tags, path);
+    }
+    Ok(tags)
+}
+
+// The embedded cover image of an audio file, the front cover when there are
+// several. None when the file has no artwork.
+pub fn read_artwork(file_path: &str) -> Result<Option<Vec<u8>>, Box<dyn Error>> {
+    let path = Path::new(file_path);
+    let mut probed = probe(path)?;
+
+    let mut visuals: Vec<(bool, Vec<u8>)> = Vec::new();
+    let mut collect = |revision: &MetadataRevision| {
+        for visual in revision.visuals() {
+            let front = visual.usage == Some(StandardVisualKey::FrontCover);
+            visuals.push((front, visual.data.to_vec()));
+        }
+    };
+    if let Some(revision) = probed.format.metadata().skip_to_latest() {
+        collect(revision);
+    }
+    if let Some(mut metadata) = probed.metadata.get() {
+        if let Some(revision) = metadata.skip_to_latest() {
+            collect(revision);
+        }
+    }
+    if has_id3_chunk(path) {
+        if let Ok(tag) = id3::Tag::read_from_path(path) {
+            for picture in tag.pictures() {
+                let front = picture.picture_type == id3::frame::PictureType::CoverFront;
+                visuals.push((front, picture.data.clone()));
+            }
+        }
+    }
+
+    let front = visuals.iter().position(|(front, _)| *front).unwrap_or(0);
+    Ok((front < visuals.len()).then(|| visuals.swap_remove(front).1))
+}
+
+fn probe(path: &Path) -> Result<ProbeResult, Box<dyn Error>> {
+    let mut hint = Hint::new();
+    if let Some(extension) = path.extension().and_then(|e| e.to_str()) {
+        hint.with_extension(extension);
+    }
+    let mss = MediaSourceStream::new(Box::new(File::open(path)?), Default::default());
+    Ok(symphonia::default::get_probe().format(
+        &hint,
+        mss,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?)
+}
+
+// WAV and AIFF files whose ID3 chunk symphonia doesn't read
+fn has_id3_chunk(path: &Path) -> bool {
+    let extension = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    matches!(extension.as_str(), "wav" | "aif" | "aiff")
+}
+
+fn apply_revision(tags: &mut EmbeddedTags, revision: &MetadataRevision) {
+    for tag in revision.tags() {
+        let field = match tag.std_key {
+            Some(StandardTagKey::TrackTitle) => Some(Field::Title),
+            Some(StandardTagKey::Artist) => Some(Field::Artist),
+            Some(StandardTagKey::Album) => Some(Field::Album),
+            Some(StandardTagKey::Genre) => Some(Field::Genre),
+            Some(StandardTagKey::Date | StandardTagKey::ReleaseDate | StandardTagKey::OriginalDate) => {
+                Some(Field::Year)
+            }
+            Some(StandardTagKey::TrackNumber) => Some(Field::TrackNumber),
+            Some(StandardTagKey::Composer) => Some(Field::Composer),
+            Some(StandardTagKey::Lyricist) => Some(Field::Lyricist),
+            Some(StandardTagKey::Comment) => Some(Field::Comments),
+            Some(StandardTagKey::Bpm) => Some(Field::Bpm),
+            // There is no standard key for the musical key, every format names it differently
+            _ => is_key_tag(&tag.key).then_some(Field::Key),
+        };
+        if let Some(field) = field {
+            tags.set(field, &tag.value.to_string());
+        }
+    }
+}
+
+// The ID3 chunk of a WAV or AIFF file. Files without one are left alone.
+fn apply_id3_chunk(tags: &mut EmbeddedTags, path: &Path) {
+    let Ok(tag) = id3::Tag::read_from_path(path) else {
+        return;
+    };
+    use id3::TagLike;
+
+    let text = |id: &str| tag.get(id).and_then(|frame| frame.content().text()).map(String::from);
+    let fields = [
+        (Field::Title, tag.title().map(String::from)),
+        (Field::Artist, tag.artist().map(String::from)),
+        (Field::Album, tag.album().map(String::from)),
+        (Field::Genre, tag.genre_parsed().map(|genre| genre.to_string())),
+        (
+            Field::Year,
+            tag.year().or_else(|| tag.date_recorded().map(|date| date.year)).map(|year| year.to_string()),
+        ),
+        (Field::TrackNumber, tag.track().map(|track| track.to_string())),
+        (Field::Composer, text("TCOM")),
+        (Field::Lyricist, text("TEXT")),
+        (Field::Comments, tag.comments().next().map(|comment| comment.text.clone())),
+        (Field::Bpm, text("TBPM")),
+        (Field::Key, text("TKEY")),
+    ];
+    for (field, value) in fields {
+        if let Some(value) = value {
+            tags.set(field, &value);
+        }
+    }
+}
+
+// TKEY in ID3, INITIALKEY or KEY in Vorbis comments, and the iTunes freeform
+// "com.apple.iTunes:initialkey" atom in MP4
+fn is_key_tag(name: &str) -> bool {
+    let name = name.to_lowercase();
+    matches!(name.as_str(), "tkey" | "initialkey" | "initial key" | "key") || name.ends_with(":initialkey")
+}
+
+// iTunes stores normalization and gapless data as comments of hex numbers
+fn is_itunes_data(comment: &str) -> bool {
+    comment.split_whitespace().count() >= 4
+        && comment
+            .split_whitespace()
+            .all(|word| word.len() == 8 && word.chars().all(|c| c.is_ascii_hexdigit()))
+}
@@ -24,8 +24,8 @@ file_paths = [
 # Iterate over each file path, analyze and print results
 for file_path in file_paths:
     try:
-        key, tempo = analyze(file_path)
+        key, tempo, confidence = analyze(file_path)
         print(f"File: {file_path}")
-        print(f"Key: {key}, Tempo: {tempo}\n")
+        print(f"Key: {key} ({confidence:.2f}), Tempo: {tempo}\n")
     except Exception as e:
         print(f"Error analyzing {file_path}: {e}\n")
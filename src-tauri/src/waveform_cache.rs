@@ -0,0 +1,209 @@
+/*
+ * waveform_cache.rs
+ *
+ * On-disk cache of waveform overviews. Decoding a whole track is slow, so every
+ * overview is stored in the app cache directory under the blake3 hash of the
+ * audio file, and only rebuilt when the file's contents change. Size and
+ * modification time are checked first so unchanged files aren't hashed again.
+ *
+ * Overviews are handed to the frontend in a compact binary layout, all numbers
+ * little endian:
+ *
+ *   magic        4 bytes  "BBWF"
+ *   version      u8       FORMAT_VERSION
+ *   flags        u8       bit 0 set when bands are present
+ *   level count  u16
+ *   sample rate  u32
+ *   frames       u64      length of the track in samples per channel
+ *
+ * followed by each level, most detailed first:
+ *
+ *   samples per bin  u32
+ *   bin count        u32
+ *   bins             min i8, max i8, rms u8, and low u8, mid u8, high u8
+ *                    when bands are present
+ *
+ * min and max are scaled from -1.0..1.0 to -127..127 and rms from 0.0..1.0 to
+ * 0..255. Band values are scaled so the loudest band bin of a level is 255,
+ * since only their balance matters for coloring.
+ */
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use crate::atomic_file;
+use crate::audio_analysis::{self, Waveform};
+
+const MAGIC: &[u8; 4] = b"BBWF";
+const FORMAT_VERSION: u8 = 1;
+const FLAG_BANDS: u8 = 1;
+
+const INDEX_FILE: &str = "index.json";
+
+// What a file looked like when it was last hashed
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+struct FileStamp {
+    len: u64,
+    modified_ms: u128,
+    hash: String,
+}
+
+pub struct WaveformCache {
+    // None when the platform has no cache directory, overviews are then built every time
+    dir: Option<PathBuf>,
+    // File path to the stamp of its last hash
+    index: Mutex<HashMap<String, FileStamp>>,
+}
+
+impl WaveformCache {
+    pub fn new(dir: Option<PathBuf>) -> Self {
+        let index = dir
+            .as_ref()
+            .and_then(|dir| fs::read_to_string(dir.join(INDEX_FILE)).ok())
+            .and_then(|contents| serde_json::from_str(&contents).ok())
+            .unwrap_or_default();
+
+        WaveformCache {
+            dir,
+            index: Mutex::new(index),
+        }
+    }
+
+    // Encoded overview of the audio file at file_path, from the cache when the
+    // file hasn't changed since it was built.
+    pub fn get(&self, file_path: &str, bands: bool) -> Result<Vec<u8>, Box<dyn Error>> {
+        let Some(dir) = &self.dir else {
+            return Ok(encode_waveform(&audio_analysis::analyze_waveform(Path::new(file_path), bands)?));
+        };
+
+        let hash = self.file_hash(dir, file_path)?;
+        let cache_path = dir.join(cache_file_name(&hash, bands));
+        if let Ok(encoded) = fs::read(&cache_path) {
+            if encoded.starts_with(MAGIC) && encoded.get(4) == Some(&FORMAT_VERSION) {
+                return Ok(encoded);
+            }
+        }
+
+        let encoded = encode_waveform(&audio_analysis::analyze_waveform(Path::new(file_path), bands)?);
+
+        fs::create_dir_all(dir)?;
+        atomic_file::write_file(&cache_path, &encoded)?;
+        Ok(encoded)
+    }
+
+    // Hash of the file's contents, reusing the last one while size and
+    // modification time are unchanged. Overviews of the previous contents are
+    // removed when the hash changes.
+    fn file_hash(&self, dir: &Path, file_path: &str) -> Result<String, Box<dyn Error>> {
+        let metadata = fs::metadata(file_path)?;
+        let len = metadata.len();
+        let modified_ms = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis())
+            .unwrap_or_default();
+
+        {
+            let index = self.index.lock().map_err(|e| e.to_string())?;
+            if let Some(stamp) = index.get(file_path) {
+                if stamp.len == len && stamp.modified_ms == modified_ms {
+                    return Ok(stamp.hash.clone());
+                }
+            }
+        }
+
+        // Hash without holding the lock, large files take a moment
+        let hash = hash_file(Path::new(file_path))?;
+
+        let mut index = self.index.lock().map_err(|e| e.to_string())?;
+        let stamp = FileStamp {
+            len,
+            modified_ms,
+            hash: hash.clone(),
+        };
+        if let Some(previous) = index.insert(file_path.to_string(), stamp) {
+            // Another path can point at an identical file, keep its overviews
+            let still_used = index.values().any(|s| s.hash == previous.hash);
+            if previous.hash != hash && !still_used {
+                for bands in [false, true] {
+                    fs::remove_file(dir.join(cache_file_name(&previous.hash, bands))).ok();
+                }
+            }
+        }
+
+        fs::create_dir_all(dir)?;
+        fs::write(dir.join(INDEX_FILE), serde_json::to_string(&*index)?)?;
+        Ok(hash)
+    }
+}
+
+// Hex encoded blake3 hash of a file's contents
+pub fn hash_file(path: &Path) -> Result<String, Box<dyn Error>> {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(File::open(path)?)?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+fn cache_file_name(hash: &str, bands: bool) -> String {
+    if bands {
+        format!("{}-bands.bbwf", hash)
+    } else {
+        format!("{}.bbwf", hash)
+    }
+}
+
+// Packs a waveform into the binary layout described at the top of this file.
+pub fn encode_waveform(waveform: &Waveform) -> Vec<u8> {
+    let bytes_per_bin = if waveform.has_bands { 6 } else { 3 };
+    let capacity = 20 + waveform
+        .levels
+        .iter()
+        .map(|level| 8 + level.bins.len() * bytes_per_bin)
+        .sum::<usize>();
+    let mut encoded = Vec::with_capacity(capacity);
+
+    encoded.extend_from_slice(MAGIC);
+    encoded.push(FORMAT_VERSION);
+    encoded.push(if waveform.has_bands { FLAG_BANDS } else { 0 });
+    encoded.extend_from_slice(&(waveform.levels.len() as u16).to_le_bytes());
+    encoded.extend_from_slice(&waveform.sample_rate.to_le_bytes());
+    encoded.extend_from_slice(&waveform.frames.to_le_bytes());
+
+    for level in &waveform.levels {
+        encoded.extend_from_slice(&level.samples_per_bin.to_le_bytes());
+        encoded.extend_from_slice(&(level.bins.len() as u32).to_le_bytes());
+
+        let band_peak = level
+            .bins
+            .iter()
+            .filter_map(|bin| bin.bands)
+            .flatten()
+            .fold(0.0f32, f32::max);
+
+        for bin in &level.bins {
+            encoded.push(scale_signed(bin.min) as u8);
+            encoded.push(scale_signed(bin.max) as u8);
+            encoded.push(scale_unsigned(bin.rms));
+            if let Some(bands) = bin.bands {
+                for band in bands {
+                    let relative = if band_peak > 0.0 { band / band_peak } else { 0.0 };
+                    encoded.push(scale_unsigned(relative));
+                }
+            }
+        }
+    }
+    encoded
+}
+
+fn scale_signed(value: f32) -> i8 {
+    (value.clamp(-1.0, 1.0) * 127.0).round() as i8
+}
+
+fn scale_unsigned(value: f32) -> u8 {
+    (value.clamp(0.0, 1.0) * 255.0).round() as u8
+}
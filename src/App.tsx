@@ -57,10 +57,22 @@ function App() {
       setIsFileDragging(false); // Hide file dragging UI when cancelled
     });
 
+    // Beats are analyzed in the background after import, refresh as results come in
+    const unlistenAnalysisComplete = listen('analysis-complete', () => {
+      fetchData();
+    });
+
+    const unlistenAnalysisFailed = listen('analysis-failed', (event) => {
+      console.error('Analysis failed:', event.payload);
+      fetchData();
+    });
+
     return () => {
       unlistenDrop.then((dispose) => dispose());
       unlistenHover.then((dispose) => dispose());
       unlistenCancelled.then((dispose) => dispose());
+      unlistenAnalysisComplete.then((dispose) => dispose());
+      unlistenAnalysisFailed.then((dispose) => dispose());
     };
   }, []);
 
@@ -153,7 +165,8 @@ function App() {
     const newTheme = theme === 'light' ? 'dark' : 'light'; // Toggle between light and dark themes
     setTheme(newTheme); // Update the theme state
 
-    await saveSettings({ theme: newTheme }); // Save the new theme settings to the backend
+    const settings = await loadSettings(); // Keep the other settings when only the theme changes
+    await saveSettings({ ...settings, theme: newTheme }); // Save the new theme settings to the backend
     console.log("Theme changed to:", newTheme); // Log the new theme for debugging purposes
   };
 
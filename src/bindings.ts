@@ -14,7 +14,52 @@ export type Beat = {
     comments?: string;
     file_path: string;
     bpm?: number | undefined;
+    bpm_raw?: number;
     musical_key?: string;
+    key_confidence?: number;
+    key_low_confidence: boolean;
+    key_display?: string;
+    analysis_status: 'pending' | 'running' | 'complete' | 'failed' | 'canceled';
+    bpm_source?: 'analysis' | 'manual' | 'imported' | 'tag' | 'filename';
+    key_source?: 'analysis' | 'manual' | 'imported' | 'tag' | 'filename';
+    integrated_loudness?: number;
+    short_term_loudness_max?: number;
+    loudness_range?: number;
+    true_peak?: number;
+    // Version number parsed from the file name, e.g. "3" for "... v3.wav"
+    version?: string;
+    // 'missing' when file_path no longer points to a file
+    file_status: 'ok' | 'missing';
+    // Hash of the decoded audio, the same for copies that only differ in tags
+    audio_hash?: string;
+    // Bytes, for finding the file again if it moves
+    file_size?: number;
+    gain?: number;
+    // Left out where cue points aren't loaded
+    cue_points?: CuePoint[];
+    // Only filled in by fetch_beats
+    comment_count?: number;
+    unresolved_comment_count?: number;
+  };
+
+export type KeyNotation = 'standard' | 'flats' | 'camelot' | 'open_key';
+
+export type KeyRelation = 'same_key' | 'relative' | 'adjacent' | 'energy_boost' | 'semitone_boost';
+
+export type CompatibleBeat = Beat & {
+    key_relation: KeyRelation;
+    bpm_difference?: number;
+    score: number;
+  };
+
+export type EnergyCurve = 'rising' | 'falling' | 'peak';
+
+export type SetOrderSuggestion = {
+    entry_ids: number[];
+    beats: Beat[];
+    key_clashes: number;
+    bpm_jumps: number;
+    score: number;
   };
 
 export type BeatCollection = {
@@ -25,6 +70,8 @@ export type BeatCollection = {
     state_name?: string;
     date_played?: string;
     date_created?: string;
+    loudness?: number;
+    gain?: number;
   };
 
   export type ColumnVis = {
@@ -48,6 +95,223 @@ export type EditThisBeat = {
 }
 
 export type BeatInCollection = {
+  id: number;
   beat_id: number;
   beat_collection_id: number;
-}
\ No newline at end of file
+  position: number;
+}
+
+// A beat at its place in a set, the same beat can appear more than once
+export type SetEntry = Beat & {
+  entry_id: number;
+  position: number;
+  details?: SetEntryDetails;
+}
+
+// Performance plan for a set entry, times in seconds into the track
+export type SetEntryDetails = {
+  set_beat_id: number;
+  transition_style?: string;
+  mix_in_time?: number;
+  mix_out_time?: number;
+  key_shift?: number;
+  pitch_adjust?: number;
+  note?: string;
+  date_updated?: string;
+}
+// Tempo change in a beat grid, position in seconds
+export type TempoMarker = {
+  position: number;
+  bpm: number;
+}
+
+export type GridBeat = {
+  position: number;
+  // 1 on a downbeat
+  beat_in_bar: number;
+}
+
+export type BeatGrid = {
+  beat_id: number;
+  markers: TempoMarker[];
+  beats_per_bar: number;
+  duration: number;
+  first_downbeat: number;
+  bpm: number;
+  source: 'analysis' | 'manual' | 'imported';
+  date_updated: string;
+  beats: GridBeat[];
+}
+
+export type CueKind = 'memory' | 'hot' | 'loop';
+
+// Cue marker or saved loop, positions in milliseconds
+export type CuePoint = {
+  id: number;
+  beat_id: number;
+  kind: CueKind;
+  position_ms: number;
+  loop_length_ms?: number;
+  // Pad 0 to 7, hot cues only
+  hot_cue_index?: number;
+  label?: string;
+  // '#RRGGBB'
+  color?: string;
+  date_created: string;
+}
+
+// What add_cue_point and update_cue_point take
+export type CuePointChangeset = Omit<CuePoint, 'id' | 'date_created'>;
+
+export type DjImportSummary = {
+  tracks: number;
+  matched: number;
+  cue_points: number;
+  beat_grids: number;
+  unmatched: string[];
+  skipped_cue_points: SkippedCuePoint[];
+}
+
+// Cue point an import left out, with why
+export type SkippedCuePoint = {
+  file_path: string;
+  position_ms: number;
+  reason: string;
+}
+
+// Review comment pinned to a moment in a beat
+export type BeatComment = {
+  id: number;
+  beat_id: number;
+  // Milliseconds into the track
+  timestamp_ms: number;
+  author: string;
+  body: string;
+  resolved: boolean;
+  date_created: string;
+}
+
+export type NewBeatComment = Pick<BeatComment, 'beat_id' | 'timestamp_ms' | 'author' | 'body'>;
+
+// A field write_tags would change, old is what the file has now
+export type TagChange = {
+  field: 'title' | 'artist' | 'genre' | 'comments' | 'bpm' | 'musical_key' | 'artwork';
+  old: string | null;
+  new: string;
+}
+
+export type TagWriteReport = {
+  beat_id: number;
+  file_path: string;
+  changes: TagChange[];
+  // False for dry runs, files already up to date and failures
+  written: boolean;
+  backup_path: string | null;
+  error: string | null;
+}
+
+// What parse_filename makes of a file name
+export type ParsedFilename = {
+  title: string | null;
+  artist: string | null;
+  bpm: number | null;
+  musical_key: string | null;
+  version: string | null;
+}
+
+export type FilenameParseSummary = {
+  beats: number;
+  titles: number;
+  artists: number;
+  bpms: number;
+  keys: number;
+  versions: number;
+}
+
+// Options of import_folder, every field may be left out
+export type ImportOptions = {
+  // Only these extensions, among the ones symphonia decodes
+  extensions?: string[];
+  include_hidden?: boolean;
+  // Queue new beats for analysis, true by default
+  analyze?: boolean;
+}
+
+export type ImportSummary = {
+  added: number;
+  // Already in the library
+  skipped: number;
+  failed: { file_path: string; reason: string }[];
+}
+
+// Payload of the import-progress event
+export type ImportProgress = {
+  folder: string;
+  processed: number;
+  total: number;
+  file_path: string;
+}
+
+// Payload of the watch-folder-update event
+export type WatchFolderUpdate = {
+  added: number;
+  // Beats whose file changed, queued for analysis again
+  refreshed: number[];
+  missing: number[];
+  failed: { file_path: string; reason: string }[];
+}
+
+// A group found by find_duplicates, oldest beat first
+export type DuplicateGroup = {
+  // 'identical' when every beat has the same audio_hash
+  kind: 'identical' | 'similar';
+  // Lowest similarity between two matched beats, 1.0 for identical ones
+  similarity: number;
+  beats: Beat[];
+}
+
+// Payload of the duplicate-scan-progress event
+export type DuplicateScanProgress = {
+  beat_id: number;
+  processed: number;
+  total: number;
+}
+
+export type LibraryScanSummary = {
+  checked: number;
+  // Every beat whose file is missing now
+  missing: number[];
+  // Beats that were missing and whose file is back
+  found: number[];
+}
+
+export type Relocation = {
+  beat_id: number;
+  old_path: string;
+  new_path: string;
+  matched_by: 'audio_hash' | 'name_and_size' | 'name';
+}
+
+// What relocate_missing found, or changed when it wasn't a dry run
+export type RelocateSummary = {
+  relocations: Relocation[];
+  not_found: number[];
+  // More than one possible file, pick one with relocate_beat
+  ambiguous: number[];
+  failed: { file_path: string; reason: string }[];
+}
+
+export type PathRewrite = {
+  beat_id: number;
+  old_path: string;
+  new_path: string;
+  // Whether a file exists at new_path
+  exists: boolean;
+}
+
+// Payload of the relocate-progress event
+export type RelocateProgress = {
+  beat_id: number;
+  processed: number;
+  total: number;
+}
@@ -1,7 +1,7 @@
 
 import { useState, useCallback, Dispatch, SetStateAction } from "react";
 import { invoke } from "@tauri-apps/api/tauri";
-import { Beat, BeatCollection } from "./../bindings";
+import { Beat, BeatCollection, SetEntry } from "./../bindings";
 
 const defaultColumnVisibility = {
   title: true,
@@ -68,7 +68,7 @@ export const useBeats = () => {
       setCurrentCollection(collectionResponse);
 
       // Fetch beats in the collection
-      const beatsResponse = await invoke<Beat[]>('get_beats_in_collection', { id: setId });
+      const beatsResponse = await invoke<SetEntry[]>('get_beats_in_collection', { id: setId });
 
       if (Array.isArray(beatsResponse)) {
         setBeats(beatsResponse);
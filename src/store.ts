@@ -13,9 +13,33 @@
 
 
 import { invoke } from '@tauri-apps/api/tauri';
+import { KeyNotation } from './bindings';
+
+export type AnalyzerBackend = 'native' | 'python' | 'null';
+
+export type BpmRange = {
+  min: number;
+  max: number;
+};
 
 interface Settings {
   theme: string;
+  analyzer: AnalyzerBackend;
+  python_executable: string;
+  python_script_path?: string;
+  bpm_range: BpmRange;
+  genre_bpm_ranges: Record<string, BpmRange>;
+  bpm_tolerance_percent: number;
+  key_notation: KeyNotation;
+  loudness_target: number;
+  // Write edits back into the tags of the file
+  write_tags_on_edit: boolean;
+  // Tried in order on imported file names, e.g. "{title} - {bpm}bpm - {key}"
+  filename_templates: string[];
+  // Changed through set_watch_folders, which also restarts the watcher
+  watch_folders: string[];
+  // 0.0 to 1.0, how alike beats must sound for find_duplicates
+  duplicate_similarity: number;
 }
 
 export async function loadSettings(): Promise<Settings> {